@@ -0,0 +1,535 @@
+use std::{collections::BTreeMap, fmt, time::Duration};
+
+use async_trait::async_trait;
+use chrono::Utc;
+use futures_util::{future::BoxFuture, stream, Stream};
+use serde::Serialize;
+
+use crate::{Annotation, BandPoint, DataPoint, Field, MetricPoint, RangeOptions, Records};
+
+/// The time range of a query.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TimeRange {
+    /// The last `Duration`, up until now.
+    Span(Duration),
+    /// Between two timestamps, in milliseconds.
+    Between { start_ms: u64, stop_ms: u64 },
+}
+
+impl TimeRange {
+    /// The start and end of the range, in milliseconds.
+    pub fn bounds(&self) -> (i64, i64) {
+        match *self {
+            TimeRange::Span(duration) => {
+                let now = Utc::now().timestamp_millis();
+                (now - duration.as_millis() as i64, now)
+            }
+            TimeRange::Between { start_ms, stop_ms } => (start_ms as i64, stop_ms as i64),
+        }
+    }
+
+    /// The aggregation window that splits this range into about `points`
+    /// windows, in milliseconds.
+    pub fn window_for(&self, points: u64) -> u64 {
+        let (start, stop) = self.bounds();
+        MIN_WINDOW_MS.max((stop - start).max(0) as u64 / points.max(1))
+    }
+}
+
+/// Why a call to a backend failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BackendError {
+    /// The backend didn't respond in time.
+    Timeout(String),
+    /// The backend can't do this at all.
+    Unsupported(String),
+    /// Any other failure, e.g. an unreachable backend or a bad response.
+    Failed(String),
+}
+
+impl BackendError {
+    /// `what` are not supported by this backend.
+    pub fn unsupported(what: &str) -> Self {
+        BackendError::Unsupported(format!("{what} are not supported by this backend."))
+    }
+
+    /// What went wrong, for humans.
+    pub fn message(&self) -> &str {
+        match self {
+            BackendError::Timeout(message)
+            | BackendError::Unsupported(message)
+            | BackendError::Failed(message) => message,
+        }
+    }
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl std::error::Error for BackendError {}
+
+impl From<String> for BackendError {
+    fn from(message: String) -> Self {
+        BackendError::Failed(message)
+    }
+}
+
+impl From<BackendError> for String {
+    fn from(error: BackendError) -> Self {
+        error.to_string()
+    }
+}
+
+#[cfg(any(feature = "prometheus", feature = "influxql"))]
+impl From<reqwest::Error> for BackendError {
+    fn from(error: reqwest::Error) -> Self {
+        if error.is_timeout() {
+            BackendError::Timeout(error.to_string())
+        } else {
+            BackendError::Failed(error.to_string())
+        }
+    }
+}
+
+/// The amount of points range queries aim for by default.
+pub const DEFAULT_POINTS: u64 = 1000;
+
+/// The smallest automatically chosen window. Smaller windows would mostly be
+/// empty at the sensor's sample rate.
+const MIN_WINDOW_MS: u64 = 30000;
+
+/// How far back data is available.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Retention {
+    /// How long data is kept for, in milliseconds. `None` if it is kept
+    /// forever or unknown.
+    pub retention_ms: Option<u64>,
+    /// Timestamp of the oldest stored point, in milliseconds.
+    pub oldest: Option<i64>,
+}
+
+/// Statistics of a local cache of aggregated points.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct CacheStats {
+    /// Amount of cached points.
+    pub entries: u64,
+    /// Range queries that were (partially) served from the cache.
+    pub hits: u64,
+    /// Range queries that had to be fetched completely.
+    pub misses: u64,
+    /// Range queries that were served expired points while they were
+    /// refreshed.
+    pub stale_serves: u64,
+    /// The maximum amount of cached points, if bounded.
+    pub max_entries: Option<u64>,
+    /// Points that were dropped to stay below `max_entries`.
+    pub evictions: u64,
+    /// Amount of points held in memory.
+    pub memory_entries: u64,
+}
+
+/// How many finer windows the default band aggregation splits each window
+/// into.
+const BAND_SUBWINDOWS: u64 = 10;
+
+/// Combine `points`, sorted by time and either raw or aggregated with windows
+/// that evenly divide `window`, into one band per `window`. Like
+/// `aggregateWindow()`, windows are aligned to the epoch and timestamped with
+/// their end.
+pub fn band_points(points: &[MetricPoint], window: i64) -> Vec<BandPoint> {
+    let mut bands: Vec<(BandPoint, usize)> = Vec::new();
+
+    for &MetricPoint(time, value) in points {
+        let end = (time + window - 1).div_euclid(window) * window;
+
+        match bands.last_mut() {
+            Some((band, count)) if band.time == end => {
+                band.min = band.min.min(value);
+                band.max = band.max.max(value);
+                band.mean += value;
+                *count += 1;
+            }
+            _ => bands.push((
+                BandPoint {
+                    time: end,
+                    min: value,
+                    mean: value,
+                    max: value,
+                },
+                1,
+            )),
+        }
+    }
+
+    bands
+        .into_iter()
+        .map(|(band, count)| BandPoint {
+            mean: (band.mean / count as f64 * 100.).round() / 100.,
+            ..band
+        })
+        .collect()
+}
+
+/// The average of `points`, weighted by the time between them with linear
+/// interpolation, like Flux's `timeWeightedAvg()`.
+pub fn time_weighted_avg(points: &[MetricPoint]) -> Option<f64> {
+    match points {
+        [] => None,
+        [point] => Some(point.1),
+        [first, .., last] => {
+            let area: f64 = points
+                .windows(2)
+                .map(|w| (w[0].1 + w[1].1) / 2. * (w[1].0 - w[0].0) as f64)
+                .sum();
+
+            Some(area / (last.0 - first.0).max(1) as f64)
+        }
+    }
+}
+
+/// Fetch `range` in chunks of at most `chunk_windows` aggregation windows, with
+/// a query per chunk. Only one chunk is held in memory at a time, as backends
+/// buffer the response of a query as a whole. The stream ends after the first
+/// error.
+pub fn stream_range<'a, B: TimeSeriesBackend + ?Sized>(
+    backend: &'a mut B,
+    range: TimeRange,
+    options: &RangeOptions,
+    chunk_windows: u64,
+) -> impl Stream<Item = Result<Vec<DataPoint>, BackendError>> + 'a {
+    let (start, stop) = range.bounds();
+    let window = options.window(&range).max(1) as i64;
+    let chunk = window * chunk_windows.max(1) as i64;
+
+    // Every chunk has to use the window of the full range.
+    let options = RangeOptions {
+        window_ms: Some(window as u64),
+        ..options.clone()
+    };
+
+    stream::unfold(Some((backend, start)), move |state| {
+        let options = options.clone();
+
+        async move {
+            let (backend, chunk_start) = state?;
+            if chunk_start >= stop {
+                return None;
+            }
+
+            // Chunks end on window boundaries, so that no window is split.
+            let chunk_stop = (chunk_start - chunk_start.rem_euclid(window) + chunk).min(stop);
+            let range = TimeRange::Between {
+                start_ms: chunk_start as u64,
+                stop_ms: chunk_stop as u64,
+            };
+
+            match backend.get_range(range, &options).await {
+                Ok(mut points) => {
+                    // The partial window after a chunk is part of the next one.
+                    if chunk_stop < stop {
+                        points.retain(|p| p.time <= chunk_stop);
+                    }
+                    Some((Ok(points), Some((backend, chunk_stop))))
+                }
+                Err(e) => Some((Err(e), None)),
+            }
+        }
+    })
+}
+
+/// The stored values of `fields` between `start_ms` and `stop_ms`, merged
+/// into points by time.
+pub async fn raw_points<B: TimeSeriesBackend + ?Sized>(
+    backend: &mut B,
+    fields: &[Field],
+    start_ms: i64,
+    stop_ms: i64,
+) -> Result<Vec<DataPoint>, BackendError> {
+    let mut points: BTreeMap<i64, DataPoint> = BTreeMap::new();
+
+    for &field in fields {
+        let values = backend
+            .get_raw_metric(field.name(), start_ms, stop_ms)
+            .await?;
+
+        for MetricPoint(time, value) in values {
+            let point = points.entry(time).or_insert(DataPoint {
+                time,
+                temperature: None,
+                humidity: None,
+                co2: None,
+            });
+            *field.value_mut(point) = Some(value);
+        }
+    }
+
+    Ok(points.into_values().collect())
+}
+
+/// Fetch the stored points of `fields` in `range` in chunks of `chunk_ms`,
+/// like [`stream_range`] but without aggregating them.
+pub fn stream_raw_range<'a, B: TimeSeriesBackend + ?Sized>(
+    backend: &'a mut B,
+    range: TimeRange,
+    fields: Vec<Field>,
+    chunk_ms: i64,
+) -> impl Stream<Item = Result<Vec<DataPoint>, BackendError>> + 'a {
+    let (start, stop) = range.bounds();
+    let chunk_ms = chunk_ms.max(1);
+
+    stream::unfold(Some((backend, start)), move |state| {
+        let fields = fields.clone();
+
+        async move {
+            let (backend, chunk_start) = state?;
+            if chunk_start >= stop {
+                return None;
+            }
+
+            let chunk_stop = chunk_start.saturating_add(chunk_ms).min(stop);
+            match raw_points(backend, &fields, chunk_start, chunk_stop).await {
+                Ok(points) => Some((Ok(points), Some((backend, chunk_stop)))),
+                Err(e) => Some((Err(e), None)),
+            }
+        }
+    })
+}
+
+/// A store of time series data that the server can read from and write to.
+#[async_trait]
+pub trait TimeSeriesBackend: Send + Sync {
+    /// The most recent point, if any.
+    async fn get_current(&mut self) -> Result<Option<DataPoint>, BackendError>;
+
+    /// Aggregated points in `range`, sorted by time.
+    async fn get_range(
+        &mut self,
+        range: TimeRange,
+        options: &RangeOptions,
+    ) -> Result<Vec<DataPoint>, BackendError>;
+
+    /// Store `points`.
+    async fn write(&mut self, points: &[DataPoint]) -> Result<(), BackendError>;
+
+    /// Aggregated values of the field `name` in `range`, sorted by time.
+    /// Backends that only know the fields in [`Field`] can rely on the
+    /// default, which projects [`TimeSeriesBackend::get_range`].
+    async fn get_metric_range(
+        &mut self,
+        name: &str,
+        range: TimeRange,
+        options: &RangeOptions,
+    ) -> Result<Vec<MetricPoint>, BackendError> {
+        let field: Field = name.parse().map_err(|_| {
+            BackendError::Unsupported(format!("Metric {name} is not supported by this backend."))
+        })?;
+
+        let options = RangeOptions {
+            fields: vec![field],
+            ..options.clone()
+        };
+
+        Ok(self
+            .get_range(range, &options)
+            .await?
+            .iter()
+            .filter_map(|p| MetricPoint::project(field, p))
+            .collect())
+    }
+
+    /// The minimum, mean and maximum of the field `name` per aggregation
+    /// window, sorted by time. The default derives them from
+    /// [`TimeSeriesBackend::get_metric_range`] with finer windows, so the
+    /// extremes are those of the finer means.
+    async fn get_metric_band(
+        &mut self,
+        name: &str,
+        range: TimeRange,
+        options: &RangeOptions,
+    ) -> Result<Vec<BandPoint>, BackendError> {
+        let window = options.window(&range).max(1);
+        let finer = RangeOptions {
+            window_ms: Some((window / BAND_SUBWINDOWS).max(1)),
+            limit: None,
+            ..options.clone()
+        };
+
+        let points = self.get_metric_range(name, range, &finer).await?;
+        let mut bands = band_points(&points, window as i64);
+        options.apply_limit(&mut bands);
+
+        Ok(bands)
+    }
+
+    /// The time-weighted average of the field `name` in `range`, or `None` if
+    /// there is no data. The default integrates the aggregated points of
+    /// [`TimeSeriesBackend::get_metric_range`], which is only an
+    /// approximation.
+    async fn get_time_weighted_avg(
+        &mut self,
+        name: &str,
+        range: TimeRange,
+    ) -> Result<Option<f64>, BackendError> {
+        let points = self
+            .get_metric_range(name, range, &RangeOptions::default())
+            .await?;
+
+        Ok(time_weighted_avg(&points))
+    }
+
+    /// Points in `range` from local storage only, without contacting the
+    /// backend. Returns `None` if nothing is stored locally.
+    async fn get_cached_range(
+        &mut self,
+        _range: TimeRange,
+        _options: &RangeOptions,
+    ) -> Result<Option<Vec<DataPoint>>, BackendError> {
+        Ok(None)
+    }
+
+    /// The retention of stored data, if the backend knows about it.
+    async fn get_retention(&mut self) -> Result<Retention, BackendError> {
+        Ok(Retention::default())
+    }
+
+    /// Statistics of the local cache, if there is one.
+    fn cache_stats(&self) -> Option<CacheStats> {
+        None
+    }
+
+    /// Drop locally cached points that include data between `start` and
+    /// `stop` (in milliseconds), or all of them if no range is given. Returns
+    /// the amount of dropped points.
+    async fn clear_cache(&mut self, _range: Option<(i64, i64)>) -> Result<u64, BackendError> {
+        Ok(0)
+    }
+
+    /// Annotations in `range`, sorted by time.
+    async fn get_annotations(
+        &mut self,
+        _range: TimeRange,
+    ) -> Result<Vec<Annotation>, BackendError> {
+        Err(BackendError::unsupported("Annotations"))
+    }
+
+    /// Store `annotation`.
+    async fn write_annotation(&mut self, _annotation: &Annotation) -> Result<(), BackendError> {
+        Err(BackendError::unsupported("Annotations"))
+    }
+
+    /// The stored values of the field `name` from `start_ms` up to, but not
+    /// including, `stop_ms`, sorted by time, e.g. to find points to correct.
+    async fn get_raw_metric(
+        &mut self,
+        _name: &str,
+        _start_ms: i64,
+        _stop_ms: i64,
+    ) -> Result<Vec<MetricPoint>, BackendError> {
+        Err(BackendError::unsupported("Raw points"))
+    }
+
+    /// Delete all fields of the points from `start_ms` up to, but not
+    /// including, `stop_ms`.
+    async fn delete_range(&mut self, _start_ms: i64, _stop_ms: i64) -> Result<(), BackendError> {
+        Err(BackendError::unsupported("Corrections"))
+    }
+
+    /// The lowest and highest value of every field ever stored. This can
+    /// take a scan of all points, so the scan doesn't borrow the backend.
+    fn scan_records(&self) -> BoxFuture<'static, Result<Vec<Records>, BackendError>> {
+        Box::pin(async { Err(BackendError::unsupported("Records")) })
+    }
+}
+
+#[async_trait]
+impl<T: TimeSeriesBackend + ?Sized> TimeSeriesBackend for Box<T> {
+    async fn get_current(&mut self) -> Result<Option<DataPoint>, BackendError> {
+        (**self).get_current().await
+    }
+
+    async fn get_range(
+        &mut self,
+        range: TimeRange,
+        options: &RangeOptions,
+    ) -> Result<Vec<DataPoint>, BackendError> {
+        (**self).get_range(range, options).await
+    }
+
+    async fn write(&mut self, points: &[DataPoint]) -> Result<(), BackendError> {
+        (**self).write(points).await
+    }
+
+    async fn get_metric_range(
+        &mut self,
+        name: &str,
+        range: TimeRange,
+        options: &RangeOptions,
+    ) -> Result<Vec<MetricPoint>, BackendError> {
+        (**self).get_metric_range(name, range, options).await
+    }
+
+    async fn get_metric_band(
+        &mut self,
+        name: &str,
+        range: TimeRange,
+        options: &RangeOptions,
+    ) -> Result<Vec<BandPoint>, BackendError> {
+        (**self).get_metric_band(name, range, options).await
+    }
+
+    async fn get_time_weighted_avg(
+        &mut self,
+        name: &str,
+        range: TimeRange,
+    ) -> Result<Option<f64>, BackendError> {
+        (**self).get_time_weighted_avg(name, range).await
+    }
+
+    async fn get_cached_range(
+        &mut self,
+        range: TimeRange,
+        options: &RangeOptions,
+    ) -> Result<Option<Vec<DataPoint>>, BackendError> {
+        (**self).get_cached_range(range, options).await
+    }
+
+    async fn get_retention(&mut self) -> Result<Retention, BackendError> {
+        (**self).get_retention().await
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        (**self).cache_stats()
+    }
+
+    async fn clear_cache(&mut self, range: Option<(i64, i64)>) -> Result<u64, BackendError> {
+        (**self).clear_cache(range).await
+    }
+
+    async fn get_annotations(&mut self, range: TimeRange) -> Result<Vec<Annotation>, BackendError> {
+        (**self).get_annotations(range).await
+    }
+
+    async fn write_annotation(&mut self, annotation: &Annotation) -> Result<(), BackendError> {
+        (**self).write_annotation(annotation).await
+    }
+
+    async fn get_raw_metric(
+        &mut self,
+        name: &str,
+        start_ms: i64,
+        stop_ms: i64,
+    ) -> Result<Vec<MetricPoint>, BackendError> {
+        (**self).get_raw_metric(name, start_ms, stop_ms).await
+    }
+
+    async fn delete_range(&mut self, start_ms: i64, stop_ms: i64) -> Result<(), BackendError> {
+        (**self).delete_range(start_ms, stop_ms).await
+    }
+
+    fn scan_records(&self) -> BoxFuture<'static, Result<Vec<Records>, BackendError>> {
+        (**self).scan_records()
+    }
+}
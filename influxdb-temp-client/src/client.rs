@@ -0,0 +1,1167 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset, Utc};
+use futures_util::{future::BoxFuture, stream};
+use influxdb2::{
+    api::{buckets::ListBucketsRequest, organization::ListOrganizationRequest},
+    models::{retention_rule, PostBucketRequest, Query, RetentionRule},
+    FromMap,
+};
+use influxdb2_structmap::{value::Value, GenericMap};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    backend::{BackendError, Retention, TimeRange, TimeSeriesBackend, DEFAULT_POINTS},
+    flux::{self, Aggregate, FluxTime},
+    queries::{self, Source},
+    rollup::{Resolution, Rollups},
+};
+
+/// The bucket that measurements are read from.
+pub const BUCKET: &str = "Temperature";
+/// The measurement that holds the sensor fields.
+pub const MEASUREMENT: &str = "aht10";
+/// The measurement that holds annotations, in the same bucket.
+pub const ANNOTATIONS_MEASUREMENT: &str = "annotations";
+
+/// A point as returned by InfluxDB, with its original timezone offset.
+#[derive(Debug, Clone, Default)]
+pub struct DataPointWithOffset {
+    pub time: DateTime<FixedOffset>,
+    pub temperature: Option<f64>,
+    pub humidity: Option<f64>,
+    pub co2: Option<f64>,
+}
+
+impl DataPointWithOffset {
+    /// Decode a point from a (pivoted) Flux record.
+    pub fn try_from_map(map: &GenericMap) -> Result<Self, String> {
+        let time = match map.get("_time") {
+            Some(Value::TimeRFC(v)) => *v,
+            Some(v) => return Err(format!("Invalid type for _time {v:?}.")),
+            None => return Err("Missing value for _time.".to_string()),
+        };
+
+        // Fields are optional, as queries may be restricted to a subset of them.
+        macro_rules! get {
+            ($name:literal) => {
+                match map.get($name) {
+                    Some(Value::Double(v)) => Some(f64::from(*v)),
+                    Some(v) => return Err(format!("Invalid type for {} {:?}.", $name, v)),
+                    None => None,
+                }
+            };
+        }
+
+        Ok(Self {
+            time,
+            temperature: get!("temperature"),
+            humidity: get!("humidity"),
+            co2: get!("co2"),
+        })
+    }
+}
+
+impl FromMap for DataPointWithOffset {
+    fn from_genericmap(map: GenericMap) -> Self {
+        match Self::try_from_map(&map) {
+            Ok(v) => v,
+            Err(e) => panic!("{e}"),
+        }
+    }
+}
+
+/// A point with a millisecond timestamp and values rounded to two decimals,
+/// as served to clients.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct DataPoint {
+    pub time: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub humidity: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub co2: Option<f64>,
+}
+
+impl From<DataPointWithOffset> for DataPoint {
+    fn from(value: DataPointWithOffset) -> Self {
+        Self {
+            humidity: value.humidity.map(|v| (v * 100.).round() / 100.),
+            temperature: value.temperature.map(|v| (v * 100.).round() / 100.),
+            time: value.time.timestamp_millis(),
+            co2: value.co2,
+        }
+    }
+}
+
+/// The lowest and highest value of a field ever stored.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Records {
+    pub field: Field,
+    pub min: MetricPoint,
+    pub max: MetricPoint,
+}
+
+/// A single value of one field, serialized as a `[time, value]` pair.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct MetricPoint(pub i64, pub f64);
+
+impl MetricPoint {
+    /// Extract `field` from `point`, if it is present.
+    pub fn project(field: Field, point: &DataPoint) -> Option<Self> {
+        field.value(point).map(|v| Self(point.time, v))
+    }
+}
+
+/// The minimum, mean and maximum of a field in one aggregation window.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct BandPoint {
+    pub time: i64,
+    pub min: f64,
+    pub mean: f64,
+    pub max: f64,
+}
+
+/// A field of the measurement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Field {
+    Temperature,
+    Humidity,
+    Co2,
+}
+
+impl Field {
+    pub const ALL: [Field; 3] = [Field::Temperature, Field::Humidity, Field::Co2];
+
+    /// The name of the field in InfluxDB and in serialized points.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Field::Temperature => "temperature",
+            Field::Humidity => "humidity",
+            Field::Co2 => "co2",
+        }
+    }
+
+    /// The unit values of this field are stored in.
+    pub fn unit(&self) -> &'static str {
+        match self {
+            Field::Temperature => "°C",
+            Field::Humidity => "%",
+            Field::Co2 => "ppm",
+        }
+    }
+
+    /// The value of this field in `point`, if it is present.
+    pub fn value(&self, point: &DataPoint) -> Option<f64> {
+        match self {
+            Field::Temperature => point.temperature,
+            Field::Humidity => point.humidity,
+            Field::Co2 => point.co2,
+        }
+    }
+
+    pub fn value_mut<'a>(&self, point: &'a mut DataPoint) -> &'a mut Option<f64> {
+        match self {
+            Field::Temperature => &mut point.temperature,
+            Field::Humidity => &mut point.humidity,
+            Field::Co2 => &mut point.co2,
+        }
+    }
+}
+
+impl std::str::FromStr for Field {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Field::ALL
+            .into_iter()
+            .find(|f| f.name() == s)
+            .ok_or_else(|| format!("Unknown field {s}."))
+    }
+}
+
+/// A note about a moment, e.g. "window opened", for charts to mark.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Annotation {
+    /// In milliseconds.
+    pub time: i64,
+    pub text: String,
+}
+
+/// A mismatch between the data in the bucket and what the server expects.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SchemaProblem {
+    MissingMeasurement,
+    MissingField(Field),
+    /// The field is stored with another type than double, which would fail
+    /// every query that includes it.
+    WrongType {
+        field: Field,
+        found: String,
+    },
+}
+
+impl SchemaProblem {
+    /// Whether queries would fail, rather than just return no data.
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, SchemaProblem::WrongType { .. })
+    }
+}
+
+impl std::fmt::Display for SchemaProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SchemaProblem::MissingMeasurement => write!(
+                f,
+                "measurement {MEASUREMENT:?} does not exist in the bucket"
+            ),
+            SchemaProblem::MissingField(field) => write!(
+                f,
+                "field {:?} of measurement {MEASUREMENT:?} has no data",
+                field.name()
+            ),
+            SchemaProblem::WrongType { field, found } => write!(
+                f,
+                "field {:?} of measurement {MEASUREMENT:?} is a {found}, expected a double (float)",
+                field.name()
+            ),
+        }
+    }
+}
+
+/// Options that apply to range queries.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct RangeOptions {
+    /// Only return points newer than this timestamp (in milliseconds).
+    pub since: Option<i64>,
+    /// Only query these fields. Queries all fields if empty.
+    pub fields: Vec<Field>,
+    /// Aggregation window in milliseconds. Derived from the length of the
+    /// range if not set.
+    pub window_ms: Option<u64>,
+    /// Only return the first or last points.
+    pub limit: Option<Limit>,
+    /// The amount of points to aim for when deriving the window. Defaults to
+    /// [`DEFAULT_POINTS`].
+    pub points: Option<u64>,
+}
+
+/// Limits the amount of points a range query returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Limit {
+    /// The oldest `n` points.
+    First(usize),
+    /// The newest `n` points.
+    Last(usize),
+}
+
+impl RangeOptions {
+    /// The aggregation window to use for `range`, in milliseconds.
+    pub fn window(&self, range: &TimeRange) -> u64 {
+        self.window_ms
+            .unwrap_or_else(|| range.window_for(self.points.unwrap_or(DEFAULT_POINTS)))
+    }
+
+    /// Apply [`RangeOptions::limit`] to sorted `points`, for backends that
+    /// can't do so themselves.
+    pub fn apply_limit<T>(&self, points: &mut Vec<T>) {
+        match self.limit {
+            Some(Limit::First(n)) => points.truncate(n),
+            Some(Limit::Last(n)) => {
+                let skip = points.len().saturating_sub(n);
+                points.drain(..skip);
+            }
+            None => {}
+        }
+    }
+}
+
+const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+
+/// How far back [`Client::check_schema`] looks for points, so that it doesn't
+/// scan all of history on startup.
+const SCHEMA_LOOKBACK_MS: u64 = 30 * DAY_MS;
+
+macro_rules! log_err {
+    ($thing:expr) => {
+        match $thing {
+            Ok(v) => Some(v),
+            Err(e) => {
+                eprintln!("{e:?}");
+                None
+            }
+        }
+    };
+}
+
+impl From<influxdb2::RequestError> for BackendError {
+    fn from(error: influxdb2::RequestError) -> Self {
+        match &error {
+            influxdb2::RequestError::ReqwestProcessing { source } if source.is_timeout() => {
+                BackendError::Timeout(error.to_string())
+            }
+            _ => BackendError::Failed(error.to_string()),
+        }
+    }
+}
+
+/// Replaces the [`influxdb2::Client`] of a [`Client`], e.g. after its token
+/// was rotated. Queries that are running keep using the previous one.
+#[derive(Debug, Clone)]
+pub struct ClientHandle(Arc<RwLock<influxdb2::Client>>);
+
+impl ClientHandle {
+    pub fn replace(&self, inner: influxdb2::Client) {
+        *self.0.write().unwrap() = inner;
+    }
+}
+
+/// Queries measurements from InfluxDB.
+#[derive(Clone)]
+pub struct Client {
+    inner: ClientHandle,
+    bucket: String,
+    rollups: Option<Rollups>,
+    timezone: Option<String>,
+}
+
+impl Client {
+    /// Wrap a configured [`influxdb2::Client`].
+    pub fn new(inner: influxdb2::Client) -> Self {
+        Self {
+            inner: ClientHandle(Arc::new(RwLock::new(inner))),
+            bucket: BUCKET.to_string(),
+            rollups: None,
+            timezone: None,
+        }
+    }
+
+    /// Read and write `bucket` instead of [`BUCKET`].
+    pub fn with_bucket(mut self, bucket: impl Into<String>) -> Self {
+        self.bucket = bucket.into();
+        self
+    }
+
+    pub fn bucket(&self) -> &str {
+        &self.bucket
+    }
+
+    /// Start calendar windows, e.g. days, in the IANA time zone `timezone`
+    /// instead of UTC.
+    pub fn with_timezone(mut self, timezone: impl Into<String>) -> Self {
+        self.timezone = Some(timezone.into());
+        self
+    }
+
+    fn script(&self, query: String) -> Query {
+        Query::new(flux::with_location(&query, self.timezone.as_deref()))
+    }
+
+    /// The raw points.
+    fn raw(&self) -> Source {
+        Source {
+            bucket: &self.bucket,
+            measurement: MEASUREMENT,
+        }
+    }
+
+    /// A handle to replace the underlying client with.
+    pub fn handle(&self) -> ClientHandle {
+        self.inner.clone()
+    }
+
+    fn inner(&self) -> influxdb2::Client {
+        self.inner.0.read().unwrap().clone()
+    }
+
+    /// Serve queries with large windows from `rollups` once they are ready.
+    pub fn with_rollups(mut self, rollups: Rollups) -> Self {
+        self.rollups = Some(rollups);
+        self
+    }
+
+    /// The bucket and measurement to aggregate windows of `window` from.
+    fn source(&self, window: u64) -> (&str, String) {
+        let resolution = self.rollups.as_ref().and_then(|r| r.for_window(window));
+        self.rollup_source(resolution)
+    }
+
+    /// The bucket and the measurements of the minimums, means and maximums
+    /// to compute bands of `window` from.
+    fn band_source(&self, window: u64) -> (&str, [String; 3]) {
+        let resolution = self.rollups.as_ref().and_then(|r| r.for_window(window));
+        let (bucket, _) = self.rollup_source(resolution);
+
+        let measurements = Aggregate::BAND.map(|aggregate| match resolution {
+            Some(resolution) => resolution.measurement_of(aggregate),
+            None => MEASUREMENT.to_string(),
+        });
+
+        (bucket, measurements)
+    }
+
+    /// The bucket and measurement of the `resolution` rollup, or of the raw
+    /// points.
+    fn rollup_source(&self, resolution: Option<Resolution>) -> (&str, String) {
+        match (&self.rollups, resolution) {
+            (Some(rollups), Some(resolution)) => (rollups.bucket(), resolution.measurement()),
+            _ => (self.bucket.as_str(), MEASUREMENT.to_string()),
+        }
+    }
+
+    /// Points between `start` and `stop`, aggregated into windows of
+    /// `window`. The response of a query is buffered as a whole, so long
+    /// ranges should be fetched in chunks with [`crate::stream_range`].
+    #[tracing::instrument(skip(self))]
+    async fn in_range<O: From<DataPointWithOffset>>(
+        &mut self,
+        start: FluxTime,
+        stop: Option<FluxTime>,
+        window: u64,
+        options: &RangeOptions,
+    ) -> Result<impl Iterator<Item = O>, BackendError> {
+        let (bucket, measurement) = self.source(window);
+        let source = Source {
+            bucket,
+            measurement: &measurement,
+        };
+
+        let query = queries::range(source, start, stop, window, &options.fields, options.limit)?;
+
+        let mut res: Vec<DataPointWithOffset> = self
+            .inner()
+            .query(Some(self.script(query)))
+            .await
+            .map_err(BackendError::from)?;
+
+        if let Some(since) = options.since {
+            res.retain(|r| r.time.timestamp_millis() > since);
+        }
+
+        Ok(res.into_iter().map(O::from))
+    }
+
+    /// Fetch aggregated points between two timestamps (in milliseconds).
+    pub async fn get_data_from_to(
+        &mut self,
+        start_ms: u64,
+        stop_ms: u64,
+        options: &RangeOptions,
+    ) -> Result<impl Iterator<Item = DataPoint>, BackendError> {
+        let window = options.window(&TimeRange::Between { start_ms, stop_ms });
+
+        // The window is based on the full range so that incremental fetches
+        // line up with the points the client already has.
+        let start_ms = match options.since {
+            Some(since) => start_ms.max(since.max(0) as u64 + 1),
+            None => start_ms,
+        };
+
+        // Round the end up to the next full second, like before.
+        let start = FluxTime::At(start_ms as i64);
+        let stop = FluxTime::At((stop_ms.saturating_add(1000 + 1) / 1000 * 1000) as i64);
+
+        self.in_range(start, Some(stop), window, options).await
+    }
+
+    /// Fetch aggregated points in the last `duration`.
+    pub async fn get_data_in_span(
+        &mut self,
+        duration: Duration,
+        options: &RangeOptions,
+    ) -> Result<impl Iterator<Item = DataPoint>, BackendError> {
+        let duration_ms = duration.as_millis();
+        let window = options.window(&TimeRange::Span(duration));
+
+        let start = match options.since {
+            Some(since) => {
+                let start = Utc::now().timestamp_millis() - duration_ms as i64;
+                FluxTime::At(start.max(since + 1))
+            }
+            None => FluxTime::Ago(duration_ms as u64),
+        };
+
+        self.in_range(start, None, window, options).await
+    }
+
+    /// Fetch the most recent temperature, logging any errors.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_current_temp(&mut self) -> Option<f64> {
+        let query = log_err!(queries::latest(self.raw(), FluxTime::Ago(DAY_MS)))?;
+
+        let res: Vec<DataPointWithOffset> =
+            log_err!(self.inner().query(Some(self.script(query))).await)?;
+
+        res.into_iter().find_map(|v| v.temperature)
+    }
+
+    /// Check that InfluxDB is reachable and healthy.
+    pub async fn check_health(&self) -> Result<(), BackendError> {
+        self.inner()
+            .health()
+            .await
+            .map(|_| ())
+            .map_err(BackendError::from)
+    }
+
+    /// Check whether the bucket exists.
+    pub async fn bucket_exists(&self) -> Result<bool, BackendError> {
+        self.has_bucket(&self.bucket).await
+    }
+
+    async fn has_bucket(&self, name: &str) -> Result<bool, BackendError> {
+        let request = ListBucketsRequest {
+            name: Some(name.to_string()),
+            ..Default::default()
+        };
+
+        let buckets = self
+            .inner()
+            .list_buckets(Some(request))
+            .await
+            .map_err(BackendError::from)?;
+
+        Ok(buckets.buckets.iter().any(|b| b.name == name))
+    }
+
+    /// Create the bucket `name` in `org` if it doesn't exist, keeping data
+    /// for `retention` or forever. Returns whether it was created. Requires
+    /// a token that may read organizations and write buckets.
+    pub async fn create_bucket_if_missing(
+        &self,
+        name: &str,
+        org: &str,
+        retention: Option<Duration>,
+    ) -> Result<bool, BackendError> {
+        if self.has_bucket(name).await? {
+            return Ok(false);
+        }
+
+        let request = ListOrganizationRequest {
+            org: Some(org.to_string()),
+            ..Default::default()
+        };
+
+        let org_id = self
+            .inner()
+            .list_organizations(request)
+            .await
+            .map_err(BackendError::from)?
+            .orgs
+            .into_iter()
+            .find(|o| o.name == org)
+            .and_then(|o| o.id)
+            .ok_or_else(|| format!("Organization {org} does not exist."))?;
+
+        let retention_rules = match retention {
+            Some(retention) => vec![RetentionRule::new(
+                retention_rule::Type::Expire,
+                retention.as_secs() as i32,
+            )],
+            None => Vec::new(),
+        };
+
+        let request = PostBucketRequest {
+            retention_rules,
+            ..PostBucketRequest::new(org_id, name.to_string())
+        };
+
+        self.inner()
+            .create_bucket(Some(request))
+            .await
+            .map_err(BackendError::from)?;
+
+        Ok(true)
+    }
+
+    /// Check whether [`MEASUREMENT`] exists in the bucket.
+    pub async fn measurement_exists(&self) -> Result<bool, BackendError> {
+        let query = format!(
+            r#"
+        import "influxdata/influxdb/schema"
+
+        schema.measurements(bucket: {})"#,
+            flux::string(&self.bucket),
+        );
+
+        let res = self
+            .inner()
+            .query_raw(Some(self.script(query)))
+            .await
+            .map_err(BackendError::from)?;
+
+        Ok(res.iter().any(|r| match r.values.get("_value") {
+            Some(Value::String(v)) => v == MEASUREMENT,
+            _ => false,
+        }))
+    }
+
+    /// Check that [`MEASUREMENT`] exists and that its fields have the
+    /// expected types. Temperature and humidity are required, CO2 is
+    /// optional. Only the points of the last 30 days are checked, and
+    /// nothing is reported if there are none.
+    pub async fn check_schema(&self) -> Result<Vec<SchemaProblem>, BackendError> {
+        if !self.measurement_exists().await? {
+            return Ok(vec![SchemaProblem::MissingMeasurement]);
+        }
+
+        let query = queries::field_types(self.raw(), FluxTime::Ago(SCHEMA_LOOKBACK_MS))?;
+
+        let res = self
+            .inner()
+            .query_raw(Some(self.script(query)))
+            .await
+            .map_err(BackendError::from)?;
+
+        if res.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut problems = Vec::new();
+        for field in Field::ALL {
+            let value = res.iter().find_map(|r| match r.values.get("_field") {
+                Some(Value::String(name)) if name == field.name() => r.values.get("_value"),
+                _ => None,
+            });
+
+            match value {
+                Some(Value::Double(_)) => {}
+                Some(other) => {
+                    let found = match other {
+                        Value::Long(_) => "integer",
+                        Value::UnsignedLong(_) => "unsigned integer",
+                        Value::Bool(_) => "boolean",
+                        Value::String(_) => "string",
+                        _ => "non-numeric value",
+                    };
+
+                    problems.push(SchemaProblem::WrongType {
+                        field,
+                        found: found.to_string(),
+                    });
+                }
+                None if field == Field::Co2 => {}
+                None => problems.push(SchemaProblem::MissingField(field)),
+            }
+        }
+
+        Ok(problems)
+    }
+
+    /// Fetch the most recent point without panicking if it fails to decode.
+    pub async fn get_latest_point(&self) -> Result<Option<DataPointWithOffset>, BackendError> {
+        let query = queries::latest(self.raw(), FluxTime::Ago(DAY_MS))?;
+
+        let res = self
+            .inner()
+            .query_raw(Some(self.script(query)))
+            .await
+            .map_err(BackendError::from)?;
+
+        res.first()
+            .map(|r| DataPointWithOffset::try_from_map(&r.values))
+            .transpose()
+            .map_err(BackendError::from)
+    }
+
+    /// The retention period of the bucket and the time of the oldest point in
+    /// [`MEASUREMENT`].
+    pub async fn get_retention(&self) -> Result<Retention, BackendError> {
+        let request = ListBucketsRequest {
+            name: Some(self.bucket.clone()),
+            ..Default::default()
+        };
+
+        let buckets = self
+            .inner()
+            .list_buckets(Some(request))
+            .await
+            .map_err(BackendError::from)?;
+
+        // A rule of 0 seconds means that data is kept forever.
+        let retention_ms = buckets
+            .buckets
+            .iter()
+            .find(|b| b.name == self.bucket)
+            .and_then(|b| b.retention_rules.first())
+            .filter(|r| r.every_seconds > 0)
+            .map(|r| r.every_seconds as u64 * 1000);
+
+        let query = queries::oldest(self.raw())?;
+
+        let res = self
+            .inner()
+            .query_raw(Some(self.script(query)))
+            .await
+            .map_err(BackendError::from)?;
+
+        let oldest = res
+            .iter()
+            .filter_map(|r| match r.values.get("_time") {
+                Some(Value::TimeRFC(t)) => Some(t.timestamp_millis()),
+                _ => None,
+            })
+            .min();
+
+        Ok(Retention {
+            retention_ms,
+            oldest,
+        })
+    }
+
+    /// Aggregated values of any field of [`MEASUREMENT`], including ones
+    /// that are not part of [`DataPoint`].
+    pub async fn get_metric(
+        &self,
+        name: &str,
+        range: TimeRange,
+        options: &RangeOptions,
+    ) -> Result<Vec<MetricPoint>, BackendError> {
+        let (start_ms, stop_ms) = range.bounds();
+        let window = options.window(&range);
+
+        let start_ms = match options.since {
+            Some(since) => start_ms.max(since + 1),
+            None => start_ms,
+        };
+
+        let (bucket, measurement) = self.source(window);
+        let source = Source {
+            bucket,
+            measurement: &measurement,
+        };
+
+        let query = queries::metric(source, name, start_ms, stop_ms + 1, window)?;
+
+        let res = self
+            .inner()
+            .query_raw(Some(self.script(query)))
+            .await
+            .map_err(BackendError::from)?;
+
+        Ok(res
+            .iter()
+            .filter_map(|r| match (r.values.get("_time"), r.values.get("_value")) {
+                (Some(Value::TimeRFC(t)), Some(Value::Double(v))) => Some(MetricPoint(
+                    t.timestamp_millis(),
+                    (f64::from(*v) * 100.).round() / 100.,
+                )),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// The stored values of the field `name` between `start_ms` and
+    /// `stop_ms`, without rounding.
+    pub async fn get_raw_metric(
+        &self,
+        name: &str,
+        start_ms: i64,
+        stop_ms: i64,
+    ) -> Result<Vec<MetricPoint>, BackendError> {
+        let query = queries::raw_metric(self.raw(), name, start_ms, stop_ms)?;
+
+        let res = self
+            .inner()
+            .query_raw(Some(self.script(query)))
+            .await
+            .map_err(BackendError::from)?;
+
+        Ok(res
+            .iter()
+            .filter_map(|r| match (r.values.get("_time"), r.values.get("_value")) {
+                (Some(Value::TimeRFC(t)), Some(Value::Double(v))) => {
+                    Some(MetricPoint(t.timestamp_millis(), f64::from(*v)))
+                }
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// The records of every field, from a scan of all points.
+    pub async fn get_records(&self) -> Result<Vec<Records>, BackendError> {
+        let query = queries::records(self.raw())?;
+
+        let res = self
+            .inner()
+            .query_raw(Some(self.script(query)))
+            .await
+            .map_err(BackendError::from)?;
+
+        let mut found: BTreeMap<Field, (Option<MetricPoint>, Option<MetricPoint>)> =
+            BTreeMap::new();
+
+        for r in &res {
+            let (
+                Some(Value::String(field)),
+                Some(Value::String(record)),
+                Some(Value::TimeRFC(t)),
+                Some(Value::Double(v)),
+            ) = (
+                r.values.get("_field"),
+                r.values.get("record"),
+                r.values.get("_time"),
+                r.values.get("_value"),
+            )
+            else {
+                continue;
+            };
+            let Ok(field) = field.parse::<Field>() else {
+                continue;
+            };
+
+            let point = Some(MetricPoint(t.timestamp_millis(), f64::from(*v)));
+            let entry = found.entry(field).or_default();
+            match record.as_str() {
+                "min" => entry.0 = point,
+                "max" => entry.1 = point,
+                _ => {}
+            }
+        }
+
+        Ok(found
+            .into_iter()
+            .filter_map(|(field, (min, max))| {
+                Some(Records {
+                    field,
+                    min: min?,
+                    max: max?,
+                })
+            })
+            .collect())
+    }
+
+    /// Delete all fields of the points in [`MEASUREMENT`] from `start_ms` up
+    /// to, but not including, `stop_ms`.
+    pub async fn delete_range(&self, start_ms: i64, stop_ms: i64) -> Result<(), BackendError> {
+        let time = |ms: i64| {
+            DateTime::<Utc>::from_timestamp_millis(ms)
+                .map(|t| t.naive_utc())
+                .ok_or_else(|| format!("Invalid timestamp {ms}."))
+        };
+
+        // The stop of a delete is inclusive.
+        let stop = time(stop_ms)? - chrono::Duration::nanoseconds(1);
+
+        self.inner()
+            .delete(
+                &self.bucket,
+                time(start_ms)?,
+                stop,
+                Some(format!("_measurement=\"{MEASUREMENT}\"")),
+            )
+            .await
+            .map_err(BackendError::from)
+    }
+
+    /// The minimum, mean and maximum of the field `name` per window, in a
+    /// single query.
+    pub async fn get_metric_band(
+        &self,
+        name: &str,
+        range: TimeRange,
+        options: &RangeOptions,
+    ) -> Result<Vec<BandPoint>, BackendError> {
+        let (start_ms, stop_ms) = range.bounds();
+        let window = options.window(&range);
+
+        let start_ms = match options.since {
+            Some(since) => start_ms.max(since + 1),
+            None => start_ms,
+        };
+
+        let (bucket, measurements) = self.band_source(window);
+        let sources = std::array::from_fn(|i| Source {
+            bucket,
+            measurement: &measurements[i],
+        });
+
+        let query = queries::band(sources, name, start_ms, stop_ms + 1, window, options.limit)?;
+
+        let res = self
+            .inner()
+            .query_raw(Some(self.script(query)))
+            .await
+            .map_err(BackendError::from)?;
+
+        let round = |v: f64| (v * 100.).round() / 100.;
+        let get = |r: &influxdb2::api::query::FluxRecord, column| match r.values.get(column) {
+            Some(Value::Double(v)) => Some(round(f64::from(*v))),
+            _ => None,
+        };
+
+        Ok(res
+            .iter()
+            .filter_map(|r| {
+                let Some(Value::TimeRFC(time)) = r.values.get("_time") else {
+                    return None;
+                };
+
+                Some(BandPoint {
+                    time: time.timestamp_millis(),
+                    min: get(r, "min")?,
+                    mean: get(r, "mean")?,
+                    max: get(r, "max")?,
+                })
+            })
+            .collect())
+    }
+
+    /// The time-weighted average of the field `name` in `range`, computed by
+    /// InfluxDB from the raw points.
+    pub async fn get_time_weighted_avg(
+        &self,
+        name: &str,
+        range: TimeRange,
+    ) -> Result<Option<f64>, BackendError> {
+        let (start_ms, stop_ms) = range.bounds();
+
+        let query = queries::time_weighted_avg(self.raw(), name, start_ms, stop_ms + 1)?;
+
+        let res = self
+            .inner()
+            .query_raw(Some(self.script(query)))
+            .await
+            .map_err(BackendError::from)?;
+
+        Ok(res.iter().find_map(|r| match r.values.get("_value") {
+            Some(Value::Double(v)) => Some(f64::from(*v)),
+            _ => None,
+        }))
+    }
+
+    /// Aggregate the points between `start_ms` and `stop_ms` into the
+    /// `resolution` rollup in `bucket`. InfluxDB does this by itself, so no
+    /// points are transferred. Windows that were rolled up before are
+    /// overwritten.
+    pub async fn rollup(
+        &self,
+        bucket: &str,
+        resolution: Resolution,
+        start_ms: i64,
+        stop_ms: i64,
+    ) -> Result<(), BackendError> {
+        let query = queries::rollup(
+            self.raw(),
+            bucket,
+            resolution,
+            FluxTime::At(start_ms),
+            Some(FluxTime::At(stop_ms)),
+        )?;
+
+        self.inner()
+            .query_raw(Some(self.script(query)))
+            .await
+            .map(|_| ())
+            .map_err(BackendError::from)
+    }
+
+    /// The time of the most recent window of the `resolution` rollup in
+    /// `bucket`, in milliseconds. `None` if any of its aggregates is empty,
+    /// e.g. because the extremes were added later.
+    pub async fn latest_rollup(
+        &self,
+        bucket: &str,
+        resolution: Resolution,
+    ) -> Result<Option<i64>, BackendError> {
+        let mut latest: Option<i64> = None;
+
+        for aggregate in Aggregate::BAND {
+            let source = Source {
+                bucket,
+                measurement: &resolution.measurement_of(aggregate),
+            };
+
+            let res = self
+                .inner()
+                .query_raw(Some(self.script(queries::latest_time(source)?)))
+                .await
+                .map_err(BackendError::from)?;
+
+            let time = res
+                .iter()
+                .filter_map(|r| match r.values.get("_time") {
+                    Some(Value::TimeRFC(t)) => Some(t.timestamp_millis()),
+                    _ => None,
+                })
+                .max();
+
+            match time {
+                Some(time) => latest = Some(latest.map_or(time, |l| l.min(time))),
+                None => return Ok(None),
+            }
+        }
+
+        Ok(latest)
+    }
+
+    /// Write `points` to `measurement` in the bucket.
+    pub async fn write_points(
+        &self,
+        measurement: &str,
+        points: &[DataPoint],
+    ) -> Result<(), BackendError> {
+        let points = points
+            .iter()
+            .map(|point| {
+                let time = point
+                    .time
+                    .checked_mul(1_000_000)
+                    .ok_or_else(|| format!("Point time {} is out of range", point.time))?;
+
+                let mut builder =
+                    influxdb2::models::DataPoint::builder(measurement).timestamp(time);
+                for field in Field::ALL {
+                    if let Some(value) = field.value(point) {
+                        builder = builder.field(field.name(), value);
+                    }
+                }
+                builder.build().map_err(|e| format!("{e}"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.inner()
+            .write(&self.bucket, stream::iter(points))
+            .await
+            .map_err(BackendError::from)
+    }
+
+    /// Annotations between `start_ms` and `stop_ms`, sorted by time.
+    pub async fn get_annotations(
+        &self,
+        start_ms: i64,
+        stop_ms: i64,
+    ) -> Result<Vec<Annotation>, BackendError> {
+        let source = Source {
+            bucket: &self.bucket,
+            measurement: ANNOTATIONS_MEASUREMENT,
+        };
+        let query = queries::annotations(source, start_ms, stop_ms)?;
+
+        let res = self
+            .inner()
+            .query_raw(Some(self.script(query)))
+            .await
+            .map_err(BackendError::from)?;
+
+        Ok(res
+            .iter()
+            .filter_map(|r| match (r.values.get("_time"), r.values.get("_value")) {
+                (Some(Value::TimeRFC(t)), Some(Value::String(text))) => Some(Annotation {
+                    time: t.timestamp_millis(),
+                    text: text.clone(),
+                }),
+                _ => None,
+            })
+            .collect())
+    }
+
+    pub async fn write_annotation(&self, annotation: &Annotation) -> Result<(), BackendError> {
+        let time = annotation
+            .time
+            .checked_mul(1_000_000)
+            .ok_or_else(|| format!("Annotation time {} is out of range", annotation.time))?;
+
+        let point = influxdb2::models::DataPoint::builder(ANNOTATIONS_MEASUREMENT)
+            .timestamp(time)
+            .field("text", annotation.text.clone())
+            .build()
+            .map_err(|e| format!("{e}"))?;
+
+        self.inner()
+            .write(&self.bucket, stream::iter([point]))
+            .await
+            .map_err(BackendError::from)
+    }
+}
+
+#[async_trait]
+impl TimeSeriesBackend for Client {
+    async fn get_current(&mut self) -> Result<Option<DataPoint>, BackendError> {
+        Ok(self.get_latest_point().await?.map(DataPoint::from))
+    }
+
+    async fn get_range(
+        &mut self,
+        range: TimeRange,
+        options: &RangeOptions,
+    ) -> Result<Vec<DataPoint>, BackendError> {
+        let points = match range {
+            TimeRange::Span(duration) => self.get_data_in_span(duration, options).await?.collect(),
+            TimeRange::Between { start_ms, stop_ms } => self
+                .get_data_from_to(start_ms, stop_ms, options)
+                .await?
+                .collect(),
+        };
+
+        Ok(points)
+    }
+
+    async fn write(&mut self, points: &[DataPoint]) -> Result<(), BackendError> {
+        self.write_points(MEASUREMENT, points).await
+    }
+
+    async fn get_metric_range(
+        &mut self,
+        name: &str,
+        range: TimeRange,
+        options: &RangeOptions,
+    ) -> Result<Vec<MetricPoint>, BackendError> {
+        self.get_metric(name, range, options).await
+    }
+
+    async fn get_metric_band(
+        &mut self,
+        name: &str,
+        range: TimeRange,
+        options: &RangeOptions,
+    ) -> Result<Vec<BandPoint>, BackendError> {
+        Client::get_metric_band(self, name, range, options).await
+    }
+
+    async fn get_time_weighted_avg(
+        &mut self,
+        name: &str,
+        range: TimeRange,
+    ) -> Result<Option<f64>, BackendError> {
+        Client::get_time_weighted_avg(self, name, range).await
+    }
+
+    async fn get_retention(&mut self) -> Result<Retention, BackendError> {
+        Client::get_retention(self).await
+    }
+
+    async fn get_annotations(&mut self, range: TimeRange) -> Result<Vec<Annotation>, BackendError> {
+        let (start, stop) = range.bounds();
+        Client::get_annotations(self, start, stop).await
+    }
+
+    async fn write_annotation(&mut self, annotation: &Annotation) -> Result<(), BackendError> {
+        Client::write_annotation(self, annotation).await
+    }
+
+    async fn get_raw_metric(
+        &mut self,
+        name: &str,
+        start_ms: i64,
+        stop_ms: i64,
+    ) -> Result<Vec<MetricPoint>, BackendError> {
+        Client::get_raw_metric(self, name, start_ms, stop_ms).await
+    }
+
+    fn scan_records(&self) -> BoxFuture<'static, Result<Vec<Records>, BackendError>> {
+        let client = self.clone();
+        Box::pin(async move { client.get_records().await })
+    }
+
+    async fn delete_range(&mut self, start_ms: i64, stop_ms: i64) -> Result<(), BackendError> {
+        Client::delete_range(self, start_ms, stop_ms).await
+    }
+}
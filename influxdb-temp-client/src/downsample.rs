@@ -0,0 +1,152 @@
+//! Downsampling that keeps the shape of a series, for charts.
+
+use std::collections::BTreeSet;
+
+use crate::{DataPoint, Field, MetricPoint};
+
+/// The indices of the points that Largest-Triangle-Three-Buckets keeps when
+/// reducing `points` to `threshold` points. Unlike averaging, this keeps
+/// spikes intact.
+fn lttb_indices(points: &[MetricPoint], threshold: usize) -> Vec<usize> {
+    let len = points.len();
+    if threshold >= len {
+        return (0..len).collect();
+    }
+    if threshold < 3 {
+        return [0, len - 1].into_iter().take(threshold).collect();
+    }
+
+    // The first and last points are always kept, the rest is split into
+    // `threshold - 2` buckets that each contribute one point.
+    let every = (len - 2) as f64 / (threshold - 2) as f64;
+    let bucket = |i: usize| {
+        let start = (i as f64 * every) as usize + 1;
+        let end = (((i + 1) as f64 * every) as usize + 1).min(len - 1);
+        start..end
+    };
+
+    let mut kept = Vec::with_capacity(threshold);
+    kept.push(0);
+
+    let mut a = 0;
+    for i in 0..threshold - 2 {
+        // The third corner is the average of the next bucket.
+        let next = if i + 1 < threshold - 2 {
+            bucket(i + 1)
+        } else {
+            len - 1..len
+        };
+        let n = next.len().max(1) as f64;
+        let avg_x = points[next.clone()].iter().map(|p| p.0 as f64).sum::<f64>() / n;
+        let avg_y = points[next].iter().map(|p| p.1).sum::<f64>() / n;
+
+        let (ax, ay) = (points[a].0 as f64, points[a].1);
+        let Some(best) = bucket(i).max_by(|&x, &y| {
+            let area = |j: usize| {
+                let (bx, by) = (points[j].0 as f64, points[j].1);
+                ((ax - avg_x) * (by - ay) - (ax - bx) * (avg_y - ay)).abs()
+            };
+            area(x).total_cmp(&area(y))
+        }) else {
+            continue;
+        };
+
+        kept.push(best);
+        a = best;
+    }
+
+    kept.push(len - 1);
+    kept
+}
+
+/// Reduce `points` to at most `threshold` points with
+/// Largest-Triangle-Three-Buckets. Returns `points` as-is if there are no more
+/// than `threshold` of them.
+pub fn lttb(points: &[MetricPoint], threshold: usize) -> Vec<MetricPoint> {
+    lttb_indices(points, threshold)
+        .into_iter()
+        .map(|i| points[i])
+        .collect()
+}
+
+/// Downsample every field in `fields` of `points` with [`lttb`], keeping the
+/// points that are selected for any of them, but no more than `threshold`.
+/// Points may therefore be missing some of their fields' values in the
+/// result.
+pub fn lttb_points(points: &[DataPoint], fields: &[Field], threshold: usize) -> Vec<DataPoint> {
+    let series: Vec<(Vec<_>, Vec<_>)> = fields
+        .iter()
+        .map(|&field| {
+            points
+                .iter()
+                .enumerate()
+                .filter_map(|(i, p)| Some((i, MetricPoint::project(field, p)?)))
+                .unzip()
+        })
+        .collect();
+
+    let kept = |per_field: usize| {
+        let mut kept = BTreeSet::new();
+        for (indices, series) in &series {
+            kept.extend(
+                lttb_indices(series, per_field)
+                    .into_iter()
+                    .map(|i| indices[i]),
+            );
+        }
+        kept
+    };
+
+    // The fields rarely select the same points, so find the most points
+    // per field that still fit. Splitting `threshold` evenly always does.
+    let (mut fits, mut too_many) = (threshold / fields.len().max(1), threshold + 1);
+    while too_many - fits > 1 {
+        let mid = fits + (too_many - fits) / 2;
+        if kept(mid).len() <= threshold {
+            fits = mid;
+        } else {
+            too_many = mid;
+        }
+    }
+
+    kept(fits).into_iter().map(|i| points[i]).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_short_series() {
+        let points: Vec<_> = (0..5).map(|i| MetricPoint(i, i as f64)).collect();
+        assert_eq!(lttb(&points, 10).len(), 5);
+    }
+
+    #[test]
+    fn keeps_spikes() {
+        let mut points: Vec<_> = (0..1000).map(|i| MetricPoint(i, 20.)).collect();
+        points[537].1 = 35.;
+
+        let sampled = lttb(&points, 50);
+        assert_eq!(sampled.len(), 50);
+        assert_eq!(sampled.first().unwrap().0, 0);
+        assert_eq!(sampled.last().unwrap().0, 999);
+        assert!(sampled.iter().any(|p| p.0 == 537));
+    }
+
+    #[test]
+    fn caps_merged_fields() {
+        let points: Vec<_> = (0..1000)
+            .map(|i| DataPoint {
+                time: i,
+                temperature: Some(((i * 7) % 13) as f64),
+                humidity: Some(((i * 11) % 17) as f64),
+                co2: None,
+            })
+            .collect();
+
+        let sampled = lttb_points(&points, &[Field::Temperature, Field::Humidity], 100);
+        assert!(sampled.len() <= 100);
+        assert!(sampled.len() >= 50);
+    }
+}
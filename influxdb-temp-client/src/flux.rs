@@ -0,0 +1,356 @@
+//! A small builder for the Flux queries used by [`Client`](crate::Client).
+//!
+//! Values are never formatted into the query text directly: strings are
+//! quoted and escaped, and times and durations are typed.
+
+use chrono::{SecondsFormat, TimeZone, Utc};
+
+use crate::Limit;
+
+/// A bound of a `range()` call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FluxTime {
+    /// An absolute timestamp, in milliseconds.
+    At(i64),
+    /// A duration before now, in milliseconds.
+    Ago(u64),
+}
+
+impl FluxTime {
+    /// Fails if the timestamp can't be represented.
+    fn render(&self) -> Result<String, String> {
+        match *self {
+            FluxTime::At(ms) => Utc
+                .timestamp_millis_opt(ms)
+                .single()
+                .map(|t| t.to_rfc3339_opts(SecondsFormat::Millis, true))
+                .ok_or_else(|| format!("Timestamp {ms} is out of range")),
+            FluxTime::Ago(ms) => Ok(format!("-{ms}ms")),
+        }
+    }
+}
+
+/// An aggregate function for `aggregateWindow()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregate {
+    Min,
+    Mean,
+    Max,
+}
+
+impl Aggregate {
+    /// The aggregates of a band, in order.
+    pub const BAND: [Aggregate; 3] = [Aggregate::Min, Aggregate::Mean, Aggregate::Max];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aggregate::Min => "min",
+            Aggregate::Mean => "mean",
+            Aggregate::Max => "max",
+        }
+    }
+}
+
+/// Quote and escape `value` as a Flux string literal.
+pub fn string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            // Prevent string interpolation.
+            '$' if chars.peek() == Some(&'{') => out.push_str("\\$"),
+            c => out.push(c),
+        }
+    }
+
+    out.push('"');
+    out
+}
+
+/// `script` with its calendar windows, e.g. of `aggregateWindow()`, in the
+/// IANA time zone `timezone`, if given.
+pub fn with_location(script: &str, timezone: Option<&str>) -> String {
+    match timezone {
+        Some(timezone) => format!(
+            "import \"timezone\"\n\noption location = timezone.location(name: {})\n\n{script}",
+            string(timezone)
+        ),
+        None => script.to_string(),
+    }
+}
+
+/// A Flux query, built as a pipeline of stages.
+#[derive(Debug, Clone)]
+pub struct FluxQuery {
+    stages: Vec<String>,
+}
+
+impl FluxQuery {
+    /// `from(bucket: ...)`
+    pub fn from(bucket: &str) -> Self {
+        Self {
+            stages: vec![format!("from(bucket: {})", string(bucket))],
+        }
+    }
+
+    /// `union(tables: [...])` of the results of `queries`.
+    pub fn union(queries: &[FluxQuery]) -> Self {
+        let tables: Vec<_> = queries.iter().map(FluxQuery::build).collect();
+        Self {
+            stages: vec![format!("union(tables: [\n{}\n])", tables.join(",\n"))],
+        }
+    }
+
+    fn stage(mut self, stage: String) -> Self {
+        self.stages.push(stage);
+        self
+    }
+
+    /// `range(start: ..., stop: ...)`
+    pub fn range(self, start: FluxTime, stop: Option<FluxTime>) -> Result<Self, String> {
+        let stage = match stop {
+            Some(stop) => format!(
+                "range(start: {}, stop: {})",
+                start.render()?,
+                stop.render()?
+            ),
+            None => format!("range(start: {})", start.render()?),
+        };
+        Ok(self.stage(stage))
+    }
+
+    /// Keep rows where `column` equals any of `values`. Does nothing if
+    /// `values` is empty.
+    pub fn filter_any<S: AsRef<str>>(self, column: &str, values: &[S]) -> Self {
+        if values.is_empty() {
+            return self;
+        }
+
+        let column = string(column);
+        let predicates: Vec<_> = values
+            .iter()
+            .map(|v| format!("r[{column}] == {}", string(v.as_ref())))
+            .collect();
+
+        self.stage(format!("filter(fn: (r) => {})", predicates.join(" or ")))
+    }
+
+    /// Keep rows where `column` equals `value`.
+    pub fn filter_eq(self, column: &str, value: &str) -> Self {
+        self.filter_any(column, &[value])
+    }
+
+    /// `aggregateWindow(every: ..., fn: ..., createEmpty: false)`
+    pub fn aggregate_window(self, every_ms: u64, aggregate: Aggregate) -> Self {
+        self.stage(format!(
+            "aggregateWindow(every: {every_ms}ms, fn: {}, createEmpty: false)",
+            aggregate.name()
+        ))
+    }
+
+    /// `first()`
+    pub fn first(self) -> Self {
+        self.stage("first()".to_string())
+    }
+
+    /// `min()` or `max()`, which keep the row of the selected value. `mean`
+    /// isn't a selector.
+    pub fn select(self, aggregate: Aggregate) -> Self {
+        debug_assert_ne!(aggregate, Aggregate::Mean);
+        self.stage(format!("{}()", aggregate.name()))
+    }
+
+    /// `last()`
+    pub fn last(self) -> Self {
+        self.stage("last()".to_string())
+    }
+
+    /// `group()`, which merges all tables into one.
+    pub fn ungroup(self) -> Self {
+        self.stage("group()".to_string())
+    }
+
+    /// `sort(columns: [...])`
+    pub fn sort(self, columns: &[&str]) -> Self {
+        let columns: Vec<_> = columns.iter().map(|c| string(c)).collect();
+        self.stage(format!("sort(columns: [{}])", columns.join(", ")))
+    }
+
+    /// `limit(n: ...)` or `tail(n: ...)`, if a limit is given.
+    pub fn limit(self, limit: Option<Limit>) -> Self {
+        match limit {
+            Some(Limit::First(n)) => self.stage(format!("limit(n: {n})")),
+            Some(Limit::Last(n)) => self.stage(format!("tail(n: {n})")),
+            None => self,
+        }
+    }
+
+    /// `timeWeightedAvg(unit: 1s)`
+    pub fn time_weighted_avg(self) -> Self {
+        self.stage("timeWeightedAvg(unit: 1s)".to_string())
+    }
+
+    /// `set(key: ..., value: ...)`
+    pub fn set(self, key: &str, value: &str) -> Self {
+        self.stage(format!(
+            "set(key: {}, value: {})",
+            string(key),
+            string(value)
+        ))
+    }
+
+    /// `pivot(rowKey: [...], columnKey: [...], valueColumn: ...)`
+    pub fn pivot(self, row_key: &[&str], column_key: &str, value_column: &str) -> Self {
+        let row_key: Vec<_> = row_key.iter().map(|c| string(c)).collect();
+        self.stage(format!(
+            "pivot(rowKey: [{}], columnKey: [{}], valueColumn: {})",
+            row_key.join(", "),
+            string(column_key),
+            string(value_column)
+        ))
+    }
+
+    /// `to(bucket: ...)`, which writes the rows to `bucket`.
+    pub fn to(self, bucket: &str) -> Self {
+        self.stage(format!("to(bucket: {})", string(bucket)))
+    }
+
+    /// `count()`
+    pub fn count(self) -> Self {
+        self.stage("count()".to_string())
+    }
+
+    /// `keep(columns: [...])`
+    pub fn keep(self, columns: &[&str]) -> Self {
+        let columns: Vec<_> = columns.iter().map(|c| string(c)).collect();
+        self.stage(format!("keep(columns: [{}])", columns.join(", ")))
+    }
+
+    /// `yield(name: ...)`
+    pub fn yield_as(self, name: &str) -> Self {
+        self.stage(format!("yield(name: {})", string(name)))
+    }
+
+    /// The query text.
+    pub fn build(&self) -> String {
+        self.stages.join("\n    |> ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_strings() {
+        assert_eq!(string("aht10"), r#""aht10""#);
+        assert_eq!(string(r#"a"b"#), r#""a\"b""#);
+        assert_eq!(string(r"a\b"), r#""a\\b""#);
+        assert_eq!(string("${x}"), r#""\${x}""#);
+        assert_eq!(string("$x"), r#""$x""#);
+        assert_eq!(string("a\nb"), r#""a\nb""#);
+    }
+
+    #[test]
+    fn builds_range_query() {
+        let query = FluxQuery::from("Temperature")
+            .range(FluxTime::At(0), Some(FluxTime::At(1500)))
+            .unwrap()
+            .filter_eq("_measurement", "aht10")
+            .filter_any("_field", &["temperature", "co2"])
+            .aggregate_window(30000, Aggregate::Mean)
+            .yield_as("mean");
+
+        assert_eq!(
+            query.build(),
+            r#"from(bucket: "Temperature")
+    |> range(start: 1970-01-01T00:00:00.000Z, stop: 1970-01-01T00:00:01.500Z)
+    |> filter(fn: (r) => r["_measurement"] == "aht10")
+    |> filter(fn: (r) => r["_field"] == "temperature" or r["_field"] == "co2")
+    |> aggregateWindow(every: 30000ms, fn: mean, createEmpty: false)
+    |> yield(name: "mean")"#
+        );
+    }
+
+    #[test]
+    fn sorts_across_tables() {
+        let query = FluxQuery::from("b")
+            .range(FluxTime::Ago(1000), None)
+            .unwrap()
+            .ungroup()
+            .sort(&["_time"]);
+
+        assert_eq!(
+            query.build(),
+            "from(bucket: \"b\")\n    |> range(start: -1000ms)\n    |> group()\n    |> sort(columns: [\"_time\"])"
+        );
+    }
+
+    #[test]
+    fn builds_union() {
+        let table = |aggregate: Aggregate| {
+            FluxQuery::from("b")
+                .range(FluxTime::Ago(1000), None)
+                .unwrap()
+                .unwrap()
+                .aggregate_window(500, aggregate)
+                .set("_field", aggregate.name())
+        };
+
+        let query = FluxQuery::union(&[table(Aggregate::Min), table(Aggregate::Max)])
+            .ungroup()
+            .pivot(&["_time"], "_field", "_value");
+
+        assert_eq!(
+            query.build(),
+            r#"union(tables: [
+from(bucket: "b")
+    |> range(start: -1000ms)
+    |> aggregateWindow(every: 500ms, fn: min, createEmpty: false)
+    |> set(key: "_field", value: "min"),
+from(bucket: "b")
+    |> range(start: -1000ms)
+    |> aggregateWindow(every: 500ms, fn: max, createEmpty: false)
+    |> set(key: "_field", value: "max")
+])
+    |> group()
+    |> pivot(rowKey: ["_time"], columnKey: ["_field"], valueColumn: "_value")"#
+        );
+    }
+
+    #[test]
+    fn rejects_out_of_range_time() {
+        let query = FluxQuery::from("b").range(FluxTime::At(i64::MAX), None);
+        assert!(query.is_err());
+    }
+
+    #[test]
+    fn sets_location() {
+        assert_eq!(with_location("x", None), "x");
+        assert_eq!(
+            with_location("x", Some("Europe/Amsterdam")),
+            "import \"timezone\"\n\noption location = timezone.location(name: \"Europe/Amsterdam\")\n\nx"
+        );
+    }
+
+    #[test]
+    fn skips_empty_filter() {
+        let query = FluxQuery::from("b")
+            .range(FluxTime::Ago(1000), None)
+            .unwrap()
+            .filter_any::<&str>("_field", &[])
+            .last();
+
+        assert_eq!(
+            query.build(),
+            "from(bucket: \"b\")\n    |> range(start: -1000ms)\n    |> last()"
+        );
+    }
+}
@@ -0,0 +1,72 @@
+use serde::{Deserialize, Serialize};
+
+use crate::DataPoint;
+
+/// The encoding of a range response.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResponseFormat {
+    #[default]
+    Json,
+    Compact,
+    Binary,
+}
+
+const SCALE: f64 = 100.;
+
+/// A column oriented series where timestamps are offsets in milliseconds from
+/// `start` and values are integers that have to be divided by `scale`.
+#[derive(Debug, Clone, Serialize)]
+pub struct CompactSeries {
+    pub start: i64,
+    pub scale: u32,
+    pub time: Vec<i64>,
+    pub temperature: Vec<Option<i64>>,
+    pub humidity: Vec<Option<i64>>,
+    pub co2: Vec<Option<i64>>,
+    /// Set if the backend was unreachable and the points were served from
+    /// the local cache.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub stale: bool,
+}
+
+impl CompactSeries {
+    /// Encode `points`, which must be sorted by time.
+    pub fn new(points: &[DataPoint]) -> Self {
+        let start = points.first().map(|p| p.time).unwrap_or_default();
+        let fixed = |v: f64| (v * SCALE).round() as i64;
+
+        Self {
+            start,
+            scale: SCALE as u32,
+            time: points.iter().map(|p| p.time - start).collect(),
+            temperature: points.iter().map(|p| p.temperature.map(fixed)).collect(),
+            humidity: points.iter().map(|p| p.humidity.map(fixed)).collect(),
+            co2: points.iter().map(|p| p.co2.map(fixed)).collect(),
+            stale: false,
+        }
+    }
+}
+
+/// The magic bytes that start a [`encode_binary`] payload.
+pub const BINARY_MAGIC: &[u8; 4] = b"TMPS";
+
+/// Encode points as a 4 byte magic (`TMPS`), a little-endian `u32` record
+/// count, followed by that many 20 byte little-endian records of
+/// `(time_ms: i64, temperature: f32, humidity: f32, co2: f32)`. Missing values
+/// are encoded as NaN.
+pub fn encode_binary(points: &[DataPoint]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(8 + points.len() * 20);
+
+    out.extend_from_slice(BINARY_MAGIC);
+    out.extend_from_slice(&(points.len() as u32).to_le_bytes());
+
+    for point in points {
+        out.extend_from_slice(&point.time.to_le_bytes());
+        for value in [point.temperature, point.humidity, point.co2] {
+            out.extend_from_slice(&(value.unwrap_or(f64::NAN) as f32).to_le_bytes());
+        }
+    }
+
+    out
+}
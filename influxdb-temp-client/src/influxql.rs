@@ -0,0 +1,288 @@
+use std::sync::{Arc, RwLock};
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    backend::{BackendError, TimeRange, TimeSeriesBackend},
+    DataPoint, Field, RangeOptions, MEASUREMENT,
+};
+
+#[derive(Debug, Deserialize)]
+struct Response {
+    #[serde(default)]
+    results: Vec<StatementResult>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StatementResult {
+    #[serde(default)]
+    series: Vec<Series>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Series {
+    columns: Vec<String>,
+    #[serde(default)]
+    values: Vec<Vec<Value>>,
+}
+
+impl Series {
+    /// The rows as points, with times in milliseconds as requested with
+    /// `epoch=ms`. Columns that aren't fields, e.g. tags, are skipped.
+    fn points(&self) -> impl Iterator<Item = DataPoint> + '_ {
+        let fields: Vec<_> = self
+            .columns
+            .iter()
+            .map(|c| c.parse::<Field>().ok())
+            .collect();
+
+        self.values.iter().filter_map(move |row| {
+            let mut point = DataPoint {
+                time: row.first()?.as_i64()?,
+                temperature: None,
+                humidity: None,
+                co2: None,
+            };
+
+            for (field, value) in fields.iter().zip(row) {
+                if let (Some(field), Some(value)) = (field, value.as_f64()) {
+                    *field.value_mut(&mut point) = Some((value * 100.).round() / 100.);
+                }
+            }
+
+            Some(point)
+        })
+    }
+}
+
+/// The rows of `series` as points labeled with the end of their window of
+/// `window` milliseconds, like in Flux. InfluxQL labels them with the start.
+fn window_ends(series: &[Series], window: i64) -> Vec<DataPoint> {
+    series
+        .iter()
+        .flat_map(Series::points)
+        .map(|p| DataPoint {
+            time: p.time + window,
+            ..p
+        })
+        .collect()
+}
+
+/// The username and password of an [`InfluxQlBackend`], which can be replaced
+/// while it is in use, e.g. when the password is rotated.
+#[derive(Clone, Default)]
+pub struct CredentialsHandle(Arc<RwLock<Option<(String, String)>>>);
+
+impl CredentialsHandle {
+    pub fn replace(&self, credentials: Option<(String, String)>) {
+        *self.0.write().unwrap() = credentials;
+    }
+}
+
+/// A backend for InfluxDB 1.x, which is queried with InfluxQL through its
+/// `/query` API. Points are stored in [`MEASUREMENT`] with a field per
+/// [`Field`], like in InfluxDB 2.
+pub struct InfluxQlBackend {
+    client: reqwest::Client,
+    url: String,
+    database: String,
+    credentials: CredentialsHandle,
+}
+
+impl InfluxQlBackend {
+    /// Use `database` of the InfluxDB at `url` (e.g. `http://localhost:8086`),
+    /// authenticating with a username and password if given.
+    pub fn new(url: &str, database: &str, credentials: Option<(String, String)>) -> Self {
+        let handle = CredentialsHandle::default();
+        handle.replace(credentials);
+
+        Self {
+            client: reqwest::Client::new(),
+            url: url.trim_end_matches('/').to_string(),
+            database: database.to_string(),
+            credentials: handle,
+        }
+    }
+
+    /// Send requests with `client`, e.g. one with timeouts.
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    pub fn credentials(&self) -> CredentialsHandle {
+        self.credentials.clone()
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        let request = self
+            .client
+            .request(method, format!("{}{path}", self.url))
+            .query(&[("db", self.database.as_str())]);
+
+        match &*self.credentials.0.read().unwrap() {
+            Some((username, password)) => request.basic_auth(username, Some(password)),
+            None => request,
+        }
+    }
+
+    async fn query(&self, query: String) -> Result<Vec<Series>, BackendError> {
+        let response: Response = self
+            .request(reqwest::Method::GET, "/query")
+            .query(&[("q", query.as_str()), ("epoch", "ms")])
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(BackendError::from)?
+            .json()
+            .await
+            .map_err(BackendError::from)?;
+
+        if let Some(error) = response.error {
+            return Err(error.into());
+        }
+
+        let mut series = Vec::new();
+        for result in response.results {
+            if let Some(error) = result.error {
+                return Err(error.into());
+            }
+            series.extend(result.series);
+        }
+
+        Ok(series)
+    }
+}
+
+/// `"name"`, as an InfluxQL identifier.
+fn identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[async_trait]
+impl TimeSeriesBackend for InfluxQlBackend {
+    async fn get_current(&mut self) -> Result<Option<DataPoint>, BackendError> {
+        let query = format!(
+            "SELECT * FROM {} WHERE time > now() - 1d ORDER BY time DESC LIMIT 1",
+            identifier(MEASUREMENT)
+        );
+
+        let series = self.query(query).await?;
+        Ok(series.iter().flat_map(Series::points).next())
+    }
+
+    async fn get_range(
+        &mut self,
+        range: TimeRange,
+        options: &RangeOptions,
+    ) -> Result<Vec<DataPoint>, BackendError> {
+        let (start, stop) = range.bounds();
+        let start = match options.since {
+            Some(since) => start.max(since + 1),
+            None => start,
+        };
+        let window = options.window(&range);
+
+        let fields = if options.fields.is_empty() {
+            Field::ALL.to_vec()
+        } else {
+            options.fields.clone()
+        };
+
+        let means: Vec<_> = fields
+            .iter()
+            .map(|f| {
+                let name = identifier(f.name());
+                format!("mean({name}) AS {name}")
+            })
+            .collect();
+
+        let query = format!(
+            "SELECT {} FROM {} WHERE time >= {start}ms AND time <= {stop}ms GROUP BY time({window}ms) fill(none)",
+            means.join(", "),
+            identifier(MEASUREMENT)
+        );
+
+        let series = self.query(query).await?;
+        let mut points = window_ends(&series, window as i64);
+        options.apply_limit(&mut points);
+
+        Ok(points)
+    }
+
+    async fn write(&mut self, points: &[DataPoint]) -> Result<(), BackendError> {
+        let body: Vec<_> = points
+            .iter()
+            .filter_map(|point| {
+                let fields: Vec<_> = Field::ALL
+                    .iter()
+                    .filter_map(|f| f.value(point).map(|v| format!("{}={v}", f.name())))
+                    .collect();
+
+                if fields.is_empty() {
+                    return None;
+                }
+
+                Some(format!("{MEASUREMENT} {} {}", fields.join(","), point.time))
+            })
+            .collect();
+
+        self.request(reqwest::Method::POST, "/write")
+            .query(&[("precision", "ms")])
+            .body(body.join("\n"))
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map(|_| ())
+            .map_err(BackendError::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn series() -> Series {
+        serde_json::from_value(json!({
+            "columns": ["time", "room", "temperature", "humidity"],
+            "values": [
+                [1000, "bedroom", 21.456, null],
+                [2000, "bedroom", 21.5, 45],
+                ["invalid", "bedroom", 22, 46],
+            ],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn points_skip_tags_and_invalid_times() {
+        let points: Vec<_> = series().points().collect();
+
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[0].time, 1000);
+        assert_eq!(points[0].temperature, Some(21.46));
+        assert_eq!(points[0].humidity, None);
+        assert_eq!(points[1].humidity, Some(45.));
+        assert_eq!(points[1].co2, None);
+    }
+
+    #[test]
+    fn windows_are_labeled_with_their_end() {
+        let points = window_ends(&[series()], 60_000);
+        assert_eq!(points[0].time, 61_000);
+        assert_eq!(points[1].time, 62_000);
+    }
+
+    #[test]
+    fn identifiers_are_escaped() {
+        assert_eq!(identifier("temperature"), "\"temperature\"");
+        assert_eq!(identifier("a\"b"), "\"a\\\"b\"");
+        assert_eq!(identifier("a\\b"), "\"a\\\\b\"");
+    }
+}
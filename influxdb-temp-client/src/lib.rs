@@ -0,0 +1,46 @@
+//! Data access layer for temperature, humidity and CO2 measurements stored in
+//! InfluxDB 2.
+//!
+//! [`Client`] wraps an [`influxdb2::Client`] and returns [`DataPoint`]s, which
+//! can be serialized as-is or with one of the encodings in [`format`]. Other
+//! stores can be used by implementing [`TimeSeriesBackend`].
+
+mod backend;
+mod client;
+pub mod downsample;
+mod flux;
+pub mod format;
+#[cfg(feature = "influxql")]
+mod influxql;
+mod mock;
+#[cfg(feature = "postgres")]
+mod postgres;
+#[cfg(feature = "prometheus")]
+mod prometheus;
+pub mod queries;
+mod rollup;
+pub mod transform;
+#[cfg(feature = "victoriametrics")]
+mod victoriametrics;
+
+pub use backend::{
+    band_points, raw_points, stream_range, stream_raw_range, time_weighted_avg, BackendError,
+    CacheStats, Retention, TimeRange, TimeSeriesBackend, DEFAULT_POINTS,
+};
+pub use flux::FluxTime;
+#[cfg(feature = "influxql")]
+pub use influxql::{CredentialsHandle, InfluxQlBackend};
+pub use mock::MockBackend;
+#[cfg(feature = "postgres")]
+pub use postgres::PostgresBackend;
+#[cfg(feature = "prometheus")]
+pub use prometheus::PrometheusBackend;
+pub use rollup::{Resolution, Rollups};
+#[cfg(feature = "victoriametrics")]
+pub use victoriametrics::VictoriaMetricsBackend;
+
+pub use client::{
+    Annotation, BandPoint, Client, ClientHandle, DataPoint, DataPointWithOffset, Field, Limit,
+    MetricPoint, RangeOptions, Records, SchemaProblem, ANNOTATIONS_MEASUREMENT, BUCKET,
+    MEASUREMENT,
+};
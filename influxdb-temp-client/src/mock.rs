@@ -0,0 +1,145 @@
+use std::f64::consts::TAU;
+
+use async_trait::async_trait;
+use chrono::Utc;
+
+use crate::{
+    backend::{BackendError, TimeRange, TimeSeriesBackend},
+    Annotation, DataPoint, Field, RangeOptions,
+};
+
+const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+
+/// How often the simulated sensor takes a reading.
+const SAMPLE_MS: i64 = 10_000;
+
+/// Deterministic noise in `-1..1` for `time`, so that the same range always
+/// returns the same points.
+fn noise(time: i64, seed: u64) -> f64 {
+    let mut x = (time as u64 / SAMPLE_MS as u64) ^ seed.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    x ^= x >> 33;
+
+    (x as f64 / u64::MAX as f64) * 2. - 1.
+}
+
+fn round(value: f64) -> f64 {
+    (value * 100.).round() / 100.
+}
+
+/// Synthetic readings of a room at `time`: the temperature peaks in the
+/// afternoon, humidity moves the other way and CO2 rises while the room is
+/// occupied in the evening.
+fn reading(time: i64) -> DataPoint {
+    let day = time.rem_euclid(DAY_MS) as f64 / DAY_MS as f64;
+    // One in the afternoon, minus one at night.
+    let daily = (TAU * (day - 0.375)).sin();
+    // Slow drift over the seasons.
+    let seasonal = (TAU * time as f64 / (365.25 * DAY_MS as f64)).sin();
+
+    let hour = day * 24.;
+    let occupied = if (17. ..23.).contains(&hour) {
+        (TAU * (hour - 17.) / 12.).sin()
+    } else {
+        0.
+    };
+
+    DataPoint {
+        time,
+        temperature: Some(round(
+            20. + 2. * daily + 1.5 * seasonal + 0.2 * noise(time, 1),
+        )),
+        humidity: Some(round(50. - 6. * daily - 5. * seasonal + noise(time, 2))),
+        co2: Some((450. + 700. * occupied + 15. * noise(time, 3)).round()),
+    }
+}
+
+/// Serves synthetic data without any database, for frontend development and
+/// tests. Written points are accepted and dropped, annotations are kept in
+/// memory.
+#[derive(Debug, Default)]
+pub struct MockBackend {
+    annotations: Vec<Annotation>,
+}
+
+impl MockBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl TimeSeriesBackend for MockBackend {
+    async fn get_current(&mut self) -> Result<Option<DataPoint>, BackendError> {
+        let now = Utc::now().timestamp_millis();
+        Ok(Some(reading(now - now.rem_euclid(SAMPLE_MS))))
+    }
+
+    async fn get_range(
+        &mut self,
+        range: TimeRange,
+        options: &RangeOptions,
+    ) -> Result<Vec<DataPoint>, BackendError> {
+        let (start, stop) = range.bounds();
+        let window = options.window(&range).max(1) as i64;
+
+        let start = match options.since {
+            Some(since) => start.max(since + 1),
+            None => start,
+        };
+
+        // Like `aggregateWindow()`, windows are aligned to the epoch and
+        // stamped with their end. The reading in the middle stands in for
+        // the mean.
+        let mut points = Vec::new();
+        let mut end = start - start.rem_euclid(window) + window;
+        while end - window < stop {
+            let mut point = reading(end - window / 2);
+            point.time = end.min(stop);
+
+            if !options.fields.is_empty() {
+                for field in Field::ALL {
+                    if !options.fields.contains(&field) {
+                        match field {
+                            Field::Temperature => point.temperature = None,
+                            Field::Humidity => point.humidity = None,
+                            Field::Co2 => point.co2 = None,
+                        }
+                    }
+                }
+            }
+
+            points.push(point);
+            end += window;
+        }
+
+        options.apply_limit(&mut points);
+        Ok(points)
+    }
+
+    async fn write(&mut self, points: &[DataPoint]) -> Result<(), BackendError> {
+        println!("Mock backend: dropping {} written point(s)", points.len());
+        Ok(())
+    }
+
+    async fn get_annotations(&mut self, range: TimeRange) -> Result<Vec<Annotation>, BackendError> {
+        let (start, stop) = range.bounds();
+        Ok(self
+            .annotations
+            .iter()
+            .filter(|a| (start..=stop).contains(&a.time))
+            .cloned()
+            .collect())
+    }
+
+    async fn write_annotation(&mut self, annotation: &Annotation) -> Result<(), BackendError> {
+        let index = self
+            .annotations
+            .partition_point(|a| a.time <= annotation.time);
+        self.annotations.insert(index, annotation.clone());
+        Ok(())
+    }
+}
@@ -0,0 +1,150 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{postgres::PgPoolOptions, PgPool};
+
+use crate::{
+    backend::{BackendError, TimeRange, TimeSeriesBackend},
+    DataPoint, Field, RangeOptions,
+};
+
+/// A backend reading from a PostgreSQL table (or TimescaleDB hypertable) with
+/// the columns `time timestamptz`, `temperature`, `humidity` and `co2`
+/// (all `double precision`).
+pub struct PostgresBackend {
+    pool: PgPool,
+    table: String,
+}
+
+impl PostgresBackend {
+    /// Connect to the database at `url`, reading from `table`.
+    pub async fn connect(url: &str, table: &str) -> Result<Self, String> {
+        let valid = !table.is_empty()
+            && table
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+        if !valid {
+            return Err(format!("Invalid table name {table:?}."));
+        }
+
+        let pool = PgPoolOptions::new()
+            .max_connections(4)
+            .connect(url)
+            .await
+            .map_err(|e| format!("{e}"))?;
+
+        Ok(Self {
+            pool,
+            table: table.to_string(),
+        })
+    }
+
+    fn column(field: Field, options: &RangeOptions) -> String {
+        if options.fields.is_empty() || options.fields.contains(&field) {
+            format!("avg({})", field.name())
+        } else {
+            "NULL::double precision".to_string()
+        }
+    }
+}
+
+type Row = (
+    DateTime<Utc>,
+    Option<f64>,
+    Option<f64>,
+    Option<f64>,
+);
+
+fn to_point((time, temperature, humidity, co2): Row) -> DataPoint {
+    let round = |v: f64| (v * 100.).round() / 100.;
+
+    DataPoint {
+        time: time.timestamp_millis(),
+        temperature: temperature.map(round),
+        humidity: humidity.map(round),
+        co2,
+    }
+}
+
+impl From<sqlx::Error> for BackendError {
+    fn from(error: sqlx::Error) -> Self {
+        match error {
+            sqlx::Error::PoolTimedOut => BackendError::Timeout(error.to_string()),
+            _ => BackendError::Failed(error.to_string()),
+        }
+    }
+}
+
+#[async_trait]
+impl TimeSeriesBackend for PostgresBackend {
+    async fn get_current(&mut self) -> Result<Option<DataPoint>, BackendError> {
+        let query = format!(
+            "SELECT time, temperature, humidity, co2 FROM {} ORDER BY time DESC LIMIT 1",
+            self.table
+        );
+
+        let row: Option<Row> = sqlx::query_as(&query)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(BackendError::from)?;
+
+        Ok(row.map(to_point))
+    }
+
+    async fn get_range(
+        &mut self,
+        range: TimeRange,
+        options: &RangeOptions,
+    ) -> Result<Vec<DataPoint>, BackendError> {
+        let (start, stop) = range.bounds();
+        let start = match options.since {
+            Some(since) => start.max(since + 1),
+            None => start,
+        };
+
+        // Like `aggregateWindow`, label each window with its end.
+        let query = format!(
+            "SELECT date_bin($1 * interval '1 millisecond', time, timestamptz 'epoch') + $1 * interval '1 millisecond' AS window_end, \
+             {}, {}, {} \
+             FROM {} \
+             WHERE time >= $2 AND time < $3 \
+             GROUP BY window_end \
+             ORDER BY window_end",
+            Self::column(Field::Temperature, options),
+            Self::column(Field::Humidity, options),
+            Self::column(Field::Co2, options),
+            self.table,
+        );
+
+        let rows: Vec<Row> = sqlx::query_as(&query)
+            .bind(options.window(&range) as i64)
+            .bind(DateTime::<Utc>::from_timestamp_millis(start))
+            .bind(DateTime::<Utc>::from_timestamp_millis(stop))
+            .fetch_all(&self.pool)
+            .await
+            .map_err(BackendError::from)?;
+
+        Ok(rows.into_iter().map(to_point).collect())
+    }
+
+    async fn write(&mut self, points: &[DataPoint]) -> Result<(), BackendError> {
+        let query = format!(
+            "INSERT INTO {} (time, temperature, humidity, co2) VALUES ($1, $2, $3, $4)",
+            self.table
+        );
+
+        let mut transaction = self.pool.begin().await.map_err(BackendError::from)?;
+
+        for point in points {
+            sqlx::query(&query)
+                .bind(DateTime::<Utc>::from_timestamp_millis(point.time))
+                .bind(point.temperature)
+                .bind(point.humidity)
+                .bind(point.co2)
+                .execute(&mut *transaction)
+                .await
+                .map_err(BackendError::from)?;
+        }
+
+        transaction.commit().await.map_err(BackendError::from)
+    }
+}
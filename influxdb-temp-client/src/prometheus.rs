@@ -0,0 +1,154 @@
+use std::collections::{BTreeMap, HashMap};
+
+use async_trait::async_trait;
+use serde::Deserialize;
+
+use crate::{
+    backend::{BackendError, TimeRange, TimeSeriesBackend},
+    DataPoint, Field, RangeOptions,
+};
+
+#[derive(Debug, Deserialize)]
+struct Response {
+    data: ResponseData,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResponseData {
+    result: Vec<Series>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Series {
+    #[serde(default)]
+    values: Vec<(f64, String)>,
+    value: Option<(f64, String)>,
+}
+
+/// A backend querying a Prometheus compatible store through its range query
+/// API, with a configurable metric (or selector) per field.
+pub struct PrometheusBackend {
+    pub(crate) client: reqwest::Client,
+    pub(crate) url: String,
+    metrics: HashMap<Field, String>,
+}
+
+impl PrometheusBackend {
+    /// Use the Prometheus API at `url` (e.g. `http://localhost:9090`). Fields
+    /// without an entry in `metrics` are never returned.
+    pub fn new(url: &str, metrics: HashMap<Field, String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url: url.trim_end_matches('/').to_string(),
+            metrics,
+        }
+    }
+
+    async fn query(
+        &self,
+        path: &str,
+        params: &[(&str, String)],
+    ) -> Result<Vec<Series>, BackendError> {
+        let response: Response = self
+            .client
+            .get(format!("{}{path}", self.url))
+            .query(params)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(BackendError::from)?
+            .json()
+            .await
+            .map_err(BackendError::from)?;
+
+        Ok(response.data.result)
+    }
+}
+
+fn set(point: &mut DataPoint, field: Field, value: &str) {
+    let value = value.parse::<f64>().ok().map(|v| (v * 100.).round() / 100.);
+
+    match field {
+        Field::Temperature => point.temperature = value,
+        Field::Humidity => point.humidity = value,
+        Field::Co2 => point.co2 = value,
+    }
+}
+
+fn empty_point(time: i64) -> DataPoint {
+    DataPoint {
+        time,
+        temperature: None,
+        humidity: None,
+        co2: None,
+    }
+}
+
+#[async_trait]
+impl TimeSeriesBackend for PrometheusBackend {
+    async fn get_current(&mut self) -> Result<Option<DataPoint>, BackendError> {
+        let mut point: Option<DataPoint> = None;
+
+        for (&field, metric) in &self.metrics {
+            let query = format!("last_over_time({metric}[1d])");
+            let series = self.query("/api/v1/query", &[("query", query)]).await?;
+
+            if let Some((time, value)) = series.into_iter().find_map(|s| s.value) {
+                let point = point.get_or_insert_with(|| empty_point((time * 1000.) as i64));
+                set(point, field, &value);
+            }
+        }
+
+        Ok(point)
+    }
+
+    async fn get_range(
+        &mut self,
+        range: TimeRange,
+        options: &RangeOptions,
+    ) -> Result<Vec<DataPoint>, BackendError> {
+        let (start, stop) = range.bounds();
+        let start = match options.since {
+            Some(since) => start.max(since + 1),
+            None => start,
+        };
+        let window = options.window(&range);
+
+        let fields = if options.fields.is_empty() {
+            Field::ALL.to_vec()
+        } else {
+            options.fields.clone()
+        };
+
+        let mut points = BTreeMap::new();
+
+        for field in fields {
+            let Some(metric) = self.metrics.get(&field) else {
+                continue;
+            };
+
+            let params = [
+                ("query", format!("avg_over_time({metric}[{window}ms])")),
+                ("start", format!("{}", start as f64 / 1000.)),
+                ("end", format!("{}", stop as f64 / 1000.)),
+                ("step", format!("{window}ms")),
+            ];
+
+            for series in self.query("/api/v1/query_range", &params).await? {
+                for (time, value) in series.values {
+                    let time = (time * 1000.) as i64;
+                    let point = points.entry(time).or_insert_with(|| empty_point(time));
+                    set(point, field, &value);
+                }
+            }
+        }
+
+        Ok(points.into_values().collect())
+    }
+
+    async fn write(&mut self, _points: &[DataPoint]) -> Result<(), BackendError> {
+        Err(BackendError::Unsupported(
+            "Writing is not supported by the Prometheus backend.".to_string(),
+        ))
+    }
+}
@@ -0,0 +1,449 @@
+//! The Flux queries that [`Client`](crate::Client) sends, as pure functions
+//! of their parameters.
+
+use crate::{
+    flux::{Aggregate, FluxQuery, FluxTime},
+    Field, Limit, Resolution, BUCKET, MEASUREMENT,
+};
+
+/// The bucket and measurement to read points from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Source<'a> {
+    pub bucket: &'a str,
+    pub measurement: &'a str,
+}
+
+impl Source<'static> {
+    /// The raw points in [`BUCKET`].
+    pub const RAW: Source<'static> = Source {
+        bucket: BUCKET,
+        measurement: MEASUREMENT,
+    };
+}
+
+impl Source<'_> {
+    fn range(&self, start: FluxTime, stop: Option<FluxTime>) -> Result<FluxQuery, String> {
+        Ok(FluxQuery::from(self.bucket)
+            .range(start, stop)?
+            .filter_eq("_measurement", self.measurement))
+    }
+}
+
+/// Means of `fields` (all if empty) per window of `window_ms`, pivoted into a
+/// row per window and sorted by time.
+pub fn range(
+    source: Source,
+    start: FluxTime,
+    stop: Option<FluxTime>,
+    window_ms: u64,
+    fields: &[Field],
+    limit: Option<Limit>,
+) -> Result<String, String> {
+    let fields: Vec<_> = fields.iter().map(Field::name).collect();
+
+    Ok(source
+        .range(start, stop)?
+        .filter_any("_field", &fields)
+        .aggregate_window(window_ms, Aggregate::Mean)
+        // Pivot first, so that a limit counts whole points.
+        .pivot(&["_time"], "_field", "_value")
+        .ungroup()
+        .sort(&["_time"])
+        .limit(limit)
+        .yield_as("mean")
+        .build())
+}
+
+/// Means of the field `name` per window of `window_ms`.
+pub fn metric(
+    source: Source,
+    name: &str,
+    start_ms: i64,
+    stop_ms: i64,
+    window_ms: u64,
+) -> Result<String, String> {
+    Ok(source
+        .range(FluxTime::At(start_ms), Some(FluxTime::At(stop_ms)))?
+        .filter_eq("_field", name)
+        .aggregate_window(window_ms, Aggregate::Mean)
+        .sort(&["_time"])
+        .keep(&["_time", "_value"])
+        .build())
+}
+
+/// The unaggregated values of the field `name`, e.g. to find bad readings.
+pub fn raw_metric(
+    source: Source,
+    name: &str,
+    start_ms: i64,
+    stop_ms: i64,
+) -> Result<String, String> {
+    Ok(source
+        .range(FluxTime::At(start_ms), Some(FluxTime::At(stop_ms)))?
+        .filter_eq("_field", name)
+        .sort(&["_time"])
+        .keep(&["_time", "_value"])
+        .build())
+}
+
+/// The minimum, mean and maximum of the field `name` per window of
+/// `window_ms`, pivoted into `min`, `mean` and `max` columns. They are taken
+/// from the respective `sources`, e.g. the extremes of a rollup.
+pub fn band(
+    sources: [Source; 3],
+    name: &str,
+    start_ms: i64,
+    stop_ms: i64,
+    window_ms: u64,
+    limit: Option<Limit>,
+) -> Result<String, String> {
+    let tables = Aggregate::BAND
+        .iter()
+        .zip(sources)
+        .map(|(aggregate, source)| {
+            Ok(source
+                .range(FluxTime::At(start_ms), Some(FluxTime::At(stop_ms)))?
+                .filter_eq("_field", name)
+                .aggregate_window(window_ms, *aggregate)
+                .set("_field", aggregate.name()))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    Ok(FluxQuery::union(&tables)
+        .ungroup()
+        .pivot(&["_time"], "_field", "_value")
+        .sort(&["_time"])
+        .limit(limit)
+        .keep(&["_time", "min", "mean", "max"])
+        .build())
+}
+
+/// The time-weighted average of the field `name`.
+pub fn time_weighted_avg(
+    source: Source,
+    name: &str,
+    start_ms: i64,
+    stop_ms: i64,
+) -> Result<String, String> {
+    Ok(source
+        .range(FluxTime::At(start_ms), Some(FluxTime::At(stop_ms)))?
+        .filter_eq("_field", name)
+        .time_weighted_avg()
+        .keep(&["_value"])
+        .build())
+}
+
+/// The most recent value of every field since `start`.
+pub fn latest(source: Source, start: FluxTime) -> Result<String, String> {
+    Ok(source.range(start, None)?.last().build())
+}
+
+/// The time of the oldest point.
+pub fn oldest(source: Source) -> Result<String, String> {
+    Ok(source
+        .range(FluxTime::At(0), None)?
+        .first()
+        .keep(&["_time"])
+        .build())
+}
+
+/// The texts of the annotations in `source` between `start_ms` and
+/// `stop_ms`.
+pub fn annotations(source: Source, start_ms: i64, stop_ms: i64) -> Result<String, String> {
+    Ok(source
+        .range(FluxTime::At(start_ms), Some(FluxTime::At(stop_ms)))?
+        .filter_eq("_field", "text")
+        .sort(&["_time"])
+        .keep(&["_time", "_value"])
+        .build())
+}
+
+/// The lowest and highest value of every field ever, with their times. The
+/// `_field` of every row is the name of the field and `record` is `min` or
+/// `max`.
+pub fn records(source: Source) -> Result<String, String> {
+    let table = |aggregate: Aggregate| {
+        Ok::<_, String>(
+            source
+                .range(FluxTime::At(0), None)?
+                .select(aggregate)
+                .set("record", aggregate.name()),
+        )
+    };
+
+    Ok(
+        FluxQuery::union(&[table(Aggregate::Min)?, table(Aggregate::Max)?])
+            .keep(&["_time", "_field", "_value", "record"])
+            .build(),
+    )
+}
+
+/// The most recent value of every field since `start`, to check their types.
+pub fn field_types(source: Source, start: FluxTime) -> Result<String, String> {
+    Ok(source
+        .range(start, None)?
+        .last()
+        .keep(&["_field", "_value"])
+        .build())
+}
+
+/// Aggregate the points of `source` in the range into the means, minimums
+/// and maximums of the `resolution` rollup in `bucket`. Only the amount of
+/// written points is returned.
+pub fn rollup(
+    source: Source,
+    bucket: &str,
+    resolution: Resolution,
+    start: FluxTime,
+    stop: Option<FluxTime>,
+) -> Result<String, String> {
+    let tables = Aggregate::BAND
+        .iter()
+        .map(|aggregate| {
+            Ok(source
+                .range(start, stop)?
+                .aggregate_window(resolution.window_ms(), *aggregate)
+                .set("_measurement", &resolution.measurement_of(*aggregate)))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    Ok(FluxQuery::union(&tables).to(bucket).count().build())
+}
+
+/// The time of the most recent window of a rollup.
+pub fn latest_time(source: Source) -> Result<String, String> {
+    Ok(source
+        .range(FluxTime::At(0), None)?
+        .last()
+        .keep(&["_time"])
+        .build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn range_of_all_fields() {
+        assert_eq!(
+            range(Source::RAW, FluxTime::Ago(3600000), None, 30000, &[], None).unwrap(),
+            r#"from(bucket: "Temperature")
+    |> range(start: -3600000ms)
+    |> filter(fn: (r) => r["_measurement"] == "aht10")
+    |> aggregateWindow(every: 30000ms, fn: mean, createEmpty: false)
+    |> pivot(rowKey: ["_time"], columnKey: ["_field"], valueColumn: "_value")
+    |> group()
+    |> sort(columns: ["_time"])
+    |> yield(name: "mean")"#
+        );
+    }
+
+    #[test]
+    fn range_with_fields_and_limit() {
+        let source = Source {
+            bucket: "rollups",
+            measurement: "aht10_1h",
+        };
+
+        assert_eq!(
+            range(
+                source,
+                FluxTime::At(0),
+                Some(FluxTime::At(1000)),
+                3600000,
+                &[Field::Humidity, Field::Co2],
+                Some(Limit::Last(10)),
+            )
+            .unwrap(),
+            r#"from(bucket: "rollups")
+    |> range(start: 1970-01-01T00:00:00.000Z, stop: 1970-01-01T00:00:01.000Z)
+    |> filter(fn: (r) => r["_measurement"] == "aht10_1h")
+    |> filter(fn: (r) => r["_field"] == "humidity" or r["_field"] == "co2")
+    |> aggregateWindow(every: 3600000ms, fn: mean, createEmpty: false)
+    |> pivot(rowKey: ["_time"], columnKey: ["_field"], valueColumn: "_value")
+    |> group()
+    |> sort(columns: ["_time"])
+    |> tail(n: 10)
+    |> yield(name: "mean")"#
+        );
+    }
+
+    #[test]
+    fn metric_escapes_name() {
+        assert_eq!(
+            metric(Source::RAW, "pm\"25", 0, 1000, 30000).unwrap(),
+            r#"from(bucket: "Temperature")
+    |> range(start: 1970-01-01T00:00:00.000Z, stop: 1970-01-01T00:00:01.000Z)
+    |> filter(fn: (r) => r["_measurement"] == "aht10")
+    |> filter(fn: (r) => r["_field"] == "pm\"25")
+    |> aggregateWindow(every: 30000ms, fn: mean, createEmpty: false)
+    |> sort(columns: ["_time"])
+    |> keep(columns: ["_time", "_value"])"#
+        );
+    }
+
+    #[test]
+    fn raw_metric_is_not_aggregated() {
+        assert_eq!(
+            raw_metric(Source::RAW, "temperature", 0, 1000).unwrap(),
+            r#"from(bucket: "Temperature")
+    |> range(start: 1970-01-01T00:00:00.000Z, stop: 1970-01-01T00:00:01.000Z)
+    |> filter(fn: (r) => r["_measurement"] == "aht10")
+    |> filter(fn: (r) => r["_field"] == "temperature")
+    |> sort(columns: ["_time"])
+    |> keep(columns: ["_time", "_value"])"#
+        );
+    }
+
+    #[test]
+    fn band_pivots_aggregates() {
+        let query = band(
+            [Source::RAW; 3],
+            "temperature",
+            0,
+            1000,
+            30000,
+            Some(Limit::First(5)),
+        )
+        .unwrap();
+
+        assert_eq!(query.matches("aggregateWindow(").count(), 3);
+        assert!(query.starts_with("union(tables: [\n"));
+        assert!(query.ends_with(
+            r#"])
+    |> group()
+    |> pivot(rowKey: ["_time"], columnKey: ["_field"], valueColumn: "_value")
+    |> sort(columns: ["_time"])
+    |> limit(n: 5)
+    |> keep(columns: ["_time", "min", "mean", "max"])"#
+        ));
+    }
+
+    #[test]
+    fn band_reads_extremes_from_sources() {
+        let source = |measurement| Source {
+            bucket: "rollups",
+            measurement,
+        };
+        let query = band(
+            [
+                source("aht10_1h_min"),
+                source("aht10_1h"),
+                source("aht10_1h_max"),
+            ],
+            "co2",
+            0,
+            1000,
+            3600000,
+            None,
+        )
+        .unwrap();
+
+        for (measurement, aggregate) in [
+            ("aht10_1h_min", "min"),
+            ("aht10_1h", "mean"),
+            ("aht10_1h_max", "max"),
+        ] {
+            assert!(query.contains(&format!(
+                r#"r["_measurement"] == "{measurement}")
+    |> filter(fn: (r) => r["_field"] == "co2")
+    |> aggregateWindow(every: 3600000ms, fn: {aggregate}, createEmpty: false)"#
+            )));
+        }
+    }
+
+    #[test]
+    fn time_weighted_avg_of_field() {
+        assert_eq!(
+            time_weighted_avg(Source::RAW, "co2", 0, 1000).unwrap(),
+            r#"from(bucket: "Temperature")
+    |> range(start: 1970-01-01T00:00:00.000Z, stop: 1970-01-01T00:00:01.000Z)
+    |> filter(fn: (r) => r["_measurement"] == "aht10")
+    |> filter(fn: (r) => r["_field"] == "co2")
+    |> timeWeightedAvg(unit: 1s)
+    |> keep(columns: ["_value"])"#
+        );
+    }
+
+    #[test]
+    fn annotations_in_range() {
+        let source = Source {
+            bucket: BUCKET,
+            measurement: "annotations",
+        };
+
+        assert_eq!(
+            annotations(source, 0, 1000).unwrap(),
+            r#"from(bucket: "Temperature")
+    |> range(start: 1970-01-01T00:00:00.000Z, stop: 1970-01-01T00:00:01.000Z)
+    |> filter(fn: (r) => r["_measurement"] == "annotations")
+    |> filter(fn: (r) => r["_field"] == "text")
+    |> sort(columns: ["_time"])
+    |> keep(columns: ["_time", "_value"])"#
+        );
+    }
+
+    #[test]
+    fn records_of_all_fields() {
+        assert_eq!(
+            records(Source::RAW).unwrap(),
+            r#"union(tables: [
+from(bucket: "Temperature")
+    |> range(start: 1970-01-01T00:00:00.000Z)
+    |> filter(fn: (r) => r["_measurement"] == "aht10")
+    |> min()
+    |> set(key: "record", value: "min"),
+from(bucket: "Temperature")
+    |> range(start: 1970-01-01T00:00:00.000Z)
+    |> filter(fn: (r) => r["_measurement"] == "aht10")
+    |> max()
+    |> set(key: "record", value: "max")
+])
+    |> keep(columns: ["_time", "_field", "_value", "record"])"#
+        );
+    }
+
+    #[test]
+    fn latest_point() {
+        assert_eq!(
+            latest(Source::RAW, FluxTime::Ago(86400000)).unwrap(),
+            r#"from(bucket: "Temperature")
+    |> range(start: -86400000ms)
+    |> filter(fn: (r) => r["_measurement"] == "aht10")
+    |> last()"#
+        );
+    }
+
+    #[test]
+    fn rollup_writes_to_bucket() {
+        assert_eq!(
+            rollup(
+                Source::RAW,
+                "rollups",
+                Resolution::Daily,
+                FluxTime::Ago(172800000),
+                None
+            )
+            .unwrap(),
+            r#"union(tables: [
+from(bucket: "Temperature")
+    |> range(start: -172800000ms)
+    |> filter(fn: (r) => r["_measurement"] == "aht10")
+    |> aggregateWindow(every: 86400000ms, fn: min, createEmpty: false)
+    |> set(key: "_measurement", value: "aht10_1d_min"),
+from(bucket: "Temperature")
+    |> range(start: -172800000ms)
+    |> filter(fn: (r) => r["_measurement"] == "aht10")
+    |> aggregateWindow(every: 86400000ms, fn: mean, createEmpty: false)
+    |> set(key: "_measurement", value: "aht10_1d"),
+from(bucket: "Temperature")
+    |> range(start: -172800000ms)
+    |> filter(fn: (r) => r["_measurement"] == "aht10")
+    |> aggregateWindow(every: 86400000ms, fn: max, createEmpty: false)
+    |> set(key: "_measurement", value: "aht10_1d_max")
+])
+    |> to(bucket: "rollups")
+    |> count()"#
+        );
+    }
+}
@@ -0,0 +1,141 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use crate::{
+    flux::{self, Aggregate, FluxTime},
+    queries::{self, Source},
+    MEASUREMENT,
+};
+
+/// How long InfluxDB tasks wait for late points after a window ends.
+const TASK_OFFSET: &str = "5m";
+
+/// The resolution of a rollup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    Hourly,
+    Daily,
+}
+
+impl Resolution {
+    /// From fine to coarse.
+    pub const ALL: [Resolution; 2] = [Resolution::Hourly, Resolution::Daily];
+
+    /// The length of a window, in milliseconds.
+    pub fn window_ms(&self) -> u64 {
+        match self {
+            Resolution::Hourly => 60 * 60 * 1000,
+            Resolution::Daily => 24 * 60 * 60 * 1000,
+        }
+    }
+
+    fn suffix(&self) -> &'static str {
+        match self {
+            Resolution::Hourly => "1h",
+            Resolution::Daily => "1d",
+        }
+    }
+
+    /// The measurement the rollup is stored as.
+    pub fn measurement(&self) -> String {
+        format!("{MEASUREMENT}_{}", self.suffix())
+    }
+
+    /// The measurement the `aggregate` of every window is stored as. The
+    /// minimums and maximums are stored next to the means, so that bands
+    /// keep the extremes of the raw points.
+    pub(crate) fn measurement_of(&self, aggregate: Aggregate) -> String {
+        match aggregate {
+            Aggregate::Mean => self.measurement(),
+            aggregate => format!("{}_{}", self.measurement(), aggregate.name()),
+        }
+    }
+}
+
+/// Means, minimums and maximums of [`MEASUREMENT`] per hour and per day, kept in a separate bucket so
+/// that long ranges don't have to aggregate every raw point.
+#[derive(Debug, Clone)]
+pub struct Rollups {
+    bucket: String,
+    raw_bucket: String,
+    ready: Arc<AtomicBool>,
+    timezone: Option<String>,
+}
+
+impl Rollups {
+    /// Roll up the points in `raw_bucket` into `bucket`.
+    pub fn new(bucket: String, raw_bucket: String) -> Self {
+        Self {
+            bucket,
+            raw_bucket,
+            ready: Arc::new(false.into()),
+            timezone: None,
+        }
+    }
+
+    /// Roll up days in the IANA time zone `timezone` instead of UTC, when
+    /// done by InfluxDB tasks.
+    pub fn with_timezone(mut self, timezone: impl Into<String>) -> Self {
+        self.timezone = Some(timezone.into());
+        self
+    }
+
+    pub fn bucket(&self) -> &str {
+        &self.bucket
+    }
+
+    fn raw(&self) -> Source {
+        Source {
+            bucket: &self.raw_bucket,
+            measurement: MEASUREMENT,
+        }
+    }
+
+    /// Mark the rollups as complete, so that queries start using them.
+    pub fn set_ready(&self) {
+        self.ready.store(true, Ordering::Relaxed);
+    }
+
+    /// The name of the InfluxDB task that keeps the `resolution` rollup up to
+    /// date.
+    pub fn task_name(&self, resolution: Resolution) -> String {
+        format!("rollup-{}", resolution.measurement())
+    }
+
+    /// The Flux script of the InfluxDB task that keeps the `resolution`
+    /// rollup up to date. It rolls up the previous and the current window
+    /// after every window.
+    pub fn task_flux(&self, resolution: Resolution) -> Result<String, String> {
+        let window = resolution.window_ms();
+        let query = queries::rollup(
+            self.raw(),
+            &self.bucket,
+            resolution,
+            FluxTime::Ago(2 * window),
+            None,
+        )?;
+
+        let script = format!(
+            "option task = {{name: {}, every: {window}ms, offset: {TASK_OFFSET}}}\n\n{query}",
+            flux::string(&self.task_name(resolution)),
+        );
+
+        // Imports have to come before the task option.
+        Ok(flux::with_location(&script, self.timezone.as_deref()))
+    }
+
+    /// The coarsest rollup that can serve queries with windows of
+    /// `window_ms`, if the rollups are complete.
+    pub fn for_window(&self, window_ms: u64) -> Option<Resolution> {
+        if !self.ready.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        Resolution::ALL
+            .into_iter()
+            .rev()
+            .find(|r| r.window_ms() <= window_ms)
+    }
+}
@@ -0,0 +1,294 @@
+//! Transformations of series that clients can request, applied after they
+//! are fetched.
+
+use std::collections::BTreeMap;
+
+use crate::{DataPoint, Field};
+
+fn round(value: f64) -> f64 {
+    (value * 100.).round() / 100.
+}
+
+/// Smooth every field of `points` with an exponential moving average, where
+/// `alpha` is the weight of the newest value. Missing values are skipped and
+/// stay missing.
+pub fn ema(points: &mut [DataPoint], alpha: f64) {
+    for field in Field::ALL {
+        let mut average: Option<f64> = None;
+
+        for point in points.iter_mut() {
+            let value = field.value_mut(point);
+            if let Some(v) = *value {
+                let smoothed = average.map_or(v, |a| alpha * v + (1. - alpha) * a);
+                average = Some(smoothed);
+                *value = Some(round(smoothed));
+            }
+        }
+    }
+}
+
+/// The values a field can plausibly take, and how much it can plausibly
+/// change from one sample to the next.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Plausible {
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub max_step: Option<f64>,
+}
+
+impl Plausible {
+    fn contains(&self, value: f64) -> bool {
+        self.min.map_or(true, |min| value >= min) && self.max.map_or(true, |max| value <= max)
+    }
+}
+
+/// Drop the values of `points` that are outside the bounds of their field,
+/// or that differ more than the field's maximum step from both neighbouring
+/// values, e.g. a single corrupted reading. A lasting jump is kept. Points
+/// that are left without values are removed.
+pub fn drop_spikes(points: &mut Vec<DataPoint>, plausible: &BTreeMap<Field, Plausible>) {
+    for (&field, bounds) in plausible {
+        for point in points.iter_mut() {
+            let value = field.value_mut(point);
+            if value.map_or(false, |v| !bounds.contains(v)) {
+                *value = None;
+            }
+        }
+
+        let Some(max_step) = bounds.max_step else {
+            continue;
+        };
+
+        let present: Vec<_> = points
+            .iter()
+            .enumerate()
+            .filter_map(|(i, p)| Some((i, field.value(p)?)))
+            .collect();
+
+        let spikes: Vec<_> = present
+            .iter()
+            .enumerate()
+            .filter(|&(n, &(_, value))| {
+                let neighbours = [n.checked_sub(1), Some(n + 1)];
+                let mut neighbours = neighbours
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|m| present.get(m))
+                    .peekable();
+
+                neighbours.peek().is_some()
+                    && neighbours.all(|&(_, other)| (value - other).abs() > max_step)
+            })
+            .map(|(_, &(i, _))| i)
+            .collect();
+
+        for i in spikes {
+            *field.value_mut(&mut points[i]) = None;
+        }
+    }
+
+    points.retain(|p| Field::ALL.iter().any(|f| f.value(p).is_some()));
+}
+
+/// The mean of every field per window of `window_ms`, where windows start at
+/// `origin_ms` plus a multiple of `window_ms`. Means are stamped with the end
+/// of their window like InfluxDB's `aggregateWindow`. `points` must be sorted
+/// by time.
+pub fn mean_windows(points: &[DataPoint], window_ms: u64, origin_ms: i64) -> Vec<DataPoint> {
+    let window_ms = window_ms.max(1) as i64;
+    let index = |time: i64| (time - origin_ms).div_euclid(window_ms);
+    let mut means = Vec::new();
+
+    let mut rest = points;
+    while let Some(first) = rest.first() {
+        let window = index(first.time);
+        let len = rest
+            .iter()
+            .position(|p| index(p.time) != window)
+            .unwrap_or(rest.len());
+        let (current, next) = rest.split_at(len);
+        rest = next;
+
+        let mut mean = DataPoint {
+            time: origin_ms + (window + 1) * window_ms,
+            temperature: None,
+            humidity: None,
+            co2: None,
+        };
+
+        for field in Field::ALL {
+            let values: Vec<_> = current.iter().filter_map(|p| field.value(p)).collect();
+            if !values.is_empty() {
+                let sum: f64 = values.iter().sum();
+                *field.value_mut(&mut mean) = Some(round(sum / values.len() as f64));
+            }
+        }
+
+        means.push(mean);
+    }
+
+    means
+}
+
+/// Fill gaps between two points of up to `max_gap_ms` with points every
+/// `interval_ms`, with the values linearly interpolated for every field that
+/// both points have. Longer gaps are left as they are.
+pub fn interpolate(points: &mut Vec<DataPoint>, interval_ms: u64, max_gap_ms: u64) {
+    let interval_ms = interval_ms.max(1) as i64;
+    let max_gap_ms = max_gap_ms as i64;
+    let mut filled = Vec::with_capacity(points.len());
+
+    for (i, point) in points.iter().enumerate() {
+        filled.push(*point);
+
+        let Some(next) = points.get(i + 1) else {
+            break;
+        };
+        let gap = next.time - point.time;
+        if gap <= interval_ms || gap > max_gap_ms {
+            continue;
+        }
+
+        let mut time = point.time + interval_ms;
+        while time < next.time {
+            let progress = (time - point.time) as f64 / gap as f64;
+            let mut between = DataPoint {
+                time,
+                temperature: None,
+                humidity: None,
+                co2: None,
+            };
+
+            for field in Field::ALL {
+                if let (Some(from), Some(to)) = (field.value(point), field.value(next)) {
+                    *field.value_mut(&mut between) = Some(round(from + (to - from) * progress));
+                }
+            }
+
+            if Field::ALL.iter().any(|f| f.value(&between).is_some()) {
+                filled.push(between);
+            }
+            time += interval_ms;
+        }
+    }
+
+    *points = filled;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temperatures(values: &[f64]) -> Vec<DataPoint> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| DataPoint {
+                time: i as i64 * 1000,
+                temperature: Some(v),
+                humidity: None,
+                co2: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn ema_follows_steps_gradually() {
+        let mut points = temperatures(&[20., 20., 30., 30.]);
+        ema(&mut points, 0.5);
+
+        let smoothed: Vec<_> = points.iter().map(|p| p.temperature.unwrap()).collect();
+        assert_eq!(smoothed, [20., 20., 25., 27.5]);
+        assert!(points.iter().all(|p| p.humidity.is_none()));
+    }
+
+    #[test]
+    fn ema_of_one_keeps_values() {
+        let mut points = temperatures(&[20.5, 19., 22.25]);
+        ema(&mut points, 1.);
+
+        assert_eq!(points[2].temperature, Some(22.25));
+    }
+
+    fn temperature_bounds(plausible: Plausible) -> BTreeMap<Field, Plausible> {
+        BTreeMap::from([(Field::Temperature, plausible)])
+    }
+
+    #[test]
+    fn drop_spikes_out_of_bounds() {
+        let mut points = temperatures(&[20., -300., 21.]);
+        drop_spikes(
+            &mut points,
+            &temperature_bounds(Plausible {
+                min: Some(-40.),
+                max: Some(85.),
+                max_step: None,
+            }),
+        );
+
+        let times: Vec<_> = points.iter().map(|p| p.time).collect();
+        assert_eq!(times, [0, 2000]);
+    }
+
+    #[test]
+    fn drop_spikes_keeps_lasting_jumps() {
+        let mut points = temperatures(&[20., 20.5, 60., 20.5, 21., 30., 30.]);
+        drop_spikes(
+            &mut points,
+            &temperature_bounds(Plausible {
+                max_step: Some(5.),
+                ..Default::default()
+            }),
+        );
+
+        let values: Vec<_> = points.iter().map(|p| p.temperature.unwrap()).collect();
+        assert_eq!(values, [20., 20.5, 20.5, 21., 30., 30.]);
+    }
+
+    #[test]
+    fn mean_windows_end_at_window_boundaries() {
+        let points = temperatures(&[20., 21., 22., 23., 24.]);
+        let means = mean_windows(&points, 2000, 0);
+
+        let means: Vec<_> = means
+            .iter()
+            .map(|p| (p.time, p.temperature.unwrap()))
+            .collect();
+        assert_eq!(means, [(2000, 20.5), (4000, 22.5), (6000, 24.)]);
+    }
+
+    #[test]
+    fn mean_windows_from_origin() {
+        let points = temperatures(&[20., 21., 22., 23., 24.]);
+        let means = mean_windows(&points, 2000, -1000);
+
+        let times: Vec<_> = means.iter().map(|p| p.time).collect();
+        assert_eq!(times, [1000, 3000, 5000]);
+        assert_eq!(means[1].temperature, Some(21.5));
+    }
+
+    #[test]
+    fn interpolate_fills_short_gaps() {
+        let mut points = temperatures(&[20., 21., 0., 0., 24.]);
+        points.drain(2..4);
+        interpolate(&mut points, 1000, 3000);
+
+        let values: Vec<_> = points
+            .iter()
+            .map(|p| (p.time, p.temperature.unwrap()))
+            .collect();
+        assert_eq!(
+            values,
+            [(0, 20.), (1000, 21.), (2000, 22.), (3000, 23.), (4000, 24.)]
+        );
+    }
+
+    #[test]
+    fn interpolate_keeps_long_gaps() {
+        let mut points = temperatures(&[20., 0., 0., 23.]);
+        points.drain(1..3);
+        interpolate(&mut points, 1000, 2000);
+
+        assert_eq!(points.len(), 2);
+    }
+}
@@ -0,0 +1,76 @@
+use async_trait::async_trait;
+
+use crate::{
+    backend::{BackendError, TimeRange, TimeSeriesBackend},
+    prometheus::PrometheusBackend,
+    DataPoint, Field, RangeOptions, MEASUREMENT,
+};
+
+/// A backend querying VictoriaMetrics through its Prometheus compatible API.
+///
+/// Points are written using the InfluxDB line protocol, so fields are stored as
+/// `<measurement>_<field>` metrics (e.g. `aht10_temperature`).
+pub struct VictoriaMetricsBackend {
+    inner: PrometheusBackend,
+}
+
+impl VictoriaMetricsBackend {
+    /// Use the VictoriaMetrics instance at `url` (e.g. `http://localhost:8428`).
+    pub fn new(url: &str) -> Self {
+        let metrics = Field::ALL
+            .into_iter()
+            .map(|f| (f, format!("{MEASUREMENT}_{}", f.name())))
+            .collect();
+
+        Self {
+            inner: PrometheusBackend::new(url, metrics),
+        }
+    }
+}
+
+#[async_trait]
+impl TimeSeriesBackend for VictoriaMetricsBackend {
+    async fn get_current(&mut self) -> Result<Option<DataPoint>, BackendError> {
+        self.inner.get_current().await
+    }
+
+    async fn get_range(
+        &mut self,
+        range: TimeRange,
+        options: &RangeOptions,
+    ) -> Result<Vec<DataPoint>, BackendError> {
+        self.inner.get_range(range, options).await
+    }
+
+    async fn write(&mut self, points: &[DataPoint]) -> Result<(), BackendError> {
+        let body: Vec<_> = points
+            .iter()
+            .filter_map(|point| {
+                let fields: Vec<_> = Field::ALL
+                    .iter()
+                    .filter_map(|f| f.value(point).map(|v| format!("{}={v}", f.name())))
+                    .collect();
+
+                if fields.is_empty() {
+                    return None;
+                }
+
+                Some(format!(
+                    "{MEASUREMENT} {} {}",
+                    fields.join(","),
+                    point.time * 1_000_000
+                ))
+            })
+            .collect();
+
+        self.inner
+            .client
+            .post(format!("{}/write", self.inner.url))
+            .body(body.join("\n"))
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map(|_| ())
+            .map_err(BackendError::from)
+    }
+}
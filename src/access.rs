@@ -0,0 +1,149 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use axum::{
+    body::Body,
+    extract::ConnectInfo,
+    http::{HeaderMap, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use clap::Args;
+use ipnet::IpNet;
+
+#[derive(Args)]
+pub struct AccessOpts {
+    /// Only accept connections from these networks (CIDR). Accepts all
+    /// networks if empty.
+    #[clap(long = "allow", env = "IP_ALLOW", value_delimiter = ',')]
+    pub allow: Vec<IpNet>,
+    /// Reject connections from these networks (CIDR), even if they are
+    /// allowed.
+    #[clap(long = "deny", env = "IP_DENY", value_delimiter = ',')]
+    pub deny: Vec<IpNet>,
+    /// Reverse proxies (CIDR) whose `Forwarded` and `X-Forwarded-For` headers
+    /// are trusted to contain the address of the client.
+    #[clap(long = "trusted-proxy", env = "TRUSTED_PROXIES", value_delimiter = ',')]
+    pub trusted_proxies: Vec<IpNet>,
+}
+
+/// The address of the client, taking trusted proxies into account.
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub IpAddr);
+
+pub struct AccessList {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+    trusted_proxies: Vec<IpNet>,
+}
+
+/// Parse a `for=` value of a `Forwarded` header or an entry of
+/// `X-Forwarded-For`, which may be quoted and include a port.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split(']').next()?.parse().ok();
+    }
+
+    node.parse()
+        .ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|a| a.ip()))
+}
+
+/// The addresses a request was forwarded for, closest to the client first.
+fn forwarded_for(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let forwarded: Vec<_> = headers
+        .get_all("forwarded")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (key, value) = pair.split_once('=')?;
+                key.trim()
+                    .eq_ignore_ascii_case("for")
+                    .then(|| parse_node(value))
+            })
+        })
+        .collect();
+
+    if !forwarded.is_empty() {
+        return forwarded;
+    }
+
+    headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(parse_node)
+        .collect()
+}
+
+impl AccessList {
+    pub fn new(opts: &AccessOpts) -> Arc<Self> {
+        Arc::new(Self {
+            allow: opts.allow.clone(),
+            deny: opts.deny.clone(),
+            trusted_proxies: opts.trusted_proxies.clone(),
+        })
+    }
+
+    fn trusted(&self, ip: &IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|net| net.contains(ip))
+    }
+
+    /// Walk the forwarding chain from the peer towards the client, and stop
+    /// at the first address that is not a trusted proxy.
+    fn client_ip(&self, peer: &SocketAddr, headers: &HeaderMap) -> IpAddr {
+        // The server listens on `[::]`, so IPv4 clients show up as mapped
+        // IPv6 addresses.
+        let mut ip = peer.ip().to_canonical();
+
+        if !self.trusted(&ip) {
+            return ip;
+        }
+
+        for hop in forwarded_for(headers).into_iter().rev() {
+            match hop {
+                Some(hop) => ip = hop.to_canonical(),
+                None => break,
+            }
+
+            if !self.trusted(&ip) {
+                break;
+            }
+        }
+
+        ip
+    }
+
+    fn permits(&self, ip: &IpAddr) -> bool {
+        if self.deny.iter().any(|net| net.contains(ip)) {
+            return false;
+        }
+
+        self.allow.is_empty() || self.allow.iter().any(|net| net.contains(ip))
+    }
+}
+
+pub async fn enforce(
+    Extension(access): Extension<Arc<AccessList>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    mut request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let ip = access.client_ip(&addr, request.headers());
+    tracing::Span::current().record("client", tracing::field::display(ip));
+
+    if !access.permits(&ip) {
+        return (StatusCode::FORBIDDEN, "Forbidden").into_response();
+    }
+
+    request.extensions_mut().insert(ClientIp(ip));
+    next.run(request).await
+}
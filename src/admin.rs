@@ -0,0 +1,413 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+use async_trait::async_trait;
+use axum::{
+    body::Body,
+    extract::{MatchedPath, Query},
+    http::{header, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::Utc;
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+
+use influxdb_temp_client::{
+    Annotation, BackendError, BandPoint, CacheStats, DataPoint, MetricPoint, RangeOptions, Records,
+    Retention, TimeRange, TimeSeriesBackend,
+};
+
+use crate::{
+    problem::{ApiError, ErrorCode},
+    scope::AdminAccess,
+    QueryLimits, SharedState,
+};
+
+#[derive(Debug, Clone, Serialize)]
+struct RecordedError {
+    time: i64,
+    message: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+struct TaskStatus {
+    /// When the task last ran, in milliseconds.
+    last_run: Option<i64>,
+    /// The error of the last run, if it failed.
+    last_error: Option<String>,
+}
+
+/// Runtime statistics of the server.
+pub struct Stats {
+    started: Instant,
+    requests: Mutex<HashMap<String, u64>>,
+    /// Responses that were served from the cache because the backend failed.
+    stale_responses: AtomicU64,
+    last_backend_error: Mutex<Option<RecordedError>>,
+    tasks: Mutex<BTreeMap<&'static str, TaskStatus>>,
+}
+
+impl Stats {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            started: Instant::now(),
+            requests: Mutex::new(HashMap::new()),
+            stale_responses: AtomicU64::new(0),
+            last_backend_error: Mutex::new(None),
+            tasks: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    /// Record a run of the background task `name`.
+    pub fn task_ran(&self, name: &'static str, result: Result<(), &str>) {
+        let mut tasks = self.tasks.lock().unwrap();
+        let status = tasks.entry(name).or_default();
+        status.last_run = Some(Utc::now().timestamp_millis());
+        status.last_error = result.err().map(str::to_string);
+    }
+
+    /// Record the status of the background task `name` that runs elsewhere,
+    /// e.g. in InfluxDB.
+    pub fn task_reported(
+        &self,
+        name: &'static str,
+        last_run: Option<i64>,
+        last_error: Option<String>,
+    ) {
+        let mut tasks = self.tasks.lock().unwrap();
+        let status = tasks.entry(name).or_default();
+        status.last_run = last_run;
+        status.last_error = last_error;
+    }
+
+    /// Remember the error of a failed backend call.
+    fn backend_result<T>(&self, result: Result<T, BackendError>) -> Result<T, BackendError> {
+        match &result {
+            // Expected for some backends, e.g. for raw points.
+            Err(BackendError::Unsupported(_)) => {}
+            Err(e) => {
+                *self.last_backend_error.lock().unwrap() = Some(RecordedError {
+                    time: Utc::now().timestamp_millis(),
+                    message: e.to_string(),
+                });
+            }
+            Ok(_) => {}
+        }
+        result
+    }
+}
+
+/// Records errors of the backend it wraps in [`Stats`].
+pub struct Monitored {
+    inner: Box<dyn TimeSeriesBackend>,
+    stats: Arc<Stats>,
+}
+
+impl Monitored {
+    pub fn new(inner: Box<dyn TimeSeriesBackend>, stats: Arc<Stats>) -> Self {
+        Self { inner, stats }
+    }
+
+    fn record<T>(&self, result: Result<T, BackendError>) -> Result<T, BackendError> {
+        self.stats.backend_result(result)
+    }
+}
+
+#[async_trait]
+impl TimeSeriesBackend for Monitored {
+    async fn get_current(&mut self) -> Result<Option<DataPoint>, BackendError> {
+        let result = self.inner.get_current().await;
+        self.record(result)
+    }
+
+    async fn get_range(
+        &mut self,
+        range: TimeRange,
+        options: &RangeOptions,
+    ) -> Result<Vec<DataPoint>, BackendError> {
+        let result = self.inner.get_range(range, options).await;
+        self.record(result)
+    }
+
+    async fn write(&mut self, points: &[DataPoint]) -> Result<(), BackendError> {
+        let result = self.inner.write(points).await;
+        self.record(result)
+    }
+
+    async fn get_metric_range(
+        &mut self,
+        name: &str,
+        range: TimeRange,
+        options: &RangeOptions,
+    ) -> Result<Vec<MetricPoint>, BackendError> {
+        let result = self.inner.get_metric_range(name, range, options).await;
+        self.record(result)
+    }
+
+    async fn get_metric_band(
+        &mut self,
+        name: &str,
+        range: TimeRange,
+        options: &RangeOptions,
+    ) -> Result<Vec<BandPoint>, BackendError> {
+        let result = self.inner.get_metric_band(name, range, options).await;
+        self.record(result)
+    }
+
+    async fn get_time_weighted_avg(
+        &mut self,
+        name: &str,
+        range: TimeRange,
+    ) -> Result<Option<f64>, BackendError> {
+        let result = self.inner.get_time_weighted_avg(name, range).await;
+        self.record(result)
+    }
+
+    async fn get_cached_range(
+        &mut self,
+        range: TimeRange,
+        options: &RangeOptions,
+    ) -> Result<Option<Vec<DataPoint>>, BackendError> {
+        self.inner.get_cached_range(range, options).await
+    }
+
+    async fn get_retention(&mut self) -> Result<Retention, BackendError> {
+        let result = self.inner.get_retention().await;
+        self.record(result)
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.inner.cache_stats()
+    }
+
+    async fn clear_cache(&mut self, range: Option<(i64, i64)>) -> Result<u64, BackendError> {
+        self.inner.clear_cache(range).await
+    }
+
+    async fn get_annotations(&mut self, range: TimeRange) -> Result<Vec<Annotation>, BackendError> {
+        let result = self.inner.get_annotations(range).await;
+        self.record(result)
+    }
+
+    async fn write_annotation(&mut self, annotation: &Annotation) -> Result<(), BackendError> {
+        let result = self.inner.write_annotation(annotation).await;
+        self.record(result)
+    }
+
+    async fn get_raw_metric(
+        &mut self,
+        name: &str,
+        start_ms: i64,
+        stop_ms: i64,
+    ) -> Result<Vec<MetricPoint>, BackendError> {
+        let result = self.inner.get_raw_metric(name, start_ms, stop_ms).await;
+        self.record(result)
+    }
+
+    async fn delete_range(&mut self, start_ms: i64, stop_ms: i64) -> Result<(), BackendError> {
+        let result = self.inner.delete_range(start_ms, stop_ms).await;
+        self.record(result)
+    }
+
+    fn scan_records(&self) -> BoxFuture<'static, Result<Vec<Records>, BackendError>> {
+        let (scan, stats) = (self.inner.scan_records(), self.stats.clone());
+        Box::pin(async move { stats.backend_result(scan.await) })
+    }
+}
+
+/// Count requests per route, and those that were answered with stale data.
+/// Requests that do not match a route are counted as `fallback`.
+pub async fn count_requests(
+    Extension(stats): Extension<Arc<Stats>>,
+    matched_path: Option<MatchedPath>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let route = matched_path
+        .as_ref()
+        .map(|p| p.as_str())
+        .unwrap_or("fallback")
+        .to_string();
+
+    *stats.requests.lock().unwrap().entry(route).or_default() += 1;
+
+    let response = next.run(request).await;
+
+    // Set by `mark_stale`.
+    if response.headers().contains_key(header::WARNING) {
+        stats.stale_responses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    response
+}
+
+#[derive(Debug, Serialize)]
+struct StatsResponse {
+    uptime_s: u64,
+    requests: BTreeMap<String, u64>,
+    cache: Option<CacheStats>,
+    stale_responses: u64,
+    /// Range queries that joined an identical one instead of querying the
+    /// backend.
+    coalesced_queries: u64,
+    last_backend_error: Option<RecordedError>,
+    tasks: BTreeMap<&'static str, TaskStatus>,
+}
+
+pub async fn stats(
+    Extension(stats): Extension<Arc<Stats>>,
+    Extension(client): Extension<SharedState>,
+    Extension(limits): Extension<QueryLimits>,
+    _: AdminAccess,
+) -> impl IntoResponse {
+    let cache = client.lock().await.cache_stats();
+
+    let response = StatsResponse {
+        uptime_s: stats.started.elapsed().as_secs(),
+        requests: stats
+            .requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(k, v)| (k.clone(), *v))
+            .collect(),
+        cache,
+        stale_responses: stats.stale_responses.load(Ordering::Relaxed),
+        coalesced_queries: limits.inflight.joined(),
+        last_backend_error: stats.last_backend_error.lock().unwrap().clone(),
+        tasks: stats.tasks.lock().unwrap().clone(),
+    };
+
+    Ok::<_, ApiError>(Json(response))
+}
+
+/// Counters of the request path and caches in the Prometheus text format.
+pub async fn metrics(
+    Extension(stats): Extension<Arc<Stats>>,
+    Extension(client): Extension<SharedState>,
+    Extension(limits): Extension<QueryLimits>,
+    _: AdminAccess,
+) -> impl IntoResponse {
+    let mut out = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, u64)]| {
+        let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} {kind}");
+        for (labels, value) in samples {
+            let _ = writeln!(out, "{name}{labels} {value}");
+        }
+    };
+
+    let requests: Vec<_> = stats
+        .requests
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(route, count)| (format!("{{route={route:?}}}"), *count))
+        .collect();
+    metric(
+        "temp_http_requests_total",
+        "counter",
+        "Requests per route.",
+        &requests,
+    );
+    metric(
+        "temp_stale_responses_total",
+        "counter",
+        "Responses served from the cache because the backend failed.",
+        &[(String::new(), stats.stale_responses.load(Ordering::Relaxed))],
+    );
+    metric(
+        "temp_coalesced_queries_total",
+        "counter",
+        "Range queries that joined an identical running query.",
+        &[(String::new(), limits.inflight.joined())],
+    );
+
+    if let Some(cache) = client.lock().await.cache_stats() {
+        let counters = [
+            (
+                "hits",
+                "Range queries (partially) served from the cache.",
+                cache.hits,
+            ),
+            (
+                "misses",
+                "Range queries fetched completely from the backend.",
+                cache.misses,
+            ),
+            (
+                "stale_serves",
+                "Range queries served expired points while refreshing.",
+                cache.stale_serves,
+            ),
+            (
+                "evictions",
+                "Points dropped to bound the cache size.",
+                cache.evictions,
+            ),
+        ];
+        for (name, help, value) in counters {
+            metric(
+                &format!("temp_cache_{name}_total"),
+                "counter",
+                help,
+                &[(String::new(), value)],
+            );
+        }
+
+        metric(
+            "temp_cache_entries",
+            "gauge",
+            "Cached points.",
+            &[(String::new(), cache.entries)],
+        );
+        metric(
+            "temp_cache_memory_entries",
+            "gauge",
+            "Cached points held in memory.",
+            &[(String::new(), cache.memory_entries)],
+        );
+    }
+
+    Ok::<_, ApiError>(([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], out))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ClearQuery {
+    start: Option<i64>,
+    stop: Option<i64>,
+}
+
+/// Drop cached aggregates, e.g. after data was backfilled or corrected.
+pub async fn clear_cache(
+    Query(query): Query<ClearQuery>,
+    Extension(client): Extension<SharedState>,
+    _: AdminAccess,
+) -> impl IntoResponse {
+    let range = match (query.start, query.stop) {
+        (None, None) => None,
+        (Some(start), Some(stop)) if start < stop => Some((start, stop)),
+        _ => {
+            return Err(ApiError::new(
+                ErrorCode::BadTimeRange,
+                "Either both or neither of start and stop must be given, and start must be before stop.",
+            ))
+        }
+    };
+
+    match client.lock().await.clear_cache(range).await {
+        Ok(cleared) => {
+            println!("Cleared {cleared} cached point(s)");
+            Ok(Json(serde_json::json!({ "cleared": cleared })))
+        }
+        Err(e) => Err(ApiError::backend(e)),
+    }
+}
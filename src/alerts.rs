@@ -0,0 +1,562 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use axum::{extract::Query, http::header, response::IntoResponse, Extension, Json};
+use chrono::{TimeZone, Utc};
+use duration_string::DurationString;
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+
+use influxdb_temp_client::{DataPoint, Field};
+
+use crate::{
+    live::Latest,
+    notify::{self, ChannelConfig, Notification, Notifier},
+    problem::{ApiError, ErrorCode},
+    scope::ReadAccess,
+    summary::{self, SummaryConfig},
+    HttpPassword, SharedState,
+};
+
+/// How often the rules file is checked for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Comparison {
+    #[serde(rename = ">")]
+    Above,
+    #[serde(rename = ">=")]
+    AtLeast,
+    #[serde(rename = "<")]
+    Below,
+    #[serde(rename = "<=")]
+    AtMost,
+}
+
+impl Comparison {
+    fn holds(&self, value: f64, threshold: f64) -> bool {
+        match self {
+            Comparison::Above => value > threshold,
+            Comparison::AtLeast => value >= threshold,
+            Comparison::Below => value < threshold,
+            Comparison::AtMost => value <= threshold,
+        }
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Comparison::Above => ">",
+            Comparison::AtLeast => ">=",
+            Comparison::Below => "<",
+            Comparison::AtMost => "<=",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    #[default]
+    Warning,
+    Critical,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Critical => "critical",
+        })
+    }
+}
+
+/// A rule as configured in the alerts file:
+///
+/// ```toml
+/// [[rule]]
+/// name = "Bedroom too humid"
+/// field = "humidity"
+/// comparison = ">"
+/// threshold = 70
+/// clear_threshold = 65
+/// duration = "10m"
+/// cooldown = "1h"
+/// severity = "warning"
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct Rule {
+    pub name: String,
+    pub field: Field,
+    pub comparison: Comparison,
+    pub threshold: f64,
+    /// A firing alert only resolves once the value no longer passes this
+    /// threshold. Defaults to `threshold`.
+    #[serde(default)]
+    pub clear_threshold: Option<f64>,
+    /// How long the condition has to hold before the alert fires.
+    #[serde(default)]
+    pub duration: Option<DurationString>,
+    /// Minimum time between two firings of the alert.
+    #[serde(default)]
+    pub cooldown: Option<DurationString>,
+    #[serde(default)]
+    pub severity: Severity,
+}
+
+fn millis(duration: Option<DurationString>) -> i64 {
+    duration
+        .map(|d| Duration::from(d).as_millis() as i64)
+        .unwrap_or(0)
+}
+
+impl Rule {
+    fn clear_threshold(&self) -> f64 {
+        self.clear_threshold.unwrap_or(self.threshold)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Config {
+    #[serde(default, rename = "rule")]
+    rules: Vec<Rule>,
+    summary: Option<SummaryConfig>,
+    #[serde(flatten)]
+    channels: ChannelConfig,
+}
+
+fn load(path: &Path) -> Result<Config, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+
+    toml::from_str(&text).map_err(|e| format!("Could not parse {}: {e}", path.display()))
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[derive(Debug, Default)]
+struct RuleState {
+    /// Time of the first point of the current streak of points that match
+    /// the rule.
+    pending_since: Option<i64>,
+    firing: bool,
+    last_fired: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Fired,
+    Resolved,
+}
+
+/// A rule that started or stopped firing.
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub kind: EventKind,
+    pub rule: Rule,
+    pub value: f64,
+    /// Time of the point that triggered the event, in milliseconds.
+    pub time: i64,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rule = &self.rule;
+        let state = match self.kind {
+            EventKind::Fired => "fired",
+            EventKind::Resolved => "resolved",
+        };
+
+        write!(
+            f,
+            "[{}] {} {state}: {} is {:.02} ({} {})",
+            rule.severity,
+            rule.name,
+            rule.field.name(),
+            self.value,
+            rule.comparison,
+            rule.threshold
+        )
+    }
+}
+
+struct Engine {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    rules: Vec<Rule>,
+    states: HashMap<String, RuleState>,
+    notifiers: Arc<Vec<Box<dyn Notifier>>>,
+    /// The summary to send, and when it is next due.
+    summary: Option<(SummaryConfig, chrono::DateTime<Utc>)>,
+}
+
+impl Engine {
+    /// Reload the rules if the file changed. Keeps the current rules if the
+    /// new ones are invalid.
+    fn reload(&mut self) {
+        let modified = modified(&self.path);
+        if modified == self.modified {
+            return;
+        }
+        self.modified = modified;
+
+        let config = load(&self.path).and_then(|config| {
+            let notifiers = config.channels.build()?;
+            let summary = match &config.summary {
+                Some(s) => Some((s.clone(), s.next_after(Utc::now())?)),
+                None => None,
+            };
+            Ok((config, notifiers, summary))
+        });
+
+        match config {
+            Ok((config, notifiers, summary)) => {
+                println!(
+                    "Loaded {} alert rule(s) and {} notification channel(s)",
+                    config.rules.len(),
+                    notifiers.len()
+                );
+                self.notifiers = Arc::new(notifiers);
+                self.summary = summary;
+                self.states
+                    .retain(|name, _| config.rules.iter().any(|r| &r.name == name));
+                self.rules = config.rules;
+            }
+            Err(e) => eprintln!("{e}"),
+        }
+    }
+
+    fn evaluate(&mut self, point: &DataPoint) -> Vec<Event> {
+        let mut events = Vec::new();
+
+        for rule in &self.rules {
+            let Some(value) = rule.field.value(point) else {
+                continue;
+            };
+
+            let state = self.states.entry(rule.name.clone()).or_default();
+
+            if state.firing {
+                if !rule.comparison.holds(value, rule.clear_threshold()) {
+                    state.firing = false;
+                    state.pending_since = None;
+                    events.push(Event {
+                        kind: EventKind::Resolved,
+                        rule: rule.clone(),
+                        value,
+                        time: point.time,
+                    });
+                }
+            } else if rule.comparison.holds(value, rule.threshold) {
+                let since = *state.pending_since.get_or_insert(point.time);
+                let cooled_down = state
+                    .last_fired
+                    .map_or(true, |t| point.time - t >= millis(rule.cooldown));
+
+                if point.time - since >= millis(rule.duration) && cooled_down {
+                    state.firing = true;
+                    state.last_fired = Some(point.time);
+                    events.push(Event {
+                        kind: EventKind::Fired,
+                        rule: rule.clone(),
+                        value,
+                        time: point.time,
+                    });
+                }
+            } else {
+                state.pending_since = None;
+            }
+        }
+
+        events
+    }
+}
+
+/// Evaluate the rules in `path` against every new point, reloading them when
+/// the file changes. Also sends the summary configured in `path`.
+pub fn spawn(path: PathBuf, mut latest: Latest, history: Arc<History>, client: SharedState) {
+    tokio::spawn(async move {
+        let mut engine = Engine {
+            path,
+            modified: None,
+            rules: Vec::new(),
+            states: HashMap::new(),
+            notifiers: Arc::new(Vec::new()),
+            summary: None,
+        };
+
+        let mut reload = tokio::time::interval(RELOAD_INTERVAL);
+
+        loop {
+            tokio::select! {
+                _ = reload.tick() => {
+                    engine.reload();
+
+                    if let Some((config, next)) = &mut engine.summary {
+                        if Utc::now() >= *next {
+                            summary::send(
+                                client.clone(),
+                                history.clone(),
+                                engine.notifiers.clone(),
+                                config.clone(),
+                                *next,
+                            );
+                            *next = config.next_after(Utc::now()).unwrap_or(*next);
+                        }
+                    }
+                }
+                changed = latest.changed() => {
+                    if changed.is_err() {
+                        return;
+                    }
+
+                    let Some(point) = *latest.borrow_and_update() else {
+                        continue;
+                    };
+
+                    for event in engine.evaluate(&point) {
+                        println!("Alert {event}");
+                        notify::dispatch(&engine.notifiers, Notification::from(&event));
+
+                        if let Err(e) = history.record(&event) {
+                            eprintln!("Could not store alert: {e}");
+                        }
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// A stored alert.
+#[derive(Debug, Clone, Serialize)]
+pub struct Record {
+    pub rule: String,
+    pub field: String,
+    pub severity: String,
+    pub value: f64,
+    pub fired_at: i64,
+    pub resolved_at: Option<i64>,
+}
+
+/// Fired alerts, stored in SQLite.
+pub struct History {
+    db: Mutex<Connection>,
+}
+
+fn sql_err(e: rusqlite::Error) -> String {
+    format!("Alert history error: {e}")
+}
+
+impl History {
+    /// Open the history at `path`, or keep it in memory if no path is given.
+    pub fn open(path: Option<&Path>) -> Result<Arc<Self>, String> {
+        let db = match path {
+            Some(path) => Connection::open(path),
+            None => Connection::open_in_memory(),
+        }
+        .map_err(sql_err)?;
+
+        db.execute_batch(
+            "CREATE TABLE IF NOT EXISTS alerts (
+                rule TEXT NOT NULL,
+                field TEXT NOT NULL,
+                severity TEXT NOT NULL,
+                value REAL NOT NULL,
+                fired_at INTEGER NOT NULL,
+                resolved_at INTEGER
+            );
+            CREATE INDEX IF NOT EXISTS alerts_fired_at ON alerts (fired_at);",
+        )
+        .map_err(sql_err)?;
+
+        Ok(Arc::new(Self { db: Mutex::new(db) }))
+    }
+
+    fn record(&self, event: &Event) -> Result<(), String> {
+        let db = self.db.lock().unwrap();
+
+        match event.kind {
+            EventKind::Fired => db.execute(
+                "INSERT INTO alerts (rule, field, severity, value, fired_at)
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    event.rule.name,
+                    event.rule.field.name(),
+                    event.rule.severity.to_string(),
+                    event.value,
+                    event.time
+                ],
+            ),
+            EventKind::Resolved => db.execute(
+                "UPDATE alerts SET resolved_at = ?1 WHERE rule = ?2 AND resolved_at IS NULL",
+                params![event.time, event.rule.name],
+            ),
+        }
+        .map_err(sql_err)?;
+
+        Ok(())
+    }
+
+    /// Alerts that fired since `since` (in milliseconds), newest first.
+    pub fn since(&self, since: i64) -> Result<Vec<Record>, String> {
+        let db = self.db.lock().unwrap();
+        let mut statement = db
+            .prepare_cached(
+                "SELECT rule, field, severity, value, fired_at, resolved_at FROM alerts
+                WHERE fired_at >= ?1 ORDER BY fired_at DESC",
+            )
+            .map_err(sql_err)?;
+
+        let rows = statement
+            .query_map(params![since], |row| {
+                Ok(Record {
+                    rule: row.get(0)?,
+                    field: row.get(1)?,
+                    severity: row.get(2)?,
+                    value: row.get(3)?,
+                    fired_at: row.get(4)?,
+                    resolved_at: row.get(5)?,
+                })
+            })
+            .map_err(sql_err)?;
+
+        rows.collect::<Result<_, _>>().map_err(sql_err)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HistoryQuery {
+    range: Option<String>,
+}
+
+impl HistoryQuery {
+    fn since(&self) -> Result<i64, ApiError> {
+        let range = match DurationString::from_str(self.range.as_deref().unwrap_or("7d")) {
+            Ok(v) => Duration::from(v),
+            Err(e) => {
+                return Err(ApiError::new(
+                    ErrorCode::BadRangeSyntax,
+                    format!("Invalid range ({e})."),
+                ))
+            }
+        };
+
+        i64::try_from(range.as_millis())
+            .ok()
+            .and_then(|ms| Utc::now().timestamp_millis().checked_sub(ms))
+            .ok_or_else(|| {
+                ApiError::new(
+                    ErrorCode::BadTimeRange,
+                    format!("Range {range:?} is too long."),
+                )
+            })
+    }
+}
+
+pub async fn history(
+    Query(query): Query<HistoryQuery>,
+    Extension(history): Extension<Arc<History>>,
+    _: ReadAccess,
+) -> impl IntoResponse {
+    match history.since(query.since()?) {
+        Ok(records) => Ok(Json(records)),
+        Err(e) => Err(ApiError::new(ErrorCode::Internal, e)),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FeedQuery {
+    /// Feed readers generally can't send a bearer token, so it is passed in
+    /// the query instead.
+    token: String,
+    #[serde(flatten)]
+    history: HistoryQuery,
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn rfc3339(ms: i64) -> String {
+    Utc.timestamp_millis_opt(ms)
+        .single()
+        .map(|t| t.to_rfc3339())
+        .unwrap_or_default()
+}
+
+/// The alert history as an Atom feed, newest first.
+pub async fn feed(
+    Query(query): Query<FeedQuery>,
+    Extension(history): Extension<Arc<History>>,
+    Extension(password): Extension<HttpPassword>,
+) -> impl IntoResponse {
+    if !password.accepts(&query.token) {
+        return Err(ApiError::new(ErrorCode::Unauthorized, "Invalid password"));
+    }
+
+    let records = history
+        .since(query.history.since()?)
+        .map_err(|e| ApiError::new(ErrorCode::Internal, e))?;
+
+    let updated = records
+        .iter()
+        .map(|r| r.resolved_at.unwrap_or(r.fired_at))
+        .max()
+        .unwrap_or_else(|| Utc::now().timestamp_millis());
+
+    let mut feed = format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+<title>Alerts</title>
+<id>urn:influxdb-temp-server:alerts</id>
+<updated>{}</updated>
+"#,
+        rfc3339(updated)
+    );
+
+    for record in &records {
+        let state = match record.resolved_at {
+            Some(resolved_at) => format!("resolved at {}", rfc3339(resolved_at)),
+            None => "firing".to_string(),
+        };
+
+        feed += &format!(
+            "<entry>
+<title>[{}] {} ({})</title>
+<id>urn:influxdb-temp-server:alert:{}:{}</id>
+<updated>{}</updated>
+<author><name>influxdb-temp-server</name></author>
+<content type=\"text\">{} was {:.02}, {}</content>
+</entry>
+",
+            escape(&record.severity),
+            escape(&record.rule),
+            escape(&state),
+            record.fired_at,
+            escape(&record.rule.replace(' ', "-")),
+            rfc3339(record.resolved_at.unwrap_or(record.fired_at)),
+            escape(&record.field),
+            record.value,
+            escape(&state),
+        );
+    }
+
+    feed += "</feed>\n";
+
+    Ok(([(header::CONTENT_TYPE, "application/atom+xml")], feed))
+}
@@ -0,0 +1,464 @@
+use axum::{
+    extract::{Path, Query},
+    response::IntoResponse,
+    Extension, Json,
+};
+use chrono::{TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+use influxdb_temp_client::{DataPoint, Field, MetricPoint, RangeOptions, TimeRange};
+
+use crate::{
+    calendar, fetch, get_range, problem::ApiError, scope::ReadAccess, Metrics, QueryLimits,
+    SharedState,
+};
+
+#[derive(Debug, Serialize)]
+struct TimeWeightedAvg {
+    field: String,
+    start: i64,
+    stop: i64,
+    twa: Option<f64>,
+}
+
+/// The time-weighted average of a field, which unlike the mean of the
+/// points is not skewed by irregular sample spacing.
+pub async fn twa(
+    Path((field, path)): Path<(String, String)>,
+    Extension(metrics): Extension<Metrics>,
+    Extension(client): Extension<SharedState>,
+    Extension(limits): Extension<QueryLimits>,
+    _: ReadAccess,
+) -> impl IntoResponse {
+    metrics.check(&field)?;
+
+    let range = TimeRange::Span(get_range(&path)?);
+    limits.check_range(&range)?;
+
+    let twa = client
+        .lock()
+        .await
+        .get_time_weighted_avg(&field, range)
+        .await
+        .map_err(ApiError::backend)?;
+
+    let (start, stop) = range.bounds();
+
+    Ok::<_, ApiError>(Json(TimeWeightedAvg {
+        field,
+        start,
+        stop,
+        twa: twa.map(round),
+    }))
+}
+
+const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+
+#[derive(Debug, Deserialize)]
+pub struct DegreeDayQuery {
+    /// Base temperature in °C. Defaults to 18.
+    base: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+struct DegreeDay {
+    date: String,
+    mean: f64,
+    heating: f64,
+    cooling: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct DegreeDays {
+    base: f64,
+    heating: f64,
+    cooling: f64,
+    days: Vec<DegreeDay>,
+}
+
+fn round(value: f64) -> f64 {
+    (value * 100.).round() / 100.
+}
+
+/// Heating and cooling degree days per day in the configured time zone, from
+/// the daily mean temperature.
+pub async fn degree_days(
+    Path(path): Path<String>,
+    Query(query): Query<DegreeDayQuery>,
+    Extension(client): Extension<SharedState>,
+    Extension(limits): Extension<QueryLimits>,
+    _: ReadAccess,
+) -> impl IntoResponse {
+    let base = query.base.unwrap_or(18.);
+    let range = TimeRange::Span(get_range(&path)?);
+    limits.check_range(&range)?;
+
+    let options = RangeOptions {
+        window_ms: Some(DAY_MS as u64),
+        ..Default::default()
+    };
+
+    let means = client
+        .lock()
+        .await
+        .get_metric_range(Field::Temperature.name(), range, &options)
+        .await
+        .map_err(ApiError::backend)?;
+
+    // Windows are labeled with their end time.
+    let days: Vec<_> = means
+        .iter()
+        .filter_map(|MetricPoint(time, mean)| {
+            let date = Utc
+                .timestamp_millis_opt(time - 1)
+                .single()?
+                .with_timezone(&calendar::timezone())
+                .date_naive();
+            Some(DegreeDay {
+                date: date.to_string(),
+                mean: round(*mean),
+                heating: round((base - mean).max(0.)),
+                cooling: round((mean - base).max(0.)),
+            })
+        })
+        .collect();
+
+    Ok::<_, ApiError>(Json(DegreeDays {
+        base,
+        heating: round(days.iter().map(|d| d.heating).sum()),
+        cooling: round(days.iter().map(|d| d.cooling).sum()),
+        days,
+    }))
+}
+
+/// Points further apart than this end a mold risk period, as the sensor was
+/// probably offline.
+const MAX_GAP_MS: i64 = 60 * 60 * 1000;
+
+/// The window the analyses query with. Default windows grow with the range,
+/// and would soon be wider than the gaps and periods they look for.
+const ANALYSIS_WINDOW_MS: u64 = 60 * 1000;
+
+/// The gap between consecutive points of `window_ms` that means the sensor
+/// was offline. Wider than [`MAX_GAP_MS`] if the query limits widened the
+/// window.
+fn max_gap(window_ms: u64) -> i64 {
+    MAX_GAP_MS.max(2 * window_ms as i64)
+}
+
+/// The relative humidity above which mold can grow at `temperature`, after
+/// the VTT model. `None` if it is too cold for growth.
+fn critical_humidity(temperature: f64) -> Option<f64> {
+    if temperature <= 0. {
+        None
+    } else if temperature <= 20. {
+        let t = temperature;
+        Some(-0.00267 * t.powi(3) + 0.160 * t.powi(2) - 3.13 * t + 100.)
+    } else {
+        Some(80.)
+    }
+}
+
+fn mold_risk(point: &DataPoint) -> bool {
+    match (point.temperature, point.humidity) {
+        (Some(t), Some(rh)) => critical_humidity(t).is_some_and(|c| rh >= c),
+        _ => false,
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MoldRiskQuery {
+    /// How long conditions have to persist to count. Defaults to 6 hours.
+    duration: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+struct Period {
+    start: i64,
+    stop: i64,
+}
+
+#[derive(Debug, Serialize)]
+struct DayScore {
+    date: String,
+    /// Percentage of the day that was part of a risk period.
+    score: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct MoldRisk {
+    periods: Vec<Period>,
+    days: Vec<DayScore>,
+}
+
+/// Periods in which temperature and humidity allowed mold growth for at least
+/// the given duration, and a score per (UTC) day.
+pub async fn mold_risk_index(
+    Path(path): Path<String>,
+    Query(query): Query<MoldRiskQuery>,
+    Extension(client): Extension<SharedState>,
+    Extension(limits): Extension<QueryLimits>,
+    _: ReadAccess,
+) -> impl IntoResponse {
+    let min_duration = get_range(query.duration.as_deref().unwrap_or("6h"))?.as_millis() as i64;
+    let range = TimeRange::Span(get_range(&path)?);
+    let options = RangeOptions {
+        fields: vec![Field::Temperature, Field::Humidity],
+        window_ms: Some(ANALYSIS_WINDOW_MS),
+        ..Default::default()
+    };
+
+    let fetched = fetch(&client, &limits, range, &options).await?;
+    let (points, max_gap) = (fetched.points, max_gap(fetched.window_ms));
+
+    let mut periods: Vec<Period> = Vec::new();
+    let mut current: Option<Period> = None;
+
+    for point in &points {
+        let extends = current.is_some_and(|p| point.time - p.stop <= max_gap);
+
+        match (mold_risk(point), extends) {
+            (true, true) => current.as_mut().unwrap().stop = point.time,
+            (at_risk, _) => {
+                periods.extend(current.take());
+                if at_risk {
+                    current = Some(Period {
+                        start: point.time,
+                        stop: point.time,
+                    });
+                }
+            }
+        }
+    }
+    periods.extend(current);
+    periods.retain(|p| p.stop - p.start >= min_duration);
+
+    let (start, stop) = range.bounds();
+    let mut days = Vec::new();
+    let mut day = start - start.rem_euclid(DAY_MS);
+
+    while day < stop {
+        let at_risk: i64 = periods
+            .iter()
+            .map(|p| (p.stop.min(day + DAY_MS) - p.start.max(day)).max(0))
+            .sum();
+
+        if let Some(date) = Utc.timestamp_millis_opt(day).single() {
+            days.push(DayScore {
+                date: date.date_naive().to_string(),
+                score: round(at_risk as f64 / DAY_MS as f64 * 100.),
+            });
+        }
+
+        day += DAY_MS;
+    }
+
+    Ok::<_, ApiError>(Json(MoldRisk { periods, days }))
+}
+
+/// The slope and coefficient of determination of the least-squares line
+/// through `points`.
+fn linear_fit(points: &[(f64, f64)]) -> Option<(f64, f64)> {
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|p| p.0).sum::<f64>() / n;
+    let mean_y = points.iter().map(|p| p.1).sum::<f64>() / n;
+
+    let (mut cov, mut var_x, mut var_y) = (0., 0., 0.);
+    for (x, y) in points {
+        cov += (x - mean_x) * (y - mean_y);
+        var_x += (x - mean_x).powi(2);
+        var_y += (y - mean_y).powi(2);
+    }
+
+    if var_x <= 0. || var_y <= 0. {
+        return None;
+    }
+
+    Some((cov / var_x, cov * cov / (var_x * var_y)))
+}
+
+/// Increases smaller than this (in ppm) don't end a decay.
+const DECAY_NOISE: f64 = 5.;
+/// Decays shorter than this are ignored.
+const MIN_DECAY_MS: i64 = 10 * 60 * 1000;
+/// Decays that drop less than this (in ppm) are ignored.
+const MIN_DECAY_DROP: f64 = 100.;
+
+#[derive(Debug, Deserialize)]
+pub struct AirExchangeQuery {
+    /// Outdoor CO2 level in ppm, which indoor levels decay towards. Defaults
+    /// to 420.
+    outdoor: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+struct Decay {
+    start: i64,
+    stop: i64,
+    start_co2: f64,
+    stop_co2: f64,
+    /// Air changes per hour.
+    ach: f64,
+    /// How well the exponential decay fits, from 0 to 1.
+    r2: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct AirExchange {
+    outdoor: f64,
+    /// The median air changes per hour of all decays.
+    ach: Option<f64>,
+    decays: Vec<Decay>,
+}
+
+/// Fit `C(t) = outdoor + (C0 - outdoor) * e^(-ach * t)` to the decay in
+/// `points`.
+fn fit_decay(points: &[MetricPoint], outdoor: f64) -> Option<Decay> {
+    let (first, last) = (points.first()?, points.last()?);
+
+    if last.0 - first.0 < MIN_DECAY_MS || first.1 - last.1 < MIN_DECAY_DROP {
+        return None;
+    }
+
+    let linearized: Vec<_> = points
+        .iter()
+        .map(|p| ((p.0 - first.0) as f64 / 3_600_000., (p.1 - outdoor).ln()))
+        .collect();
+
+    let (slope, r2) = linear_fit(&linearized)?;
+
+    Some(Decay {
+        start: first.0,
+        stop: last.0,
+        start_co2: first.1,
+        stop_co2: last.1,
+        ach: round(-slope),
+        r2: round(r2),
+    })
+}
+
+/// Estimate the air exchange rate from periods in which CO2 decays, e.g.
+/// after opening a window.
+pub async fn air_exchange(
+    Path(path): Path<String>,
+    Query(query): Query<AirExchangeQuery>,
+    Extension(client): Extension<SharedState>,
+    Extension(limits): Extension<QueryLimits>,
+    _: ReadAccess,
+) -> impl IntoResponse {
+    let outdoor = query.outdoor.unwrap_or(420.);
+    let range = TimeRange::Span(get_range(&path)?);
+    let options = RangeOptions {
+        fields: vec![Field::Co2],
+        window_ms: Some(ANALYSIS_WINDOW_MS),
+        ..Default::default()
+    };
+
+    let fetched = fetch(&client, &limits, range, &options).await?;
+    let max_gap = max_gap(fetched.window_ms);
+    let points: Vec<_> = fetched
+        .points
+        .iter()
+        .filter_map(|p| MetricPoint::project(Field::Co2, p))
+        .collect();
+
+    let mut decays = Vec::new();
+    let mut segment: Vec<MetricPoint> = Vec::new();
+
+    for point in points {
+        let continues = segment
+            .last()
+            .is_some_and(|last| point.1 <= last.1 + DECAY_NOISE && point.0 - last.0 <= max_gap);
+
+        if !continues {
+            decays.extend(fit_decay(&segment, outdoor));
+            segment.clear();
+        }
+
+        // The logarithm is undefined at or below the outdoor level.
+        if point.1 > outdoor {
+            segment.push(point);
+        }
+    }
+    decays.extend(fit_decay(&segment, outdoor));
+
+    let mut rates: Vec<_> = decays.iter().map(|d| d.ach).collect();
+    rates.sort_by(f64::total_cmp);
+
+    Ok::<_, ApiError>(Json(AirExchange {
+        outdoor,
+        ach: rates.get(rates.len() / 2).copied(),
+        decays,
+    }))
+}
+
+/// CO2 rising faster than this (in ppm per minute) means someone is in the
+/// room.
+const OCCUPIED_RATE: f64 = 1.;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Occupancy {
+    Occupied,
+    Unoccupied,
+}
+
+#[derive(Debug, Serialize)]
+struct OccupancyInterval {
+    start: i64,
+    stop: i64,
+    state: Occupancy,
+}
+
+/// Experimental: label periods as occupied when CO2 rises and unoccupied when
+/// it stays level or decays. Ventilating an occupied room also shows up as
+/// unoccupied.
+pub async fn occupancy(
+    Path(path): Path<String>,
+    Extension(client): Extension<SharedState>,
+    Extension(limits): Extension<QueryLimits>,
+    _: ReadAccess,
+) -> impl IntoResponse {
+    let range = TimeRange::Span(get_range(&path)?);
+    let options = RangeOptions {
+        fields: vec![Field::Co2],
+        window_ms: Some(ANALYSIS_WINDOW_MS),
+        ..Default::default()
+    };
+
+    let fetched = fetch(&client, &limits, range, &options).await?;
+    let max_gap = max_gap(fetched.window_ms);
+    let points: Vec<_> = fetched
+        .points
+        .iter()
+        .filter_map(|p| MetricPoint::project(Field::Co2, p))
+        .collect();
+
+    let mut intervals: Vec<OccupancyInterval> = Vec::new();
+
+    for pair in points.windows(2) {
+        let (a, b) = (pair[0], pair[1]);
+        let minutes = (b.0 - a.0) as f64 / 60_000.;
+
+        if b.0 - a.0 > max_gap || minutes <= 0. {
+            continue;
+        }
+
+        let state = if (b.1 - a.1) / minutes >= OCCUPIED_RATE {
+            Occupancy::Occupied
+        } else {
+            Occupancy::Unoccupied
+        };
+
+        match intervals.last_mut() {
+            Some(last) if last.state == state && last.stop == a.0 => last.stop = b.0,
+            _ => intervals.push(OccupancyInterval {
+                start: a.0,
+                stop: b.0,
+                state,
+            }),
+        }
+    }
+
+    Ok::<_, ApiError>(Json(intervals))
+}
@@ -0,0 +1,100 @@
+use axum::{extract::Path, http::StatusCode, response::IntoResponse, Extension, Json};
+use chrono::Utc;
+use serde::Deserialize;
+
+use influxdb_temp_client::{Annotation, TimeRange};
+
+use crate::{
+    between, get_range,
+    problem::{ApiError, ErrorCode},
+    scope::{AdminAccess, ReadAccess},
+    SharedState,
+};
+
+/// Longest accepted annotation text, in bytes.
+const MAX_TEXT_LEN: usize = 1000;
+
+#[derive(Debug, Deserialize)]
+pub struct NewAnnotation {
+    /// In milliseconds. Defaults to now.
+    time: Option<i64>,
+    text: String,
+}
+
+pub async fn create(
+    Extension(client): Extension<SharedState>,
+    _: AdminAccess,
+    Json(new): Json<NewAnnotation>,
+) -> impl IntoResponse {
+    let text = new.text.trim();
+    if text.is_empty() || text.len() > MAX_TEXT_LEN {
+        return Err(ApiError::new(
+            ErrorCode::BadRequest,
+            format!("Text must be between 1 and {MAX_TEXT_LEN} bytes long."),
+        ));
+    }
+
+    let time = new.time.unwrap_or_else(|| Utc::now().timestamp_millis());
+    // Stored in nanoseconds.
+    if time.checked_mul(1_000_000).is_none() {
+        return Err(ApiError::new(
+            ErrorCode::BadTimeRange,
+            format!("Timestamp {time} is out of range."),
+        ));
+    }
+
+    let annotation = Annotation {
+        time,
+        text: text.to_string(),
+    };
+
+    client
+        .lock()
+        .await
+        .write_annotation(&annotation)
+        .await
+        .map_err(ApiError::backend)?;
+
+    Ok((StatusCode::CREATED, Json(annotation)))
+}
+
+/// The annotations in `range`, if `include` is set.
+pub async fn overlapping(
+    client: &SharedState,
+    range: TimeRange,
+    include: bool,
+) -> Result<Option<Vec<Annotation>>, ApiError> {
+    if !include {
+        return Ok(None);
+    }
+
+    let annotations = client
+        .lock()
+        .await
+        .get_annotations(range)
+        .await
+        .map_err(ApiError::backend)?;
+
+    Ok(Some(annotations))
+}
+
+async fn list(client: &SharedState, range: TimeRange) -> Result<Json<Vec<Annotation>>, ApiError> {
+    let annotations = overlapping(client, range, true).await?;
+    Ok(Json(annotations.unwrap_or_default()))
+}
+
+pub async fn range(
+    Path(path): Path<String>,
+    Extension(client): Extension<SharedState>,
+    _: ReadAccess,
+) -> impl IntoResponse {
+    list(&client, TimeRange::Span(get_range(&path)?)).await
+}
+
+pub async fn range_start_end(
+    Path((start, stop)): Path<(u64, u64)>,
+    Extension(client): Extension<SharedState>,
+    _: ReadAccess,
+) -> impl IntoResponse {
+    list(&client, between(start, stop)?).await
+}
@@ -0,0 +1,520 @@
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use chrono_tz::Tz;
+use clap::{Args, ValueEnum};
+use duration_string::DurationString;
+#[cfg(feature = "prometheus")]
+use influxdb_temp_client::Field;
+use influxdb_temp_client::{
+    Client, MockBackend, Rollups, SchemaProblem, TimeSeriesBackend, BUCKET,
+};
+use tokio::sync::{watch, Mutex};
+
+use crate::{cache::CachedBackend, calendar, tasks::TaskApi, SharedState};
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum BackendKind {
+    Influxdb,
+    #[cfg(feature = "postgres")]
+    Postgres,
+    #[cfg(feature = "victoriametrics")]
+    Victoriametrics,
+    #[cfg(feature = "prometheus")]
+    Prometheus,
+    /// InfluxDB 1.x, queried with InfluxQL.
+    #[cfg(feature = "influxql")]
+    Influxql,
+}
+
+/// What keeps the rollups up to date.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum RollupMode {
+    /// The server, in the background.
+    Server,
+    /// InfluxDB tasks, which the server creates and updates.
+    Tasks,
+}
+
+#[derive(Args, Clone)]
+pub struct BackendOpts {
+    /// Serve synthetic data instead of connecting to a backend.
+    #[clap(long, env = "MOCK")]
+    pub mock: bool,
+    #[clap(long, value_enum, env = "BACKEND", default_value = "influxdb")]
+    pub backend: BackendKind,
+    #[clap(long, env = "INFLUXDB_TOKEN")]
+    pub api_token: Option<String>,
+    /// File to read the InfluxDB token from. It is read again on SIGHUP, so
+    /// that the token can be rotated without a restart.
+    #[clap(long, env = "INFLUXDB_TOKEN_FILE", conflicts_with = "api_token")]
+    pub token_file: Option<PathBuf>,
+    /// The token as read from Vault, which takes precedence.
+    #[clap(skip)]
+    pub vault_token: Option<watch::Receiver<String>>,
+    #[clap(long, env = "INFLUXDB_HOST")]
+    pub host: Option<String>,
+    #[clap(long, env = "INFLUXDB_ORG")]
+    pub org: Option<String>,
+    #[clap(long, env = "INFLUXDB_BUCKET", default_value = BUCKET)]
+    pub bucket: String,
+    /// IANA time zone that days start in, e.g. `Europe/Amsterdam`, for daily
+    /// aggregations and the `today` range. Defaults to UTC.
+    #[clap(long, env = "TIMEZONE", value_parser = calendar::parse_timezone)]
+    pub timezone: Option<Tz>,
+    /// How long to wait for a connection to InfluxDB.
+    #[clap(long, env = "INFLUXDB_CONNECT_TIMEOUT")]
+    pub influxdb_connect_timeout: Option<DurationString>,
+    /// Maximum amount of idle connections to InfluxDB to keep open.
+    #[clap(long, env = "INFLUXDB_POOL_SIZE")]
+    pub influxdb_pool_size: Option<usize>,
+    /// Interval of TCP keepalive probes on connections to InfluxDB.
+    #[clap(long, env = "INFLUXDB_TCP_KEEPALIVE")]
+    pub influxdb_tcp_keepalive: Option<DurationString>,
+    /// PEM file with an additional CA certificate to trust for InfluxDB, e.g.
+    /// of an internal proxy with a self-signed certificate.
+    #[clap(long, env = "INFLUXDB_CA_CERT")]
+    pub influxdb_ca_cert: Option<PathBuf>,
+    #[cfg(feature = "postgres")]
+    #[clap(long, env = "DATABASE_URL")]
+    pub database_url: Option<String>,
+    #[cfg(feature = "postgres")]
+    #[clap(long, env = "DATABASE_TABLE", default_value = "measurements")]
+    pub database_table: String,
+    #[cfg(feature = "victoriametrics")]
+    #[clap(long, env = "VICTORIAMETRICS_URL")]
+    pub victoriametrics_url: Option<String>,
+    #[cfg(feature = "prometheus")]
+    #[clap(long, env = "PROMETHEUS_URL")]
+    pub prometheus_url: Option<String>,
+    /// Metric (or selector) per field, e.g. `temperature=room_temperature_celsius`.
+    /// Repeat the flag for more fields, or separate them with `;`, since
+    /// selectors contain commas.
+    #[cfg(feature = "prometheus")]
+    #[clap(long, env = "PROMETHEUS_METRICS", value_delimiter = ';', value_parser = parse_metric)]
+    pub prometheus_metrics: Vec<(Field, String)>,
+    /// URL of InfluxDB 1.x, e.g. `http://localhost:8086`.
+    #[cfg(feature = "influxql")]
+    #[clap(long, env = "INFLUXQL_URL")]
+    pub influxql_url: Option<String>,
+    #[cfg(feature = "influxql")]
+    #[clap(long, env = "INFLUXQL_DATABASE", default_value = BUCKET)]
+    pub influxql_database: String,
+    #[cfg(feature = "influxql")]
+    #[clap(long, env = "INFLUXQL_USERNAME")]
+    pub influxql_username: Option<String>,
+    #[cfg(feature = "influxql")]
+    #[clap(long, env = "INFLUXQL_PASSWORD", requires = "influxql_username")]
+    pub influxql_password: Option<String>,
+    /// File to read the InfluxQL password from. It is read again on SIGHUP.
+    #[cfg(feature = "influxql")]
+    #[clap(
+        long,
+        env = "INFLUXQL_PASSWORD_FILE",
+        requires = "influxql_username",
+        conflicts_with = "influxql_password"
+    )]
+    pub influxql_password_file: Option<PathBuf>,
+    /// The InfluxQL password as read from Vault, which takes precedence.
+    #[cfg(feature = "influxql")]
+    #[clap(skip)]
+    pub vault_influxql_password: Option<watch::Receiver<String>>,
+    /// SQLite database to cache aggregated ranges in.
+    #[clap(long, env = "CACHE_DB")]
+    pub cache_db: Option<PathBuf>,
+    /// Serve the most recent points from memory for this long. Expired points
+    /// are still served while they are refreshed in the background.
+    #[clap(long, env = "CACHE_MAX_AGE", requires = "cache_db")]
+    pub cache_max_age: Option<DurationString>,
+    /// Maximum amount of cached points. Each takes about 50 bytes. The least
+    /// recently used window sizes are dropped first.
+    #[clap(long, env = "CACHE_MAX_ENTRIES", requires = "cache_db")]
+    pub cache_max_entries: Option<u64>,
+    /// Bucket to keep hourly and daily rollups in. The server fills it in the
+    /// background and serves long ranges from it.
+    #[clap(long, env = "ROLLUP_BUCKET")]
+    pub rollup_bucket: Option<String>,
+    #[clap(
+        long,
+        value_enum,
+        env = "ROLLUP_MODE",
+        default_value = "server",
+        requires = "rollup_bucket"
+    )]
+    pub rollup_mode: RollupMode,
+    /// Create the bucket (and the rollup bucket) if it doesn't exist. The
+    /// token needs permission to read organizations and write buckets.
+    #[clap(long, env = "CREATE_BUCKET")]
+    pub create_bucket: bool,
+    /// Retention of the bucket if it is created. Data is kept forever if not
+    /// set.
+    #[clap(long, env = "BUCKET_RETENTION", requires = "create_bucket")]
+    pub bucket_retention: Option<DurationString>,
+}
+
+#[cfg(feature = "prometheus")]
+fn parse_metric(input: &str) -> Result<(Field, String), String> {
+    match input.split_once('=') {
+        Some((field, metric)) => Ok((field.parse()?, metric.to_string())),
+        None => Err(format!("Expected <field>=<metric>, got {input}.")),
+    }
+}
+
+fn required<T: Clone>(value: &Option<T>, name: &str) -> T {
+    match value {
+        Some(v) => v.clone(),
+        None => {
+            eprintln!("{name} is required for the selected backend");
+            std::process::exit(2);
+        }
+    }
+}
+
+/// Exit if the stored data doesn't match what queries expect, instead of
+/// failing on every query later on.
+async fn check_schema(client: &Client) {
+    let problems = match client.check_schema().await {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("Could not check the schema: {e}");
+            return;
+        }
+    };
+
+    for problem in &problems {
+        eprintln!("Schema: {problem}");
+    }
+
+    if problems.iter().any(SchemaProblem::is_fatal) {
+        eprintln!("Fix the field types, or write to a new bucket.");
+        std::process::exit(1);
+    }
+}
+
+impl BackendOpts {
+    /// The HTTP client for InfluxDB, tuned as configured.
+    fn http_client(&self) -> reqwest::ClientBuilder {
+        let mut builder = reqwest::Client::builder();
+
+        if let Some(timeout) = self.influxdb_connect_timeout {
+            builder = builder.connect_timeout(timeout.into());
+        }
+        if let Some(size) = self.influxdb_pool_size {
+            builder = builder.pool_max_idle_per_host(size);
+        }
+        if let Some(interval) = self.influxdb_tcp_keepalive {
+            builder = builder.tcp_keepalive(Duration::from(interval));
+        }
+
+        if let Some(path) = &self.influxdb_ca_cert {
+            let cert = std::fs::read(path)
+                .map_err(|e| e.to_string())
+                .and_then(|pem| reqwest::Certificate::from_pem(&pem).map_err(|e| e.to_string()));
+
+            match cert {
+                Ok(cert) => builder = builder.add_root_certificate(cert),
+                Err(e) => {
+                    eprintln!("Could not load CA certificate {}: {e}", path.display());
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        builder
+    }
+
+    fn token(&self) -> Result<String, String> {
+        if let Some(token) = &self.vault_token {
+            return Ok(token.borrow().clone());
+        }
+
+        match &self.token_file {
+            Some(path) => std::fs::read_to_string(path)
+                .map(|token| token.trim().to_string())
+                .map_err(|e| format!("Could not read {}: {e}", path.display())),
+            None => Ok(required(&self.api_token, "INFLUXDB_TOKEN")),
+        }
+    }
+
+    fn build_influxdb(&self) -> Result<influxdb2::Client, String> {
+        influxdb2::ClientBuilder::with_builder(
+            self.http_client(),
+            required(&self.host, "INFLUXDB_HOST"),
+            required(&self.org, "INFLUXDB_ORG"),
+            self.token()?,
+        )
+        .build()
+        .map_err(|e| format!("Could not create the InfluxDB client: {e}"))
+    }
+
+    /// Call `reload` whenever the token may have changed: when Vault returns
+    /// a new one, or on SIGHUP if it is read from a file.
+    fn on_token_change(&self, reload: impl Fn(&BackendOpts) + Send + 'static) {
+        self.on_secret_change(self.vault_token.clone(), self.token_file.is_some(), reload);
+    }
+
+    /// Call `reload` whenever a secret may have changed: on `vault` updates,
+    /// or on SIGHUP if it is read `from_file`.
+    fn on_secret_change(
+        &self,
+        vault: Option<watch::Receiver<String>>,
+        from_file: bool,
+        reload: impl Fn(&BackendOpts) + Send + 'static,
+    ) {
+        if let Some(mut updates) = vault {
+            let opts = self.clone();
+            tokio::spawn(async move {
+                while updates.changed().await.is_ok() {
+                    reload(&opts);
+                }
+            });
+            return;
+        }
+
+        if !from_file {
+            return;
+        }
+
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+
+            let opts = self.clone();
+            tokio::spawn(async move {
+                let mut hangup = match signal(SignalKind::hangup()) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        eprintln!("Could not listen for SIGHUP: {e}");
+                        return;
+                    }
+                };
+
+                while hangup.recv().await.is_some() {
+                    reload(&opts);
+                }
+            });
+        }
+
+        #[cfg(not(unix))]
+        let _ = reload;
+    }
+
+    /// The username and password for InfluxQL, if a username is set.
+    #[cfg(feature = "influxql")]
+    fn influxql_credentials(&self) -> Result<Option<(String, String)>, String> {
+        let Some(username) = self.influxql_username.clone() else {
+            return Ok(None);
+        };
+
+        let password = match (&self.vault_influxql_password, &self.influxql_password_file) {
+            (Some(password), _) => password.borrow().clone(),
+            (None, Some(path)) => std::fs::read_to_string(path)
+                .map(|password| password.trim().to_string())
+                .map_err(|e| format!("Could not read {}: {e}", path.display()))?,
+            (None, None) => required(&self.influxql_password, "INFLUXQL_PASSWORD"),
+        };
+
+        Ok(Some((username, password)))
+    }
+
+    pub fn influxdb(&self) -> Client {
+        let client = match self.build_influxdb() {
+            Ok(client) => {
+                let client = Client::new(client).with_bucket(&self.bucket);
+                match self.timezone {
+                    Some(timezone) => client.with_timezone(timezone.name()),
+                    None => client,
+                }
+            }
+            Err(e) => {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        };
+
+        let handle = client.handle();
+        self.on_token_change(move |opts| match opts.build_influxdb() {
+            Ok(inner) => {
+                println!("Reloaded the InfluxDB token");
+                handle.replace(inner);
+            }
+            Err(e) => eprintln!("Keeping the previous InfluxDB token: {e}"),
+        });
+
+        client
+    }
+
+    /// Create the configured buckets that don't exist yet.
+    async fn create_buckets(&self, client: &Client) {
+        let org = required(&self.org, "INFLUXDB_ORG");
+        let retention = self.bucket_retention.map(Duration::from);
+
+        let buckets = std::iter::once((self.bucket.as_str(), retention))
+            .chain(self.rollup_bucket.as_deref().map(|b| (b, None)));
+
+        for (bucket, retention) in buckets {
+            match client
+                .create_bucket_if_missing(bucket, &org, retention)
+                .await
+            {
+                Ok(true) => println!("Created bucket {bucket}"),
+                Ok(false) => {}
+                Err(e) => {
+                    eprintln!("Could not create bucket {bucket}: {e}");
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    pub fn task_api(&self) -> TaskApi {
+        let client = match self.http_client().build() {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("Could not create the InfluxDB client: {e}");
+                std::process::exit(1);
+            }
+        };
+
+        let token = match self.token() {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        };
+
+        let api = TaskApi::new(
+            client,
+            required(&self.host, "INFLUXDB_HOST"),
+            required(&self.org, "INFLUXDB_ORG"),
+            token,
+        );
+
+        let reloaded = api.clone();
+        self.on_token_change(move |opts| match opts.token() {
+            Ok(token) => reloaded.set_token(token),
+            Err(e) => eprintln!("Keeping the previous InfluxDB token for tasks: {e}"),
+        });
+
+        api
+    }
+
+    /// The configured rollups, which start out empty.
+    pub fn rollups(&self) -> Option<Rollups> {
+        let bucket = self.rollup_bucket.clone()?;
+
+        if self.mock || !matches!(self.backend, BackendKind::Influxdb) {
+            eprintln!("ROLLUP_BUCKET is only supported with InfluxDB");
+            std::process::exit(2);
+        }
+
+        let rollups = Rollups::new(bucket, self.bucket.clone());
+        Some(match self.timezone {
+            Some(timezone) => rollups.with_timezone(timezone.name()),
+            None => rollups,
+        })
+    }
+
+    pub async fn connect(&self) -> SharedState {
+        Arc::new(Mutex::new(self.open(None).await))
+    }
+
+    pub async fn open(&self, rollups: Option<Rollups>) -> Box<dyn TimeSeriesBackend> {
+        let backend: Box<dyn TimeSeriesBackend> = match self.backend {
+            _ if self.mock => {
+                println!("Serving synthetic data");
+                Box::new(MockBackend::new())
+            }
+            BackendKind::Influxdb => {
+                let client = self.influxdb();
+                if self.create_bucket {
+                    self.create_buckets(&client).await;
+                }
+                check_schema(&client).await;
+
+                match rollups {
+                    Some(rollups) => Box::new(client.with_rollups(rollups)),
+                    None => Box::new(client),
+                }
+            }
+            #[cfg(feature = "postgres")]
+            BackendKind::Postgres => {
+                let url = required(&self.database_url, "DATABASE_URL");
+                match influxdb_temp_client::PostgresBackend::connect(&url, &self.database_table)
+                    .await
+                {
+                    Ok(backend) => Box::new(backend),
+                    Err(e) => {
+                        eprintln!("Could not connect to PostgreSQL: {e}");
+                        std::process::exit(1);
+                    }
+                }
+            }
+            #[cfg(feature = "victoriametrics")]
+            BackendKind::Victoriametrics => {
+                let url = required(&self.victoriametrics_url, "VICTORIAMETRICS_URL");
+                Box::new(influxdb_temp_client::VictoriaMetricsBackend::new(&url))
+            }
+            #[cfg(feature = "prometheus")]
+            BackendKind::Prometheus => {
+                let url = required(&self.prometheus_url, "PROMETHEUS_URL");
+                let metrics = self.prometheus_metrics.iter().cloned().collect();
+                Box::new(influxdb_temp_client::PrometheusBackend::new(&url, metrics))
+            }
+            #[cfg(feature = "influxql")]
+            BackendKind::Influxql => {
+                let url = required(&self.influxql_url, "INFLUXQL_URL");
+                let credentials = match self.influxql_credentials() {
+                    Ok(v) => v,
+                    Err(e) => {
+                        eprintln!("{e}");
+                        std::process::exit(1);
+                    }
+                };
+                let client = match self.http_client().build() {
+                    Ok(v) => v,
+                    Err(e) => {
+                        eprintln!("Could not create the InfluxQL client: {e}");
+                        std::process::exit(1);
+                    }
+                };
+
+                let backend = influxdb_temp_client::InfluxQlBackend::new(
+                    &url,
+                    &self.influxql_database,
+                    credentials,
+                )
+                .with_client(client);
+
+                let handle = backend.credentials();
+                self.on_secret_change(
+                    self.vault_influxql_password.clone(),
+                    self.influxql_password_file.is_some(),
+                    move |opts| match opts.influxql_credentials() {
+                        Ok(credentials) => {
+                            println!("Reloaded the InfluxQL password");
+                            handle.replace(credentials);
+                        }
+                        Err(e) => eprintln!("Keeping the previous InfluxQL password: {e}"),
+                    },
+                );
+
+                Box::new(backend)
+            }
+        };
+
+        match &self.cache_db {
+            Some(path) => {
+                let max_age = self.cache_max_age.map(Duration::from);
+                match CachedBackend::open(backend, path, max_age, self.cache_max_entries) {
+                    Ok(cached) => Box::new(cached),
+                    Err(e) => {
+                        eprintln!("Could not open cache database: {e}");
+                        std::process::exit(1);
+                    }
+                }
+            }
+            None => backend,
+        }
+    }
+}
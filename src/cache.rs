@@ -0,0 +1,724 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use chrono::Utc;
+use futures_util::future::BoxFuture;
+use influxdb_temp_client::{
+    Annotation, BackendError, BandPoint, CacheStats, DataPoint, Field, MetricPoint, RangeOptions,
+    Records, Retention, TimeRange, TimeSeriesBackend,
+};
+use rusqlite::{params, Connection, OptionalExtension};
+
+/// Caches aggregated ranges in a local SQLite database.
+///
+/// Aggregation windows are aligned to the epoch, so the points for a given
+/// window size never change once the window has passed. For every window size
+/// the cache keeps track of a single contiguous span of window end times that
+/// is fully stored, and only queries the inner backend for what lies outside
+/// of it.
+///
+/// The most recent, incomplete windows can additionally be kept in memory for
+/// `max_age`. Once they expire they are still served, and refreshed in the
+/// background.
+///
+/// With `max_entries`, the least recently used window sizes are dropped once
+/// the cache holds more points than that.
+pub struct CachedBackend {
+    inner: Arc<tokio::sync::Mutex<Box<dyn TimeSeriesBackend>>>,
+    db: Mutex<Connection>,
+    hits: u64,
+    misses: u64,
+    stale_serves: u64,
+    max_age: Option<Duration>,
+    tails: Arc<Mutex<HashMap<i64, Tail>>>,
+    max_entries: Option<u64>,
+    evictions: u64,
+}
+
+/// Points of the windows after the covered span, per window size.
+struct Tail {
+    /// Points are for windows ending after `start`.
+    start: i64,
+    points: Vec<DataPoint>,
+    fetched: Instant,
+    /// When the points were fetched, in milliseconds.
+    fetched_ms: i64,
+    refreshing: bool,
+}
+
+impl Tail {
+    fn new(start: i64, points: Vec<DataPoint>) -> Self {
+        Self {
+            start,
+            points,
+            fetched: Instant::now(),
+            fetched_ms: Utc::now().timestamp_millis(),
+            refreshing: false,
+        }
+    }
+}
+
+fn sql_err(e: rusqlite::Error) -> String {
+    format!("Cache error: {e}")
+}
+
+impl CachedBackend {
+    pub fn open(
+        inner: Box<dyn TimeSeriesBackend>,
+        path: &Path,
+        max_age: Option<Duration>,
+        max_entries: Option<u64>,
+    ) -> Result<Self, String> {
+        let db = Connection::open(path).map_err(sql_err)?;
+
+        db.execute_batch(
+            "CREATE TABLE IF NOT EXISTS points (
+                window_ms INTEGER NOT NULL,
+                time INTEGER NOT NULL,
+                temperature REAL,
+                humidity REAL,
+                co2 REAL,
+                PRIMARY KEY (window_ms, time)
+            );
+            CREATE TABLE IF NOT EXISTS covered (
+                window_ms INTEGER PRIMARY KEY,
+                start INTEGER NOT NULL,
+                stop INTEGER NOT NULL,
+                last_used INTEGER NOT NULL DEFAULT 0
+            );",
+        )
+        .map_err(sql_err)?;
+
+        // Caches created before eviction was added lack `last_used`.
+        let has_last_used: bool = db
+            .query_row(
+                "SELECT count(*) > 0 FROM pragma_table_info('covered') WHERE name = 'last_used'",
+                [],
+                |row| row.get(0),
+            )
+            .map_err(sql_err)?;
+        if !has_last_used {
+            db.execute(
+                "ALTER TABLE covered ADD COLUMN last_used INTEGER NOT NULL DEFAULT 0",
+                [],
+            )
+            .map_err(sql_err)?;
+        }
+
+        Ok(Self {
+            inner: Arc::new(tokio::sync::Mutex::new(inner)),
+            db: Mutex::new(db),
+            hits: 0,
+            misses: 0,
+            stale_serves: 0,
+            max_age,
+            tails: Arc::new(Mutex::new(HashMap::new())),
+            max_entries,
+            evictions: 0,
+        })
+    }
+
+    fn touch(&self, window: i64) -> Result<(), String> {
+        self.db
+            .lock()
+            .unwrap()
+            .execute(
+                "UPDATE covered SET last_used = ?1 WHERE window_ms = ?2",
+                params![Utc::now().timestamp_millis(), window],
+            )
+            .map(|_| ())
+            .map_err(sql_err)
+    }
+
+    /// Drop the stored points that include data between `start` and `stop`,
+    /// or all of them, and shrink the covered spans to match. Returns the
+    /// amount of dropped points.
+    fn clear_stored(&self, range: Option<(i64, i64)>) -> Result<u64, String> {
+        let mut db = self.db.lock().unwrap();
+        let transaction = db.transaction().map_err(sql_err)?;
+
+        let Some((start, stop)) = range else {
+            let cleared = transaction
+                .execute("DELETE FROM points", [])
+                .map_err(sql_err)?;
+            transaction
+                .execute("DELETE FROM covered", [])
+                .map_err(sql_err)?;
+            transaction.commit().map_err(sql_err)?;
+            return Ok(cleared as u64);
+        };
+
+        // A point at `time` holds the aggregate of `(time - window, time]`.
+        let cleared = transaction
+            .execute(
+                "DELETE FROM points WHERE time > ?1 AND time - window_ms < ?2",
+                params![start, stop],
+            )
+            .map_err(sql_err)?;
+
+        // The covered span has to stay contiguous, so cut it off before the
+        // first cleared window.
+        transaction
+            .execute(
+                "UPDATE covered SET stop = min(stop, ?1 - (?1 % window_ms)) WHERE stop > ?1",
+                params![start],
+            )
+            .map_err(sql_err)?;
+        transaction
+            .execute("DELETE FROM covered WHERE stop <= start", [])
+            .map_err(sql_err)?;
+
+        transaction.commit().map_err(sql_err)?;
+        Ok(cleared as u64)
+    }
+
+    /// Drop points until at most `max_entries` are left: first the window
+    /// sizes that were used least recently, then the oldest points of
+    /// `window`, which is in use.
+    fn evict(&mut self, window: i64) -> Result<(), String> {
+        let Some(max_entries) = self.max_entries else {
+            return Ok(());
+        };
+
+        let mut db = self.db.lock().unwrap();
+        let transaction = db.transaction().map_err(sql_err)?;
+
+        loop {
+            let entries: u64 = transaction
+                .query_row("SELECT count(*) FROM points", [], |row| row.get(0))
+                .map_err(sql_err)?;
+            if entries <= max_entries {
+                break;
+            }
+
+            let least_recent: Option<i64> = transaction
+                .query_row(
+                    "SELECT window_ms FROM covered WHERE window_ms != ?1
+                    ORDER BY last_used LIMIT 1",
+                    params![window],
+                    |row| row.get(0),
+                )
+                .optional()
+                .map_err(sql_err)?;
+
+            let Some(evicted) = least_recent else {
+                // Only `window` is left. The covered span has to stay
+                // contiguous, so cut off its start.
+                let excess = entries - max_entries;
+                let new_start: Option<i64> = transaction
+                    .query_row(
+                        "SELECT time FROM points WHERE window_ms = ?1
+                        ORDER BY time LIMIT 1 OFFSET ?2",
+                        params![window, (excess - 1) as i64],
+                        |row| row.get(0),
+                    )
+                    .optional()
+                    .map_err(sql_err)?;
+
+                // Points that aren't covered are never read, drop them all.
+                let Some(new_start) = new_start else {
+                    transaction
+                        .execute("DELETE FROM points WHERE window_ms != ?1", params![window])
+                        .map_err(sql_err)?;
+                    break;
+                };
+
+                let deleted = transaction
+                    .execute(
+                        "DELETE FROM points WHERE window_ms = ?1 AND time <= ?2",
+                        params![window, new_start],
+                    )
+                    .map_err(sql_err)?;
+                transaction
+                    .execute(
+                        "UPDATE covered SET start = max(start, ?2) WHERE window_ms = ?1",
+                        params![window, new_start],
+                    )
+                    .map_err(sql_err)?;
+                transaction
+                    .execute("DELETE FROM covered WHERE stop <= start", [])
+                    .map_err(sql_err)?;
+
+                self.evictions += deleted as u64;
+                break;
+            };
+
+            let deleted = transaction
+                .execute("DELETE FROM points WHERE window_ms = ?1", params![evicted])
+                .map_err(sql_err)?;
+            transaction
+                .execute("DELETE FROM covered WHERE window_ms = ?1", params![evicted])
+                .map_err(sql_err)?;
+
+            self.tails.lock().unwrap().remove(&evicted);
+            self.evictions += deleted as u64;
+        }
+
+        transaction.commit().map_err(sql_err)
+    }
+
+    fn covered(&self, window: i64) -> Result<Option<(i64, i64)>, String> {
+        self.db
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT start, stop FROM covered WHERE window_ms = ?1",
+                params![window],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .map_err(sql_err)
+    }
+
+    fn load(&self, window: i64, start: i64, stop: i64) -> Result<Vec<DataPoint>, String> {
+        let db = self.db.lock().unwrap();
+        let mut statement = db
+            .prepare_cached(
+                "SELECT time, temperature, humidity, co2 FROM points
+                WHERE window_ms = ?1 AND time > ?2 AND time <= ?3
+                ORDER BY time",
+            )
+            .map_err(sql_err)?;
+
+        let rows = statement
+            .query_map(params![window, start, stop], |row| {
+                Ok(DataPoint {
+                    time: row.get(0)?,
+                    temperature: row.get(1)?,
+                    humidity: row.get(2)?,
+                    co2: row.get(3)?,
+                })
+            })
+            .map_err(sql_err)?;
+
+        rows.collect::<Result<_, _>>().map_err(sql_err)
+    }
+
+    /// Store the complete points in `points`, which were fetched for windows
+    /// ending in `(start, stop]`, and extend the covered span accordingly.
+    fn store(&self, window: i64, points: &[DataPoint], start: i64, stop: i64) -> Result<(), String> {
+        if stop <= start {
+            return Ok(());
+        }
+
+        let mut db = self.db.lock().unwrap();
+        let transaction = db.transaction().map_err(sql_err)?;
+
+        transaction
+            .execute(
+                "DELETE FROM points WHERE window_ms = ?1 AND time > ?2 AND time <= ?3",
+                params![window, start, stop],
+            )
+            .map_err(sql_err)?;
+
+        for point in points.iter().filter(|p| p.time > start && p.time <= stop) {
+            transaction
+                .execute(
+                    "INSERT INTO points (window_ms, time, temperature, humidity, co2)
+                    VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![
+                        window,
+                        point.time,
+                        point.temperature,
+                        point.humidity,
+                        point.co2
+                    ],
+                )
+                .map_err(sql_err)?;
+        }
+
+        let existing: Option<(i64, i64)> = transaction
+            .query_row(
+                "SELECT start, stop FROM covered WHERE window_ms = ?1",
+                params![window],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .map_err(sql_err)?;
+
+        let (start, stop) = match existing {
+            Some((s, e)) if s <= stop && start <= e => (s.min(start), e.max(stop)),
+            _ => (start, stop),
+        };
+
+        transaction
+            .execute(
+                "INSERT OR REPLACE INTO covered (window_ms, start, stop, last_used)
+                VALUES (?1, ?2, ?3, ?4)",
+                params![window, start, stop, Utc::now().timestamp_millis()],
+            )
+            .map_err(sql_err)?;
+
+        transaction.commit().map_err(sql_err)
+    }
+
+    async fn fetch(
+        &self,
+        window: i64,
+        start: i64,
+        stop: i64,
+    ) -> Result<Vec<DataPoint>, BackendError> {
+        fetch(&self.inner, window, start, stop).await
+    }
+
+    /// The tail for `window` if it has windows ending after `start`. Starts a
+    /// refresh if it expired.
+    fn tail(&self, window: i64, start: i64) -> Option<(Vec<DataPoint>, i64)> {
+        let max_age = self.max_age?;
+        let mut tails = self.tails.lock().unwrap();
+        let tail = tails.get_mut(&window).filter(|t| t.start <= start)?;
+
+        if tail.fetched.elapsed() > max_age && !tail.refreshing {
+            tail.refreshing = true;
+
+            let (inner, tails) = (self.inner.clone(), self.tails.clone());
+            tokio::spawn(async move {
+                let result = fetch(&inner, window, start, Utc::now().timestamp_millis()).await;
+
+                let mut tails = tails.lock().unwrap();
+                match result {
+                    Ok(points) => {
+                        tails.insert(window, Tail::new(start, points));
+                    }
+                    Err(e) => {
+                        eprintln!("Could not refresh cached points: {e}");
+                        if let Some(tail) = tails.get_mut(&window) {
+                            tail.refreshing = false;
+                        }
+                    }
+                }
+            });
+        }
+
+        Some((tail.points.clone(), tail.fetched_ms))
+    }
+}
+
+async fn fetch(
+    inner: &tokio::sync::Mutex<Box<dyn TimeSeriesBackend>>,
+    window: i64,
+    start: i64,
+    stop: i64,
+) -> Result<Vec<DataPoint>, BackendError> {
+    let options = RangeOptions {
+        window_ms: Some(window as u64),
+        ..Default::default()
+    };
+
+    let range = TimeRange::Between {
+        start_ms: start as u64,
+        stop_ms: stop as u64,
+    };
+
+    inner.lock().await.get_range(range, &options).await
+}
+
+/// Windows that windows derived from a point count are rounded up to, so
+/// that clients asking for slightly different amounts of points share the
+/// cached points of one window size. Longer windows are rounded up to whole
+/// days.
+const WINDOW_STEPS_MS: [u64; 14] = [
+    30_000,
+    60_000,
+    2 * 60_000,
+    5 * 60_000,
+    10 * 60_000,
+    15 * 60_000,
+    30 * 60_000,
+    3_600_000,
+    2 * 3_600_000,
+    3 * 3_600_000,
+    6 * 3_600_000,
+    12 * 3_600_000,
+    DAY_MS,
+    2 * DAY_MS,
+];
+
+const DAY_MS: u64 = 24 * 3_600_000;
+
+/// The window to cache `range` in. Explicit windows are used as-is.
+fn cache_window(range: &TimeRange, options: &RangeOptions) -> i64 {
+    let window = options.window(range);
+    if options.window_ms.is_some() {
+        return window as i64;
+    }
+
+    let window = WINDOW_STEPS_MS
+        .into_iter()
+        .find(|&step| step >= window)
+        .unwrap_or_else(|| window.div_ceil(DAY_MS) * DAY_MS);
+    window as i64
+}
+
+/// Drop points older than `since` and fields that were not requested.
+fn filter(points: &mut Vec<DataPoint>, options: &RangeOptions) {
+    if let Some(since) = options.since {
+        points.retain(|p| p.time > since);
+    }
+
+    if !options.fields.is_empty() {
+        for point in points.iter_mut() {
+            for field in Field::ALL {
+                if !options.fields.contains(&field) {
+                    match field {
+                        Field::Temperature => point.temperature = None,
+                        Field::Humidity => point.humidity = None,
+                        Field::Co2 => point.co2 = None,
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl TimeSeriesBackend for CachedBackend {
+    async fn get_current(&mut self) -> Result<Option<DataPoint>, BackendError> {
+        self.inner.lock().await.get_current().await
+    }
+
+    async fn get_range(
+        &mut self,
+        range: TimeRange,
+        options: &RangeOptions,
+    ) -> Result<Vec<DataPoint>, BackendError> {
+        let (start, stop) = range.bounds();
+        let window = cache_window(&range, options);
+        let aligned_start = start - start.rem_euclid(window);
+
+        // Leave some slack for points that are written late.
+        let now = Utc::now().timestamp_millis();
+        let complete_until = stop.min(now - window);
+
+        let mut points = match self.covered(window)? {
+            Some((covered_start, covered_stop)) if covered_start <= aligned_start => {
+                self.hits += 1;
+                self.touch(window)?;
+                let mut points = self.load(window, start, stop.min(covered_stop))?;
+
+                if stop > covered_stop {
+                    let tail_start = covered_stop - covered_stop.rem_euclid(window);
+
+                    let (tail, fetched_ms) = match self.tail(window, tail_start) {
+                        Some((tail, fetched_ms)) => {
+                            let max_age = self.max_age.unwrap_or_default().as_millis() as i64;
+                            if now - fetched_ms > max_age {
+                                self.stale_serves += 1;
+                            }
+                            (tail, fetched_ms)
+                        }
+                        None => {
+                            let tail = self.fetch(window, tail_start, stop).await?;
+
+                            // Only ranges up until now are worth keeping.
+                            if self.max_age.is_some() && stop >= now - window {
+                                self.tails
+                                    .lock()
+                                    .unwrap()
+                                    .insert(window, Tail::new(tail_start, tail.clone()));
+                            }
+
+                            (tail, now)
+                        }
+                    };
+
+                    let complete_until = complete_until.min(fetched_ms - window);
+                    self.store(window, &tail, covered_stop, complete_until)?;
+                    points.extend(
+                        tail.into_iter()
+                            .filter(|p| p.time > covered_stop && p.time <= stop),
+                    );
+                }
+
+                points
+            }
+            _ => {
+                self.misses += 1;
+                let points = self.fetch(window, aligned_start, stop).await?;
+                self.store(window, &points, aligned_start, complete_until)?;
+                points
+            }
+        };
+
+        self.evict(window)?;
+        filter(&mut points, options);
+
+        Ok(points)
+    }
+
+    async fn write(&mut self, points: &[DataPoint]) -> Result<(), BackendError> {
+        let result = self.inner.lock().await.write(points).await;
+
+        // Buffered points can be written long after they were measured, into
+        // windows that were already stored as complete. The recent windows in
+        // memory are refreshed anyway.
+        let times = points.iter().map(|p| p.time);
+        if let (Some(min), Some(max)) = (times.clone().min(), times.max()) {
+            if let Err(e) = self.clear_stored(Some((min - 1, max + 1))) {
+                eprintln!("Could not clear the cache after a write: {e}");
+            }
+        }
+
+        result
+    }
+
+    async fn get_metric_range(
+        &mut self,
+        name: &str,
+        range: TimeRange,
+        options: &RangeOptions,
+    ) -> Result<Vec<MetricPoint>, BackendError> {
+        // Only the fields of `DataPoint` are cached.
+        let Ok(field) = name.parse::<Field>() else {
+            return self
+                .inner
+                .lock()
+                .await
+                .get_metric_range(name, range, options)
+                .await;
+        };
+
+        let options = RangeOptions {
+            fields: vec![field],
+            ..options.clone()
+        };
+
+        Ok(self
+            .get_range(range, &options)
+            .await?
+            .iter()
+            .filter_map(|p| MetricPoint::project(field, p))
+            .collect())
+    }
+
+    async fn get_metric_band(
+        &mut self,
+        name: &str,
+        range: TimeRange,
+        options: &RangeOptions,
+    ) -> Result<Vec<BandPoint>, BackendError> {
+        self.inner
+            .lock()
+            .await
+            .get_metric_band(name, range, options)
+            .await
+    }
+
+    async fn get_time_weighted_avg(
+        &mut self,
+        name: &str,
+        range: TimeRange,
+    ) -> Result<Option<f64>, BackendError> {
+        self.inner
+            .lock()
+            .await
+            .get_time_weighted_avg(name, range)
+            .await
+    }
+
+    async fn get_retention(&mut self) -> Result<Retention, BackendError> {
+        self.inner.lock().await.get_retention().await
+    }
+
+    async fn get_annotations(&mut self, range: TimeRange) -> Result<Vec<Annotation>, BackendError> {
+        self.inner.lock().await.get_annotations(range).await
+    }
+
+    async fn write_annotation(&mut self, annotation: &Annotation) -> Result<(), BackendError> {
+        self.inner.lock().await.write_annotation(annotation).await
+    }
+
+    async fn get_raw_metric(
+        &mut self,
+        name: &str,
+        start_ms: i64,
+        stop_ms: i64,
+    ) -> Result<Vec<MetricPoint>, BackendError> {
+        self.inner
+            .lock()
+            .await
+            .get_raw_metric(name, start_ms, stop_ms)
+            .await
+    }
+
+    async fn delete_range(&mut self, start_ms: i64, stop_ms: i64) -> Result<(), BackendError> {
+        let result = self
+            .inner
+            .lock()
+            .await
+            .delete_range(start_ms, stop_ms)
+            .await;
+
+        // Windows end at their time, so the window ending at `start_ms`
+        // includes it.
+        self.clear_cache(Some((start_ms - 1, stop_ms))).await?;
+        result
+    }
+
+    fn scan_records(&self) -> BoxFuture<'static, Result<Vec<Records>, BackendError>> {
+        let inner = self.inner.clone();
+        Box::pin(async move {
+            let scan = inner.lock().await.scan_records();
+            scan.await
+        })
+    }
+
+    async fn clear_cache(&mut self, range: Option<(i64, i64)>) -> Result<u64, BackendError> {
+        self.tails.lock().unwrap().clear();
+        Ok(self.clear_stored(range)?)
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        let entries = self
+            .db
+            .lock()
+            .unwrap()
+            .query_row("SELECT count(*) FROM points", [], |row| row.get(0))
+            .unwrap_or_default();
+
+        let memory_entries = self
+            .tails
+            .lock()
+            .unwrap()
+            .values()
+            .map(|t| t.points.len() as u64)
+            .sum();
+
+        Some(CacheStats {
+            entries,
+            hits: self.hits,
+            misses: self.misses,
+            stale_serves: self.stale_serves,
+            max_entries: self.max_entries,
+            evictions: self.evictions,
+            memory_entries,
+        })
+    }
+
+    async fn get_cached_range(
+        &mut self,
+        range: TimeRange,
+        options: &RangeOptions,
+    ) -> Result<Option<Vec<DataPoint>>, BackendError> {
+        let (start, stop) = range.bounds();
+        let window = cache_window(&range, options);
+
+        let Some((covered_start, covered_stop)) = self.covered(window)? else {
+            return Ok(None);
+        };
+
+        if covered_stop <= start || stop <= covered_start {
+            return Ok(None);
+        }
+
+        let mut points = self.load(window, start, stop)?;
+        filter(&mut points, options);
+
+        Ok(Some(points))
+    }
+}
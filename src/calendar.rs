@@ -0,0 +1,35 @@
+//! The time zone that days start in, for everything that is split into days.
+
+use std::{sync::OnceLock, time::Duration};
+
+use chrono::{DateTime, NaiveTime, Utc};
+use chrono_tz::Tz;
+
+static TIMEZONE: OnceLock<Tz> = OnceLock::new();
+
+pub fn parse_timezone(input: &str) -> Result<Tz, String> {
+    input
+        .parse()
+        .map_err(|e| format!("Unknown time zone {input}: {e}"))
+}
+
+/// Use `timezone` instead of UTC from now on. Only the first call has an
+/// effect.
+pub fn init(timezone: Option<Tz>) {
+    if let Some(timezone) = timezone {
+        let _ = TIMEZONE.set(timezone);
+        println!("Days start at midnight in {}", timezone.name());
+    }
+}
+
+pub fn timezone() -> Tz {
+    TIMEZONE.get().copied().unwrap_or(Tz::UTC)
+}
+
+/// The time since the most recent midnight before `now`, as shown on a
+/// clock. It is off by the change on days that DST starts or ends.
+pub fn since_midnight(now: DateTime<Utc>) -> Duration {
+    let time = now.with_timezone(&timezone()).time();
+
+    (time - NaiveTime::MIN).to_std().unwrap_or_default()
+}
@@ -0,0 +1,323 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use async_trait::async_trait;
+use axum::{
+    body::Body,
+    http::{HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+    Extension,
+};
+use clap::Args;
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+
+use influxdb_temp_client::{
+    Annotation, BackendError, BandPoint, CacheStats, DataPoint, Field, MetricPoint, RangeOptions,
+    Records, Retention, TimeRange, TimeSeriesBackend,
+};
+
+#[derive(Args)]
+pub struct CalibrationOpts {
+    /// Amount to add to every value of a field, as `field=offset`, e.g.
+    /// `temperature=-0.8` for a sensor that reads 0.8 °C high.
+    #[clap(long = "calibration-offset", env = "CALIBRATION_OFFSETS", value_delimiter = ',', value_parser = parse_correction)]
+    pub offsets: Vec<(Field, f64)>,
+    /// Factor to multiply every value of a field with before the offset is
+    /// added, as `field=scale`.
+    #[clap(long = "calibration-scale", env = "CALIBRATION_SCALES", value_delimiter = ',', value_parser = parse_correction)]
+    pub scales: Vec<(Field, f64)>,
+}
+
+fn parse_correction(input: &str) -> Result<(Field, f64), String> {
+    match input.split_once('=') {
+        Some((field, value)) => Ok((
+            field.parse()?,
+            value
+                .parse()
+                .map_err(|e| format!("Invalid correction {value}: {e}"))?,
+        )),
+        None => Err(format!("Expected <field>=<value>, got {input}.")),
+    }
+}
+
+fn one() -> f64 {
+    1.
+}
+
+/// A linear correction of the values of a field.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Correction {
+    #[serde(default = "one")]
+    pub scale: f64,
+    #[serde(default)]
+    pub offset: f64,
+}
+
+impl Default for Correction {
+    fn default() -> Self {
+        Self {
+            scale: 1.,
+            offset: 0.,
+        }
+    }
+}
+
+impl Correction {
+    pub fn apply(&self, value: f64) -> f64 {
+        ((value * self.scale + self.offset) * 100.).round() / 100.
+    }
+
+    /// The stored value that reads as `value` after the correction.
+    pub fn invert(&self, value: f64) -> f64 {
+        (value - self.offset) / self.scale
+    }
+}
+
+/// Corrections of the fields of a sensor.
+#[derive(Debug, Clone, Default)]
+pub struct Calibration(Arc<BTreeMap<Field, Correction>>);
+
+impl From<BTreeMap<Field, Correction>> for Calibration {
+    fn from(corrections: BTreeMap<Field, Correction>) -> Self {
+        Self(Arc::new(corrections))
+    }
+}
+
+impl Calibration {
+    pub fn new(opts: &CalibrationOpts) -> Result<Self, String> {
+        let mut corrections = BTreeMap::new();
+
+        for &(field, scale) in &opts.scales {
+            corrections
+                .entry(field)
+                .or_insert_with(Correction::default)
+                .scale = scale;
+        }
+        for &(field, offset) in &opts.offsets {
+            corrections
+                .entry(field)
+                .or_insert_with(Correction::default)
+                .offset = offset;
+        }
+
+        let calibration = Self::from(corrections);
+        calibration.validate()?;
+        Ok(calibration)
+    }
+
+    /// Negative scales would swap the minimum and maximum of bands.
+    pub fn validate(&self) -> Result<(), String> {
+        match self.0.iter().find(|(_, c)| c.scale <= 0.) {
+            Some((field, _)) => Err(format!(
+                "The calibration scale of {} must be positive",
+                field.name()
+            )),
+            None => Ok(()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn get(&self, field: Field) -> Option<Correction> {
+        self.0.get(&field).copied()
+    }
+
+    fn apply(&self, field: Field, value: Option<f64>) -> Option<f64> {
+        match self.get(field) {
+            Some(correction) => value.map(|v| correction.apply(v)),
+            None => value,
+        }
+    }
+
+    pub fn apply_point(&self, point: &mut DataPoint) {
+        point.temperature = self.apply(Field::Temperature, point.temperature);
+        point.humidity = self.apply(Field::Humidity, point.humidity);
+        point.co2 = self.apply(Field::Co2, point.co2);
+    }
+
+    /// The correction of the metric `name`, if it is a calibrated field.
+    fn metric(&self, name: &str) -> Option<Correction> {
+        self.get(name.parse().ok()?)
+    }
+
+    /// E.g. `temperature; scale=1; offset=-0.8`, for the `X-Calibration`
+    /// header.
+    fn header(&self) -> Option<HeaderValue> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let value = self
+            .0
+            .iter()
+            .map(|(field, c)| format!("{}; scale={}; offset={}", field.name(), c.scale, c.offset))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        HeaderValue::from_str(&value).ok()
+    }
+}
+
+/// Applies a [`Calibration`] to every value read from the backend it wraps.
+/// Written points are stored as they are.
+pub struct Calibrated {
+    inner: Box<dyn TimeSeriesBackend>,
+    calibration: Calibration,
+}
+
+impl Calibrated {
+    pub fn new(inner: Box<dyn TimeSeriesBackend>, calibration: Calibration) -> Self {
+        Self { inner, calibration }
+    }
+}
+
+#[async_trait]
+impl TimeSeriesBackend for Calibrated {
+    async fn get_current(&mut self) -> Result<Option<DataPoint>, BackendError> {
+        let mut point = self.inner.get_current().await?;
+        if let Some(point) = &mut point {
+            self.calibration.apply_point(point);
+        }
+        Ok(point)
+    }
+
+    async fn get_range(
+        &mut self,
+        range: TimeRange,
+        options: &RangeOptions,
+    ) -> Result<Vec<DataPoint>, BackendError> {
+        let mut points = self.inner.get_range(range, options).await?;
+        points
+            .iter_mut()
+            .for_each(|p| self.calibration.apply_point(p));
+        Ok(points)
+    }
+
+    async fn write(&mut self, points: &[DataPoint]) -> Result<(), BackendError> {
+        self.inner.write(points).await
+    }
+
+    async fn get_metric_range(
+        &mut self,
+        name: &str,
+        range: TimeRange,
+        options: &RangeOptions,
+    ) -> Result<Vec<MetricPoint>, BackendError> {
+        let mut points = self.inner.get_metric_range(name, range, options).await?;
+        if let Some(correction) = self.calibration.metric(name) {
+            points.iter_mut().for_each(|p| p.1 = correction.apply(p.1));
+        }
+        Ok(points)
+    }
+
+    async fn get_metric_band(
+        &mut self,
+        name: &str,
+        range: TimeRange,
+        options: &RangeOptions,
+    ) -> Result<Vec<BandPoint>, BackendError> {
+        let mut bands = self.inner.get_metric_band(name, range, options).await?;
+        if let Some(correction) = self.calibration.metric(name) {
+            for band in &mut bands {
+                band.min = correction.apply(band.min);
+                band.mean = correction.apply(band.mean);
+                band.max = correction.apply(band.max);
+            }
+        }
+        Ok(bands)
+    }
+
+    async fn get_time_weighted_avg(
+        &mut self,
+        name: &str,
+        range: TimeRange,
+    ) -> Result<Option<f64>, BackendError> {
+        let average = self.inner.get_time_weighted_avg(name, range).await?;
+        Ok(match self.calibration.metric(name) {
+            Some(correction) => average.map(|v| correction.apply(v)),
+            None => average,
+        })
+    }
+
+    async fn get_cached_range(
+        &mut self,
+        range: TimeRange,
+        options: &RangeOptions,
+    ) -> Result<Option<Vec<DataPoint>>, BackendError> {
+        let mut points = self.inner.get_cached_range(range, options).await?;
+        if let Some(points) = &mut points {
+            points
+                .iter_mut()
+                .for_each(|p| self.calibration.apply_point(p));
+        }
+        Ok(points)
+    }
+
+    async fn get_retention(&mut self) -> Result<Retention, BackendError> {
+        self.inner.get_retention().await
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.inner.cache_stats()
+    }
+
+    async fn clear_cache(&mut self, range: Option<(i64, i64)>) -> Result<u64, BackendError> {
+        self.inner.clear_cache(range).await
+    }
+
+    async fn get_annotations(&mut self, range: TimeRange) -> Result<Vec<Annotation>, BackendError> {
+        self.inner.get_annotations(range).await
+    }
+
+    async fn write_annotation(&mut self, annotation: &Annotation) -> Result<(), BackendError> {
+        self.inner.write_annotation(annotation).await
+    }
+
+    /// Uncalibrated, as corrections apply to the stored values.
+    async fn get_raw_metric(
+        &mut self,
+        name: &str,
+        start_ms: i64,
+        stop_ms: i64,
+    ) -> Result<Vec<MetricPoint>, BackendError> {
+        self.inner.get_raw_metric(name, start_ms, stop_ms).await
+    }
+
+    async fn delete_range(&mut self, start_ms: i64, stop_ms: i64) -> Result<(), BackendError> {
+        self.inner.delete_range(start_ms, stop_ms).await
+    }
+
+    fn scan_records(&self) -> BoxFuture<'static, Result<Vec<Records>, BackendError>> {
+        let (scan, calibration) = (self.inner.scan_records(), self.calibration.clone());
+        Box::pin(async move {
+            let mut records = scan.await?;
+            for record in &mut records {
+                if let Some(correction) = calibration.get(record.field) {
+                    record.min.1 = correction.apply(record.min.1);
+                    record.max.1 = correction.apply(record.max.1);
+                }
+            }
+            Ok(records)
+        })
+    }
+}
+
+/// Note the calibration of the sensor in an `X-Calibration` header, so that
+/// clients know that values were corrected.
+pub async fn note(
+    Extension(calibration): Extension<Calibration>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let mut response = next.run(request).await;
+
+    if let Some(value) = calibration.header() {
+        response.headers_mut().insert("x-calibration", value);
+    }
+
+    response
+}
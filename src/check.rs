@@ -0,0 +1,77 @@
+use clap::Args;
+
+use influxdb_temp_client::{Client, MEASUREMENT};
+
+#[derive(Args)]
+pub struct CheckOpts {}
+
+pub async fn run(client: Client, _opts: CheckOpts) {
+    let mut failed = false;
+
+    macro_rules! step {
+        ($name:expr, $result:expr) => {
+            match $result {
+                Ok(msg) => println!("[ OK ] {}: {}", $name, msg),
+                Err(e) => {
+                    println!("[FAIL] {}: {}", $name, e);
+                    failed = true;
+                }
+            }
+        };
+    }
+
+    step!(
+        "InfluxDB reachable",
+        client.check_health().await.map(|_| "healthy".to_string())
+    );
+
+    step!(
+        format!("Bucket {:?}", client.bucket()),
+        match client.bucket_exists().await {
+            Ok(true) => Ok("exists".to_string()),
+            Ok(false) => Err("does not exist".to_string()),
+            Err(e) => Err(e.to_string()),
+        }
+    );
+
+    step!(
+        format!("Measurement {MEASUREMENT:?}"),
+        match client.measurement_exists().await {
+            Ok(true) => Ok("exists".to_string()),
+            Ok(false) => Err("does not exist".to_string()),
+            Err(e) => Err(e.to_string()),
+        }
+    );
+
+    step!(
+        "Schema",
+        match client.check_schema().await {
+            Ok(problems) if problems.is_empty() => Ok("fields are doubles".to_string()),
+            Ok(problems) => Err(problems
+                .iter()
+                .map(|p| p.to_string())
+                .collect::<Vec<_>>()
+                .join("; ")),
+            Err(e) => Err(e.to_string()),
+        }
+    );
+
+    step!(
+        "Sample point",
+        match client.get_latest_point().await {
+            Ok(Some(p)) => match (p.temperature, p.humidity) {
+                (Some(temperature), Some(humidity)) => Ok(format!(
+                    "decoded point at {} ({temperature:.02} C, {humidity:.02} %H)",
+                    p.time
+                )),
+                _ => Err(format!("point at {} is missing temperature or humidity", p.time)),
+            },
+            Ok(None) => Err("no data in the last day".to_string()),
+            Err(e) => Err(e.to_string()),
+        }
+    );
+
+    if failed {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,130 @@
+use std::collections::BTreeMap;
+
+use axum::{
+    extract::{Path, Query},
+    response::IntoResponse,
+    Extension, Json,
+};
+use chrono::{Datelike, Days, NaiveDate, TimeZone, Utc};
+use serde::Serialize;
+
+use influxdb_temp_client::{BandPoint, Field, RangeOptions, TimeRange};
+
+use crate::{
+    calendar, get_range, problem::ApiError, scope::ReadAccess, JsonOnly, NoFilter, QueryLimits,
+    SharedState,
+};
+
+const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+
+/// The minimum, mean and maximum of a field in a week or month.
+#[derive(Debug, Serialize)]
+struct PeriodBand {
+    /// The first day of the period.
+    start: String,
+    /// Days with data in the period.
+    days: usize,
+    min: f64,
+    mean: f64,
+    max: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Period {
+    /// Weeks start on Monday.
+    Week,
+    Month,
+}
+
+impl Period {
+    fn start(&self, day: NaiveDate) -> NaiveDate {
+        match self {
+            Period::Week => day - Days::new(day.weekday().num_days_from_monday() as u64),
+            Period::Month => day.with_day(1).unwrap_or(day),
+        }
+    }
+}
+
+fn round(value: f64) -> f64 {
+    (value * 100.).round() / 100.
+}
+
+/// Combine the daily `bands` into bands per `period`.
+fn per_period(bands: &[BandPoint], period: Period) -> Vec<PeriodBand> {
+    let mut periods: BTreeMap<NaiveDate, Vec<&BandPoint>> = BTreeMap::new();
+
+    for band in bands {
+        // Windows are labeled with their end time.
+        let Some(end) = Utc.timestamp_millis_opt(band.time - 1).single() else {
+            continue;
+        };
+        let day = end.with_timezone(&calendar::timezone()).date_naive();
+        periods.entry(period.start(day)).or_default().push(band);
+    }
+
+    periods
+        .into_iter()
+        .map(|(start, days)| PeriodBand {
+            start: start.to_string(),
+            days: days.len(),
+            min: days.iter().map(|d| d.min).fold(f64::INFINITY, f64::min),
+            mean: round(days.iter().map(|d| d.mean).sum::<f64>() / days.len() as f64),
+            max: days.iter().map(|d| d.max).fold(f64::NEG_INFINITY, f64::max),
+        })
+        .collect()
+}
+
+/// Daily bands from the rollups, if they are configured, combined per
+/// `period`.
+async fn fetch(
+    client: &SharedState,
+    limits: &QueryLimits,
+    field: Field,
+    path: &str,
+    period: Period,
+) -> Result<Json<Vec<PeriodBand>>, ApiError> {
+    let range = TimeRange::Span(get_range(path)?);
+
+    let mut options = RangeOptions {
+        window_ms: Some(DAY_MS),
+        ..Default::default()
+    };
+    limits.check(&range, &mut options)?;
+
+    let bands = client
+        .lock()
+        .await
+        .get_metric_band(field.name(), range, &options)
+        .await
+        .map_err(ApiError::backend)?;
+
+    Ok(Json(per_period(&bands, period)))
+}
+
+pub async fn weekly(
+    Path(path): Path<String>,
+    Query(filter): Query<NoFilter>,
+    Query(format): Query<JsonOnly>,
+    Extension(field): Extension<Field>,
+    Extension(client): Extension<SharedState>,
+    Extension(limits): Extension<QueryLimits>,
+    _: ReadAccess,
+) -> impl IntoResponse {
+    filter.check()?;
+    format.check()?;
+    fetch(&client, &limits, field, &path, Period::Week).await
+}
+
+pub async fn monthly(
+    Path(path): Path<String>,
+    Query(filter): Query<NoFilter>,
+    Query(format): Query<JsonOnly>,
+    Extension(field): Extension<Field>,
+    Extension(client): Extension<SharedState>,
+    Extension(limits): Extension<QueryLimits>,
+    _: ReadAccess,
+) -> impl IntoResponse {
+    filter.check()?;
+    format.check()?;
+    fetch(&client, &limits, field, &path, Period::Month).await
+}
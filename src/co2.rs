@@ -0,0 +1,183 @@
+use std::time::Duration;
+
+use axum::{response::IntoResponse, Extension, Json};
+use clap::Args;
+use serde::Serialize;
+
+use influxdb_temp_client::{Field, MetricPoint, RangeOptions, TimeRange};
+
+use crate::{
+    problem::{ApiError, ErrorCode},
+    scope::ReadAccess,
+    SharedState,
+};
+
+/// How far back the rate of change is determined from.
+const TREND_WINDOW: Duration = Duration::from_secs(30 * 60);
+
+#[derive(Args, Debug, Clone, Copy, Serialize)]
+pub struct Co2Opts {
+    /// CO2 level (ppm) below which it is fine to stop ventilating.
+    #[clap(long = "co2-fresh", env = "CO2_FRESH", default_value = "600")]
+    pub fresh: f64,
+    /// CO2 level (ppm) from which air quality is considered moderate.
+    #[clap(long = "co2-moderate", env = "CO2_MODERATE", default_value = "1000")]
+    pub moderate: f64,
+    /// CO2 level (ppm) from which air quality is considered poor.
+    #[clap(long = "co2-poor", env = "CO2_POOR", default_value = "1500")]
+    pub poor: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Co2Band {
+    Good,
+    Moderate,
+    Poor,
+}
+
+impl Co2Opts {
+    pub fn classify(&self, co2: f64) -> Co2Band {
+        if co2 >= self.poor {
+            Co2Band::Poor
+        } else if co2 >= self.moderate {
+            Co2Band::Moderate
+        } else {
+            Co2Band::Good
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Co2Status {
+    time: i64,
+    co2: f64,
+    status: Co2Band,
+    /// The configured bands, so that clients can classify series themselves.
+    thresholds: Co2Opts,
+}
+
+/// The most recent CO2 level and its band.
+pub async fn status(
+    Extension(bands): Extension<Co2Opts>,
+    Extension(client): Extension<SharedState>,
+    _: ReadAccess,
+) -> impl IntoResponse {
+    let point = client
+        .lock()
+        .await
+        .get_current()
+        .await
+        .map_err(ApiError::backend)?;
+
+    let Some((time, co2)) = point.and_then(|p| Some((p.time, p.co2?))) else {
+        return Err(ApiError::new(ErrorCode::NoData, "No recent CO2 reading"));
+    };
+
+    Ok(Json(Co2Status {
+        time,
+        co2,
+        status: bands.classify(co2),
+        thresholds: bands,
+    }))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Action {
+    VentilateNow,
+    Ok,
+    CloseWindow,
+}
+
+impl Action {
+    fn message(&self) -> &'static str {
+        match self {
+            Action::VentilateNow => "Ventilate now",
+            Action::Ok => "OK",
+            Action::CloseWindow => "Closing the window is fine",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Recommendation {
+    time: i64,
+    co2: f64,
+    /// Rate of change in ppm per minute.
+    rate: f64,
+    /// Minutes until the moderate threshold is reached at the current rate.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    minutes_until_moderate: Option<f64>,
+    action: Action,
+    message: &'static str,
+}
+
+/// The least-squares slope of `points`, in ppm per minute.
+fn rate(points: &[MetricPoint]) -> f64 {
+    let Some(first) = points.first() else {
+        return 0.;
+    };
+
+    let n = points.len() as f64;
+    let xs = points.iter().map(|p| (p.0 - first.0) as f64 / 60_000.);
+    let mean_x = xs.clone().sum::<f64>() / n;
+    let mean_y = points.iter().map(|p| p.1).sum::<f64>() / n;
+
+    let (cov, var) = xs.zip(points).fold((0., 0.), |(cov, var), (x, p)| {
+        (
+            cov + (x - mean_x) * (p.1 - mean_y),
+            var + (x - mean_x).powi(2),
+        )
+    });
+
+    if var > 0. {
+        cov / var
+    } else {
+        0.
+    }
+}
+
+/// Whether to ventilate, based on the current CO2 level and its trend.
+pub async fn recommendation(
+    Extension(bands): Extension<Co2Opts>,
+    Extension(client): Extension<SharedState>,
+    _: ReadAccess,
+) -> impl IntoResponse {
+    let points = client
+        .lock()
+        .await
+        .get_metric_range(
+            Field::Co2.name(),
+            TimeRange::Span(TREND_WINDOW),
+            &RangeOptions::default(),
+        )
+        .await
+        .map_err(ApiError::backend)?;
+
+    let Some(&MetricPoint(time, co2)) = points.last() else {
+        return Err(ApiError::new(ErrorCode::NoData, "No recent CO2 reading"));
+    };
+
+    let rate = rate(&points);
+
+    let action = if co2 >= bands.poor || (co2 >= bands.moderate && rate > 0.) {
+        Action::VentilateNow
+    } else if co2 <= bands.fresh && rate <= 0. {
+        Action::CloseWindow
+    } else {
+        Action::Ok
+    };
+
+    let minutes_until_moderate =
+        (co2 < bands.moderate && rate > 0.).then(|| ((bands.moderate - co2) / rate).round());
+
+    Ok(Json(Recommendation {
+        time,
+        co2,
+        rate: (rate * 100.).round() / 100.,
+        minutes_until_moderate,
+        action,
+        message: action.message(),
+    }))
+}
@@ -0,0 +1,98 @@
+use axum::{
+    extract::{Path, Query},
+    response::IntoResponse,
+    Extension,
+};
+use clap::Args;
+
+use influxdb_temp_client::{DataPoint, Field, MetricPoint, TimeRange};
+
+use crate::{
+    get_range, mark_stale, problem::ApiError, scope::ReadAccess, spikes::SpikeFilter, to_json,
+    QueryLimits, RangeParams, SharedState,
+};
+
+#[derive(Args, Debug, Clone)]
+pub struct ComfortOpts {
+    /// Weight of temperature in the comfort index.
+    #[clap(long, env = "COMFORT_TEMPERATURE_WEIGHT", default_value = "1")]
+    pub comfort_temperature_weight: f64,
+    /// Weight of humidity in the comfort index.
+    #[clap(long, env = "COMFORT_HUMIDITY_WEIGHT", default_value = "1")]
+    pub comfort_humidity_weight: f64,
+    /// Weight of CO2 in the comfort index.
+    #[clap(long, env = "COMFORT_CO2_WEIGHT", default_value = "1")]
+    pub comfort_co2_weight: f64,
+}
+
+/// 100 inside `good`, dropping linearly to 0 at `falloff` outside of it.
+fn band(value: f64, good: (f64, f64), falloff: f64) -> f64 {
+    let distance = if value < good.0 {
+        good.0 - value
+    } else if value > good.1 {
+        value - good.1
+    } else {
+        0.
+    };
+
+    (100. * (1. - distance / falloff)).clamp(0., 100.)
+}
+
+impl ComfortOpts {
+    fn weight(&self, field: Field) -> f64 {
+        match field {
+            Field::Temperature => self.comfort_temperature_weight,
+            Field::Humidity => self.comfort_humidity_weight,
+            Field::Co2 => self.comfort_co2_weight,
+        }
+    }
+
+    /// The comfort score of `point` from 0 to 100, based on the fields it
+    /// has.
+    fn score(&self, point: &DataPoint) -> Option<f64> {
+        let mut total = 0.;
+        let mut weights = 0.;
+
+        for field in Field::ALL {
+            let Some(value) = field.value(point) else {
+                continue;
+            };
+
+            let score = match field {
+                Field::Temperature => band(value, (20., 24.), 5.),
+                Field::Humidity => band(value, (40., 60.), 20.),
+                Field::Co2 => band(value, (0., 800.), 1200.),
+            };
+
+            total += score * self.weight(field);
+            weights += self.weight(field);
+        }
+
+        (weights > 0.).then(|| (total / weights).round())
+    }
+}
+
+pub async fn index(
+    Path(path): Path<String>,
+    Query(params): Query<RangeParams>,
+    Extension(comfort): Extension<ComfortOpts>,
+    Extension(client): Extension<SharedState>,
+    Extension(limits): Extension<QueryLimits>,
+    Extension(spikes): Extension<SpikeFilter>,
+    _: ReadAccess,
+) -> impl IntoResponse {
+    let range = TimeRange::Span(get_range(&path)?);
+    let fetched = params
+        .fetch(&client, &limits, range, &params.options()?)
+        .await?
+        .filter(params.filter, &spikes)
+        .aggregate(params.aggregation(&range)?);
+
+    let points: Vec<_> = fetched
+        .points
+        .iter()
+        .filter_map(|p| Some(MetricPoint(p.time, comfort.score(p)?)))
+        .collect();
+
+    Ok::<_, ApiError>(mark_stale(to_json(&points)?.into_response(), fetched.stale))
+}
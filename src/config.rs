@@ -0,0 +1,204 @@
+use axum::{response::IntoResponse, Extension, Json};
+use serde::Serialize;
+
+use influxdb_temp_client::{Field, MEASUREMENT};
+
+use crate::{
+    calibration::{Calibration, Correction},
+    metric_prefix, HttpPassword, Metrics, QueryLimits,
+};
+
+/// Where the API of the tenant of a request is served.
+#[derive(Debug, Clone)]
+pub struct ApiBase(pub String);
+
+/// How a client authenticates to an endpoint.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase", tag = "type")]
+enum Auth {
+    None,
+    /// The HTTP password or an API key as bearer token.
+    Bearer,
+    /// The HTTP password or an API key in the query parameter `param`, for
+    /// clients that can't set headers.
+    Query {
+        param: &'static str,
+    },
+    /// A public token in the path parameter `param`.
+    Path {
+        param: &'static str,
+    },
+    /// The signature and query of a URL minted by `/share`.
+    Signed,
+    /// The admin password or an admin API key as bearer token.
+    Admin,
+}
+
+#[derive(Debug, Serialize)]
+struct Endpoint {
+    method: &'static str,
+    path: String,
+    auth: Auth,
+}
+
+impl Endpoint {
+    fn get(base: &str, path: impl AsRef<str>, auth: Auth) -> Self {
+        Self {
+            method: "GET",
+            path: format!("{base}{}", path.as_ref()),
+            auth,
+        }
+    }
+
+    fn post(base: &str, path: impl AsRef<str>, auth: Auth) -> Self {
+        Self {
+            method: "POST",
+            path: format!("{base}{}", path.as_ref()),
+            auth,
+        }
+    }
+}
+
+/// The routes of `metric_routes`, relative to the prefix of the field.
+const METRIC_ROUTES: [&str; 6] = [
+    "/range/:range",
+    "/from/:start/to/:stop",
+    "/band/:range",
+    "/band/from/:start/to/:stop",
+    "/weekly/:range",
+    "/monthly/:range",
+];
+
+#[derive(Debug, Serialize)]
+struct FieldConfig {
+    name: String,
+    /// Unknown for metrics that are only configured by name.
+    unit: Option<&'static str>,
+    /// The prefix of the per-field routes, if the field has them.
+    path: Option<&'static str>,
+    /// The correction that is applied to every value, if any.
+    calibration: Option<Correction>,
+}
+
+#[derive(Debug, Serialize)]
+struct Sensor {
+    name: &'static str,
+    fields: Vec<FieldConfig>,
+}
+
+#[derive(Debug, Serialize)]
+struct Limits {
+    max_range_ms: u128,
+    max_points: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct FrontendConfig {
+    sensors: Vec<Sensor>,
+    endpoints: Vec<Endpoint>,
+    limits: Limits,
+}
+
+fn endpoints(base: &str, admin: bool) -> Vec<Endpoint> {
+    let mut endpoints = vec![
+        Endpoint::get(base, "/config.json", Auth::None),
+        Endpoint::get(base, "/temp/current", Auth::None),
+        Endpoint::get(base, "/temp/live", Auth::Query { param: "password" }),
+        Endpoint::get(base, "/temp/poll", Auth::Bearer),
+        Endpoint::get(base, "/data/range/:range", Auth::Bearer),
+        Endpoint::get(base, "/data/from/:start/to/:stop", Auth::Bearer),
+        Endpoint::post(base, "/ingest", Auth::Admin),
+        Endpoint::get(base, "/meta/retention", Auth::Bearer),
+        Endpoint::get(base, "/records", Auth::Bearer),
+        Endpoint::post(base, "/annotations", Auth::Admin),
+        Endpoint::get(base, "/annotations/range/:range", Auth::Bearer),
+        Endpoint::get(base, "/annotations/from/:start/to/:stop", Auth::Bearer),
+        Endpoint::get(base, "/alerts/history", Auth::Bearer),
+        Endpoint::get(base, "/alerts/feed.atom", Auth::Query { param: "token" }),
+        Endpoint::get(base, "/compare/outdoor/:range", Auth::Bearer),
+        Endpoint::get(base, "/compare/years/from/:start/to/:stop", Auth::Bearer),
+        Endpoint::get(base, "/comfort/index/:range", Auth::Bearer),
+        Endpoint::get(base, "/co2/status", Auth::Bearer),
+        Endpoint::get(base, "/co2/recommendation", Auth::Bearer),
+        Endpoint::get(base, "/stats/twa/:field/:range", Auth::Bearer),
+        Endpoint::get(base, "/analysis/degree-days/:range", Auth::Bearer),
+        Endpoint::get(base, "/analysis/mold-risk/:range", Auth::Bearer),
+        Endpoint::get(base, "/analysis/air-exchange/:range", Auth::Bearer),
+        Endpoint::get(base, "/analysis/occupancy/:range", Auth::Bearer),
+        Endpoint::get(
+            base,
+            "/public/:token/current",
+            Auth::Path { param: "token" },
+        ),
+        Endpoint::get(
+            base,
+            "/public/:token/range/:range",
+            Auth::Path { param: "token" },
+        ),
+        Endpoint::post(base, "/share", Auth::Bearer),
+        Endpoint::get(base, "/share/:signature", Auth::Signed),
+        Endpoint::get(base, "/metric/:name/range/:range", Auth::Bearer),
+        Endpoint::get(base, "/metric/:name/from/:start/to/:stop", Auth::Bearer),
+    ];
+
+    for field in Field::ALL {
+        for route in METRIC_ROUTES {
+            endpoints.push(Endpoint::get(
+                base,
+                format!("{}{route}", metric_prefix(field)),
+                Auth::Bearer,
+            ));
+        }
+    }
+
+    if admin {
+        endpoints.push(Endpoint::get(base, "/admin/stats", Auth::Admin));
+        endpoints.push(Endpoint::get(base, "/admin/metrics", Auth::Admin));
+        endpoints.push(Endpoint::post(base, "/admin/cache/clear", Auth::Admin));
+        endpoints.push(Endpoint::post(base, "/admin/points/delete", Auth::Admin));
+        endpoints.push(Endpoint::post(base, "/admin/points/overwrite", Auth::Admin));
+    }
+
+    endpoints
+}
+
+/// What the frontend needs to configure itself. Contains no secrets, so it
+/// is served without authentication.
+pub async fn config(
+    Extension(metrics): Extension<Metrics>,
+    Extension(limits): Extension<QueryLimits>,
+    Extension(password): Extension<HttpPassword>,
+    Extension(ApiBase(base)): Extension<ApiBase>,
+    Extension(calibration): Extension<Calibration>,
+) -> impl IntoResponse {
+    let fields = metrics
+        .0
+        .iter()
+        .map(|name| match name.parse::<Field>() {
+            Ok(field) => FieldConfig {
+                name: name.clone(),
+                unit: Some(field.unit()),
+                path: Some(metric_prefix(field)),
+                calibration: calibration.get(field),
+            },
+            Err(_) => FieldConfig {
+                name: name.clone(),
+                unit: None,
+                path: None,
+                calibration: None,
+            },
+        })
+        .collect();
+
+    Json(FrontendConfig {
+        sensors: vec![Sensor {
+            name: MEASUREMENT,
+            fields,
+        }],
+        endpoints: endpoints(&base, password.admin_enabled()),
+        limits: Limits {
+            max_range_ms: limits.max_range.as_millis(),
+            max_points: limits.max_points,
+        },
+    })
+}
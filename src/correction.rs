@@ -0,0 +1,255 @@
+use std::collections::BTreeMap;
+
+use axum::{Extension, Json};
+use serde::{Deserialize, Serialize};
+
+use influxdb_temp_client::{DataPoint, Field, MetricPoint};
+
+use crate::{
+    calibration::Calibration,
+    problem::{ApiError, ErrorCode},
+    records::RecordsCache,
+    scope::AdminAccess,
+    SharedState,
+};
+
+/// Longest window that can be corrected at once.
+const MAX_SPAN_MS: i64 = 7 * 24 * 60 * 60 * 1000;
+/// Most points that can be corrected at once.
+const MAX_POINTS: usize = 1000;
+
+/// The points to correct: those in the window whose value of `field` is
+/// above `above` and/or below `below`. At least one of them is required, so
+/// that a correction never hits every point in the window by accident. Like
+/// the values the API returns, they are calibrated.
+#[derive(Debug, Deserialize)]
+pub struct Selection {
+    /// In milliseconds.
+    start: i64,
+    stop: i64,
+    field: Field,
+    above: Option<f64>,
+    below: Option<f64>,
+    /// Only return the matching points.
+    #[serde(default)]
+    dry_run: bool,
+}
+
+impl Selection {
+    fn check(&self) -> Result<(), ApiError> {
+        if self.start >= self.stop {
+            return Err(ApiError::new(
+                ErrorCode::BadTimeRange,
+                "Start must be before stop.",
+            ));
+        }
+
+        if self.stop - self.start > MAX_SPAN_MS {
+            return Err(ApiError::new(
+                ErrorCode::RangeTooLarge,
+                "Corrections are limited to 7 days at once.",
+            ));
+        }
+
+        if self.above.is_none() && self.below.is_none() {
+            return Err(ApiError::new(
+                ErrorCode::BadRequest,
+                "At least one of above and below is required.",
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn matches(&self, value: f64) -> bool {
+        self.above.map_or(true, |above| value > above)
+            && self.below.map_or(true, |below| value < below)
+    }
+
+    /// The matching points, with calibrated values.
+    async fn select(
+        &self,
+        client: &SharedState,
+        calibration: &Calibration,
+    ) -> Result<Vec<MetricPoint>, ApiError> {
+        self.check()?;
+
+        let correction = calibration.get(self.field);
+        let points: Vec<_> = client
+            .lock()
+            .await
+            .get_raw_metric(self.field.name(), self.start, self.stop)
+            .await
+            .map_err(ApiError::backend)?
+            .into_iter()
+            .map(|p| match correction {
+                Some(correction) => MetricPoint(p.0, correction.apply(p.1)),
+                None => p,
+            })
+            .filter(|p| self.matches(p.1))
+            .collect();
+
+        if points.len() > MAX_POINTS {
+            return Err(ApiError::new(
+                ErrorCode::TooManyPoints,
+                format!(
+                    "{} points match, more than the maximum of {MAX_POINTS}. Narrow the window or the condition.",
+                    points.len()
+                ),
+            ));
+        }
+
+        Ok(points)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Corrected {
+    /// The matching points, with their calibrated values before the
+    /// correction.
+    points: Vec<MetricPoint>,
+    /// Unset for dry runs.
+    applied: bool,
+}
+
+/// Drop the cached aggregates of the window and the records, which include
+/// the bad values.
+async fn clear_cache(
+    client: &SharedState,
+    records: &RecordsCache,
+    selection: &Selection,
+) -> Result<(), ApiError> {
+    records.clear();
+
+    client
+        .lock()
+        .await
+        .clear_cache(Some((selection.start, selection.stop)))
+        .await
+        .map(|_| ())
+        .map_err(ApiError::backend)
+}
+
+/// Delete the matching points, including their other fields.
+pub async fn delete(
+    Extension(client): Extension<SharedState>,
+    Extension(records): Extension<RecordsCache>,
+    Extension(calibration): Extension<Calibration>,
+    _: AdminAccess,
+    Json(selection): Json<Selection>,
+) -> Result<Json<Corrected>, ApiError> {
+    let points = selection.select(&client, &calibration).await?;
+
+    if !selection.dry_run {
+        for point in &points {
+            client
+                .lock()
+                .await
+                .delete_range(point.0, point.0 + 1)
+                .await
+                .map_err(ApiError::backend)?;
+        }
+
+        clear_cache(&client, &records, &selection).await?;
+        println!(
+            "Deleted {} point(s) with bad {}",
+            points.len(),
+            selection.field.name()
+        );
+    }
+
+    Ok(Json(Corrected {
+        points,
+        applied: !selection.dry_run,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Overwrite {
+    #[serde(flatten)]
+    selection: Selection,
+    /// The value to store instead, calibrated like the selection.
+    value: f64,
+}
+
+/// Replace the value of the field of the matching points, keeping their
+/// other fields. Points are read with millisecond precision, so they are
+/// deleted and written again with all of their fields, instead of next to
+/// the original.
+pub async fn overwrite(
+    Extension(client): Extension<SharedState>,
+    Extension(records): Extension<RecordsCache>,
+    Extension(calibration): Extension<Calibration>,
+    _: AdminAccess,
+    Json(overwrite): Json<Overwrite>,
+) -> Result<Json<Corrected>, ApiError> {
+    let Overwrite { selection, value } = overwrite;
+    let stored = match calibration.get(selection.field) {
+        Some(correction) => correction.invert(value),
+        None => value,
+    };
+    let points = selection.select(&client, &calibration).await?;
+
+    if !selection.dry_run {
+        let mut corrected: BTreeMap<i64, DataPoint> = points
+            .iter()
+            .map(|p| {
+                let mut point = DataPoint {
+                    time: p.0,
+                    temperature: None,
+                    humidity: None,
+                    co2: None,
+                };
+                *selection.field.value_mut(&mut point) = Some(stored);
+                (p.0, point)
+            })
+            .collect();
+
+        let mut backend = client.lock().await;
+
+        for field in Field::ALL {
+            if field == selection.field {
+                continue;
+            }
+
+            let values = backend
+                .get_raw_metric(field.name(), selection.start, selection.stop)
+                .await
+                .map_err(ApiError::backend)?;
+            for MetricPoint(time, value) in values {
+                if let Some(point) = corrected.get_mut(&time) {
+                    *field.value_mut(point) = Some(value);
+                }
+            }
+        }
+
+        for point in &points {
+            backend
+                .delete_range(point.0, point.0 + 1)
+                .await
+                .map_err(ApiError::backend)?;
+        }
+
+        let corrected: Vec<_> = corrected.into_values().collect();
+        if let Err(e) = backend.write(&corrected).await {
+            eprintln!("Could not write corrected points {corrected:?}: {e}");
+            return Err(ApiError::new(
+                ErrorCode::UpstreamError,
+                format!("The original points were deleted, but the corrected ones could not be written: {e}"),
+            ));
+        }
+        drop(backend);
+
+        clear_cache(&client, &records, &selection).await?;
+        println!(
+            "Overwrote {} point(s) with bad {}",
+            points.len(),
+            selection.field.name()
+        );
+    }
+
+    Ok(Json(Corrected {
+        points,
+        applied: !selection.dry_run,
+    }))
+}
@@ -0,0 +1,70 @@
+use axum::{
+    body::Body,
+    http::{header::ACCEPT_ENCODING, HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
+
+/// The encodings responses are compressed with, most preferred first for
+/// clients that accept several of them equally.
+const ENCODINGS: [&str; 3] = ["zstd", "br", "gzip"];
+
+/// The quality of `encoding` in the `Accept-Encoding` value `accept`, or that
+/// of `*` if it isn't listed.
+fn quality(accept: &str, encoding: &str) -> f32 {
+    let mut wildcard = 0.;
+
+    for item in accept.split(',') {
+        let mut params = item.split(';');
+        let name = params.next().unwrap_or_default().trim();
+        let q = params
+            .filter_map(|p| p.split_once('='))
+            .find(|(key, _)| key.trim().eq_ignore_ascii_case("q"))
+            .map_or(1., |(_, q)| q.trim().parse().unwrap_or(0.));
+
+        if name.eq_ignore_ascii_case(encoding) {
+            return q;
+        } else if name == "*" {
+            wildcard = q;
+        }
+    }
+
+    wildcard
+}
+
+/// The encoding with the highest quality in `accept`, unless the client
+/// accepts none of them.
+fn preferred(accept: &str) -> Option<&'static str> {
+    let mut best: Option<(&str, f32)> = None;
+
+    for encoding in ENCODINGS {
+        let q = quality(accept, encoding);
+        if q > 0. && best.map_or(true, |(_, b)| q > b) {
+            best = Some((encoding, q));
+        }
+    }
+
+    best.map(|(encoding, _)| encoding)
+}
+
+/// Narrow `Accept-Encoding` down to the encoding the client prefers. Each
+/// compression layer only handles one encoding, and would otherwise use it
+/// whenever the client accepts it at all.
+pub async fn negotiate(mut request: Request<Body>, next: Next<Body>) -> Response {
+    let accept = request
+        .headers()
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .collect::<Vec<_>>()
+        .join(",");
+
+    if !accept.is_empty() {
+        let encoding = preferred(&accept).unwrap_or("identity");
+        let headers = request.headers_mut();
+        headers.remove(ACCEPT_ENCODING);
+        headers.insert(ACCEPT_ENCODING, HeaderValue::from_static(encoding));
+    }
+
+    next.run(request).await
+}
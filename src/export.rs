@@ -0,0 +1,230 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::PathBuf,
+    pin::Pin,
+};
+
+use chrono::Utc;
+use clap::{Args, ValueEnum};
+use duration_string::DurationString;
+use futures_util::{Stream, StreamExt};
+
+use influxdb_temp_client::{
+    stream_range, stream_raw_range, BackendError, DataPoint, Field, RangeOptions, TimeRange,
+};
+
+use crate::{import::parse_time, SharedState};
+
+/// Length of the part of the range to fetch (and hold in memory) at a time.
+const CHUNK_MS: i64 = 24 * 60 * 60 * 1000;
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Format {
+    Csv,
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+#[derive(Args)]
+pub struct ExportOpts {
+    /// Start of the range, in milliseconds since the epoch or RFC 3339.
+    #[clap(long, value_parser = parse_time)]
+    pub from: i64,
+    /// End of the range. Defaults to now.
+    #[clap(long, value_parser = parse_time)]
+    pub to: Option<i64>,
+    /// Aggregation window. The stored points are exported as they are if not
+    /// set.
+    #[clap(long)]
+    pub window: Option<DurationString>,
+    #[clap(long, value_enum, default_value = "csv")]
+    pub format: Format,
+    #[clap(long)]
+    pub out: PathBuf,
+}
+
+/// Writes chunks of points to the output file as they are fetched.
+enum Sink {
+    Csv(BufWriter<File>),
+    #[cfg(feature = "parquet")]
+    Parquet(parquet::file::writer::SerializedFileWriter<File>),
+}
+
+impl Sink {
+    fn new(format: Format, file: File) -> Result<Self, String> {
+        match format {
+            Format::Csv => {
+                let mut out = BufWriter::new(file);
+                crate::query::write_csv_header(&mut out, &Field::ALL).map_err(|e| e.to_string())?;
+                Ok(Sink::Csv(out))
+            }
+            #[cfg(feature = "parquet")]
+            Format::Parquet => parquet_writer(file)
+                .map(Sink::Parquet)
+                .map_err(|e| e.to_string()),
+        }
+    }
+
+    fn write(&mut self, data: &[DataPoint]) -> Result<(), String> {
+        match self {
+            Sink::Csv(out) => {
+                crate::query::write_csv_rows(out, data, &Field::ALL).map_err(|e| e.to_string())
+            }
+            #[cfg(feature = "parquet")]
+            Sink::Parquet(writer) => write_row_group(writer, data).map_err(|e| e.to_string()),
+        }
+    }
+
+    fn finish(self) -> Result<(), String> {
+        match self {
+            Sink::Csv(mut out) => out.flush().map_err(|e| e.to_string()),
+            #[cfg(feature = "parquet")]
+            Sink::Parquet(writer) => writer.close().map(|_| ()).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+pub async fn run(backend: SharedState, opts: ExportOpts) {
+    let stop = opts.to.unwrap_or_else(|| Utc::now().timestamp_millis());
+    if stop <= opts.from {
+        eprintln!("--from must be before --to");
+        std::process::exit(2);
+    }
+
+    let options = RangeOptions {
+        window_ms: opts
+            .window
+            .map(|w| std::time::Duration::from(w).as_millis() as u64),
+        ..Default::default()
+    };
+
+    let range = TimeRange::Between {
+        start_ms: opts.from as u64,
+        stop_ms: stop as u64,
+    };
+
+    let file = match File::create(&opts.out) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("Could not create {}: {e}", opts.out.display());
+            std::process::exit(1);
+        }
+    };
+
+    let mut sink = match Sink::new(opts.format, file) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("Could not write {}: {e}", opts.out.display());
+            std::process::exit(1);
+        }
+    };
+
+    let mut backend = backend.lock().await;
+    let mut chunks: Pin<Box<dyn Stream<Item = Result<Vec<DataPoint>, BackendError>> + '_>> =
+        match options.window_ms {
+            Some(window) => {
+                let chunk_windows = (CHUNK_MS as u64 / window.max(1)).max(1);
+                Box::pin(stream_range(&mut *backend, range, &options, chunk_windows))
+            }
+            None => Box::pin(stream_raw_range(
+                &mut *backend,
+                range,
+                Field::ALL.to_vec(),
+                CHUNK_MS,
+            )),
+        };
+
+    let mut exported = 0;
+    while let Some(chunk) = chunks.next().await {
+        let data = match chunk {
+            Ok(v) => v,
+            Err(BackendError::Unsupported(_)) if options.window_ms.is_none() => {
+                eprintln!("The backend can't export raw points, pass --window instead");
+                std::process::exit(2);
+            }
+            Err(e) => {
+                eprintln!("Could not fetch data: {e}");
+                std::process::exit(1);
+            }
+        };
+
+        if let Err(e) = sink.write(&data) {
+            eprintln!("Could not write {}: {e}", opts.out.display());
+            std::process::exit(1);
+        }
+
+        exported += data.len();
+    }
+
+    if let Err(e) = sink.finish() {
+        eprintln!("Could not write {}: {e}", opts.out.display());
+        std::process::exit(1);
+    }
+
+    println!("Exported {exported} point(s) to {}", opts.out.display());
+}
+
+#[cfg(feature = "parquet")]
+fn parquet_writer(
+    file: File,
+) -> parquet::errors::Result<parquet::file::writer::SerializedFileWriter<File>> {
+    use std::sync::Arc;
+
+    use parquet::{
+        file::{properties::WriterProperties, writer::SerializedFileWriter},
+        schema::parser::parse_message_type,
+    };
+
+    let schema = parse_message_type(
+        "message point {
+            REQUIRED INT64 time (TIMESTAMP(MILLIS, true));
+            OPTIONAL DOUBLE temperature;
+            OPTIONAL DOUBLE humidity;
+            OPTIONAL DOUBLE co2;
+        }",
+    )?;
+
+    let props = WriterProperties::builder().build();
+    SerializedFileWriter::new(file, Arc::new(schema), Arc::new(props))
+}
+
+/// Write `data` as a single row group.
+#[cfg(feature = "parquet")]
+fn write_row_group(
+    writer: &mut parquet::file::writer::SerializedFileWriter<File>,
+    data: &[DataPoint],
+) -> parquet::errors::Result<()> {
+    use parquet::data_type::{DoubleType, Int64Type};
+
+    if data.is_empty() {
+        return Ok(());
+    }
+
+    let mut row_group = writer.next_row_group()?;
+
+    let times: Vec<_> = data.iter().map(|p| p.time).collect();
+    let mut column = row_group.next_column()?.unwrap();
+    column
+        .typed::<Int64Type>()
+        .write_batch(&times, None, None)?;
+    column.close()?;
+
+    for field in Field::ALL {
+        let values: Vec<_> = data.iter().filter_map(|p| field.value(p)).collect();
+        let levels: Vec<_> = data
+            .iter()
+            .map(|p| field.value(p).is_some() as i16)
+            .collect();
+
+        let mut column = row_group.next_column()?.unwrap();
+        column
+            .typed::<DoubleType>()
+            .write_batch(&values, Some(&levels), None)?;
+        column.close()?;
+    }
+
+    row_group.close()?;
+
+    Ok(())
+}
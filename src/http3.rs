@@ -0,0 +1,99 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::ConnectInfo,
+    http::{Request, Response},
+    Router,
+};
+use bytes::Buf;
+use h3::{quic::BidiStream, server::RequestStream};
+use tokio::sync::Semaphore;
+use tower::ServiceExt;
+
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// Listen for QUIC on `addr`, with the certificate and key of the TCP
+/// listener's `tls` config.
+pub fn bind(addr: SocketAddr, tls: &rustls::ServerConfig) -> Result<quinn::Endpoint, Error> {
+    let mut tls = tls.clone();
+    tls.alpn_protocols = vec![b"h3".to_vec()];
+
+    let config = quinn::ServerConfig::with_crypto(Arc::new(tls));
+    let endpoint = quinn::Endpoint::server(config, addr)?;
+
+    println!("Serving HTTP/3 on UDP port {}", addr.port());
+    Ok(endpoint)
+}
+
+/// Serve `app` on `endpoint`. Every connection holds one of `permits`, which
+/// are shared with the TCP listener.
+pub async fn serve(app: Router, endpoint: quinn::Endpoint, permits: Arc<Semaphore>) {
+    loop {
+        let permit = permits.clone().acquire_owned().await.unwrap();
+        let Some(connecting) = endpoint.accept().await else {
+            break;
+        };
+        let app = app.clone();
+
+        tokio::spawn(async move {
+            let _permit = permit;
+            if let Err(e) = handle_connection(app, connecting).await {
+                eprintln!("HTTP/3 connection failed: {e}");
+            }
+        });
+    }
+}
+
+async fn handle_connection(app: Router, connecting: quinn::Connecting) -> Result<(), Error> {
+    let connection = connecting.await?;
+    let remote = connection.remote_address();
+    let quic = connection.clone();
+    let connection = h3_quinn::Connection::new(connection);
+    let mut connection = h3::server::Connection::<_, Bytes>::new(connection).await?;
+
+    while let Some((request, stream)) = connection.accept().await? {
+        let app = app.clone();
+        let quic = quic.clone();
+
+        // Stop working on the request if the client goes away.
+        tokio::spawn(async move {
+            tokio::select! {
+                result = handle_request(app, remote, request, stream) => {
+                    if let Err(e) = result {
+                        eprintln!("HTTP/3 request failed: {e}");
+                    }
+                }
+                _ = quic.closed() => {}
+            }
+        });
+    }
+
+    Ok(())
+}
+
+async fn handle_request<S>(
+    app: Router,
+    remote: SocketAddr,
+    request: Request<()>,
+    mut stream: RequestStream<S, Bytes>,
+) -> Result<(), Error>
+where
+    S: BidiStream<Bytes>,
+{
+    let mut body = Vec::new();
+    while let Some(mut chunk) = stream.recv_data().await? {
+        body.extend_from_slice(&chunk.copy_to_bytes(chunk.remaining()));
+    }
+
+    let (mut parts, ()) = request.into_parts();
+    parts.extensions.insert(ConnectInfo(remote));
+    let response = app.oneshot(Request::from_parts(parts, Body::from(body))).await?;
+
+    let (parts, body) = response.into_parts();
+    stream.send_response(Response::from_parts(parts, ())).await?;
+    stream.send_data(hyper::body::to_bytes(body).await?).await?;
+    stream.finish().await?;
+
+    Ok(())
+}
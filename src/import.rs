@@ -0,0 +1,155 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::PathBuf,
+};
+
+use chrono::DateTime;
+use clap::Args;
+
+use influxdb_temp_client::{Client, DataPoint, Field, MEASUREMENT};
+
+#[derive(Args)]
+pub struct ImportOpts {
+    /// CSV file with a header row containing `time` and any of the field
+    /// names. Times are either milliseconds since the epoch or RFC 3339.
+    #[clap(long)]
+    pub file: PathBuf,
+    #[clap(long, default_value = MEASUREMENT)]
+    pub measurement: String,
+    /// Amount of points to write per request.
+    #[clap(long, default_value = "5000")]
+    pub batch_size: usize,
+}
+
+pub fn parse_time(input: &str) -> Result<i64, String> {
+    if let Ok(ms) = input.parse() {
+        return Ok(ms);
+    }
+
+    DateTime::parse_from_rfc3339(input)
+        .map(|t| t.timestamp_millis())
+        .map_err(|e| format!("Invalid time {input} ({e})"))
+}
+
+fn parse_line(
+    columns: &[Option<Field>],
+    time_column: usize,
+    line: &str,
+) -> Result<DataPoint, String> {
+    let values: Vec<_> = line.split(',').map(str::trim).collect();
+    if values.len() != columns.len() {
+        return Err(format!(
+            "Expected {} columns, got {}",
+            columns.len(),
+            values.len()
+        ));
+    }
+
+    let mut point = DataPoint {
+        time: parse_time(values[time_column])?,
+        temperature: None,
+        humidity: None,
+        co2: None,
+    };
+
+    for (field, value) in columns.iter().zip(&values) {
+        let Some(field) = field else {
+            continue;
+        };
+
+        if value.is_empty() {
+            continue;
+        }
+
+        let value = value
+            .parse()
+            .map_err(|e| format!("Invalid {} value {value} ({e})", field.name()))?;
+
+        match field {
+            Field::Temperature => point.temperature = Some(value),
+            Field::Humidity => point.humidity = Some(value),
+            Field::Co2 => point.co2 = Some(value),
+        }
+    }
+
+    Ok(point)
+}
+
+pub async fn run(client: Client, opts: ImportOpts) {
+    let file = match File::open(&opts.file) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("Could not open {}: {e}", opts.file.display());
+            std::process::exit(1);
+        }
+    };
+
+    let mut lines = BufReader::new(file).lines();
+
+    let header = match lines.next() {
+        Some(Ok(v)) => v,
+        _ => {
+            eprintln!("{} has no header row", opts.file.display());
+            std::process::exit(1);
+        }
+    };
+
+    // Columns that are neither `time` nor a known field are ignored.
+    let names: Vec<_> = header.split(',').map(str::trim).collect();
+    let Some(time_column) = names.iter().position(|n| *n == "time") else {
+        eprintln!("Header row has no time column");
+        std::process::exit(1);
+    };
+    let columns: Vec<Option<Field>> = names.iter().map(|n| n.parse().ok()).collect();
+
+    let mut batch = Vec::with_capacity(opts.batch_size);
+    let mut written = 0;
+
+    for (number, line) in lines.enumerate() {
+        let line = match line {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("Could not read {}: {e}", opts.file.display());
+                std::process::exit(1);
+            }
+        };
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match parse_line(&columns, time_column, &line) {
+            Ok(point) => batch.push(point),
+            Err(e) => {
+                // The header is line 1.
+                eprintln!("Line {}: {e}", number + 2);
+                std::process::exit(1);
+            }
+        }
+
+        if batch.len() >= opts.batch_size {
+            written += write(&client, &opts.measurement, &mut batch).await;
+        }
+    }
+
+    written += write(&client, &opts.measurement, &mut batch).await;
+
+    println!("Imported {written} point(s) into {}", opts.measurement);
+}
+
+async fn write(client: &Client, measurement: &str, batch: &mut Vec<DataPoint>) -> usize {
+    if batch.is_empty() {
+        return 0;
+    }
+
+    if let Err(e) = client.write_points(measurement, batch).await {
+        eprintln!("Could not write batch: {e}");
+        std::process::exit(1);
+    }
+
+    let written = batch.len();
+    println!("Wrote {written} point(s)");
+    batch.clear();
+    written
+}
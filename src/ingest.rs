@@ -0,0 +1,227 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+
+use axum::{http::StatusCode, response::IntoResponse, Extension, Json};
+use chrono::Utc;
+use clap::Args;
+use duration_string::DurationString;
+use serde::Deserialize;
+use tokio::sync::Mutex;
+
+use influxdb_temp_client::DataPoint;
+
+use crate::{
+    admin::Stats,
+    calibration::Calibration,
+    problem::{ApiError, ErrorCode},
+    records::RecordsCache,
+    scope::AdminAccess,
+    SharedState,
+};
+
+#[derive(Args)]
+pub struct IngestOpts {
+    /// Maximum amount of points to buffer in memory while the backend is
+    /// unavailable. Further points are spilled to `ingest_spill_file`.
+    #[clap(long, env = "INGEST_BUFFER_SIZE", default_value = "10000")]
+    pub ingest_buffer_size: usize,
+    /// File to spill buffered points to once the in-memory buffer is full.
+    #[clap(long, env = "INGEST_SPILL_FILE")]
+    pub ingest_spill_file: Option<PathBuf>,
+    /// How often to retry writing buffered points.
+    #[clap(long, env = "INGEST_FLUSH_INTERVAL", default_value = "30s")]
+    pub ingest_flush_interval: DurationString,
+}
+
+/// A point as submitted by a sensor. Points without a timestamp are stamped
+/// when they are received, so that buffering does not shift them.
+#[derive(Debug, Deserialize)]
+pub struct IngestPoint {
+    time: Option<i64>,
+    temperature: Option<f64>,
+    humidity: Option<f64>,
+    co2: Option<f64>,
+}
+
+/// Points that could not be written yet, oldest first. Points on disk are
+/// always older than the ones in memory.
+pub struct WriteBuffer {
+    memory: Vec<DataPoint>,
+    capacity: usize,
+    spill_file: Option<PathBuf>,
+}
+
+pub type Buffer = Arc<Mutex<WriteBuffer>>;
+
+impl WriteBuffer {
+    fn is_empty(&self) -> bool {
+        self.memory.is_empty() && !self.spill_file.as_ref().is_some_and(|f| f.exists())
+    }
+
+    fn push(&mut self, points: Vec<DataPoint>) -> Result<(), String> {
+        self.memory.extend(points);
+
+        if self.memory.len() <= self.capacity {
+            return Ok(());
+        }
+
+        let Some(path) = &self.spill_file else {
+            let excess = self.memory.len() - self.capacity;
+            eprintln!("Ingest buffer full, dropping {excess} point(s)");
+            self.memory.drain(..excess);
+            return Ok(());
+        };
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("Could not open spill file: {e}"))?;
+
+        let mut lines = String::new();
+        for point in &self.memory {
+            lines.push_str(&serde_json::to_string(point).unwrap());
+            lines.push('\n');
+        }
+
+        // Keep the points in memory until they are safely on disk, and don't
+        // leave half a line behind if they aren't.
+        let len = file
+            .metadata()
+            .map_err(|e| format!("Could not read spill file: {e}"))?
+            .len();
+        if let Err(e) = file.write_all(lines.as_bytes()).and_then(|_| file.flush()) {
+            let _ = file.set_len(len);
+            return Err(format!("Could not write spill file: {e}"));
+        }
+
+        self.memory.clear();
+        Ok(())
+    }
+
+    fn read_spilled(&self) -> Result<Vec<DataPoint>, String> {
+        let Some(path) = self.spill_file.as_ref().filter(|f| f.exists()) else {
+            return Ok(Vec::new());
+        };
+
+        let file = fs::File::open(path).map_err(|e| format!("Could not open spill file: {e}"))?;
+
+        BufReader::new(file)
+            .lines()
+            .map(|line| {
+                let line = line.map_err(|e| format!("Could not read spill file: {e}"))?;
+                serde_json::from_str(&line).map_err(|e| format!("Invalid spilled point: {e}"))
+            })
+            .collect()
+    }
+}
+
+pub fn buffer(capacity: usize, spill_file: Option<PathBuf>) -> Buffer {
+    Arc::new(Mutex::new(WriteBuffer {
+        memory: Vec::new(),
+        capacity,
+        spill_file,
+    }))
+}
+
+/// Periodically try to write buffered points to the backend.
+pub fn spawn_flusher(client: SharedState, buffer: Buffer, interval: Duration, stats: Arc<Stats>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+
+        loop {
+            interval.tick().await;
+
+            let mut buffer = buffer.lock().await;
+            if buffer.is_empty() {
+                stats.task_ran("ingest_flusher", Ok(()));
+                continue;
+            }
+
+            let spilled = match buffer.read_spilled() {
+                Ok(v) => v,
+                Err(e) => {
+                    eprintln!("{e}");
+                    stats.task_ran("ingest_flusher", Err(&e));
+                    continue;
+                }
+            };
+
+            if !spilled.is_empty() {
+                if let Err(e) = client.lock().await.write(&spilled).await {
+                    eprintln!("Could not flush {} spilled point(s): {e}", spilled.len());
+                    stats.task_ran("ingest_flusher", Err(e.message()));
+                    continue;
+                }
+
+                if let Some(path) = &buffer.spill_file {
+                    if let Err(e) = fs::remove_file(path) {
+                        eprintln!("Could not remove spill file: {e}");
+                    }
+                }
+            }
+
+            if !buffer.memory.is_empty() {
+                if let Err(e) = client.lock().await.write(&buffer.memory).await {
+                    eprintln!("Could not flush {} point(s): {e}", buffer.memory.len());
+                    stats.task_ran("ingest_flusher", Err(e.message()));
+                    continue;
+                }
+            }
+
+            println!(
+                "Flushed {} buffered point(s)",
+                spilled.len() + buffer.memory.len()
+            );
+            buffer.memory.clear();
+            stats.task_ran("ingest_flusher", Ok(()));
+        }
+    });
+}
+
+/// Write points to the backend, buffering them if it is unavailable.
+pub async fn ingest(
+    Extension(client): Extension<SharedState>,
+    Extension(buffer): Extension<Buffer>,
+    Extension(records): Extension<RecordsCache>,
+    Extension(calibration): Extension<Calibration>,
+    _: AdminAccess,
+    Json(points): Json<Vec<IngestPoint>>,
+) -> impl IntoResponse {
+    let now = Utc::now().timestamp_millis();
+    let points: Vec<_> = points
+        .into_iter()
+        .map(|p| DataPoint {
+            time: p.time.unwrap_or(now),
+            temperature: p.temperature,
+            humidity: p.humidity,
+            co2: p.co2,
+        })
+        .collect();
+
+    let mut buffer = buffer.lock().await;
+
+    // Keep points in order while older ones are still waiting to be flushed.
+    if buffer.is_empty() {
+        match client.lock().await.write(&points).await {
+            Ok(()) => {
+                records.update(&points, &calibration);
+                return Ok(StatusCode::NO_CONTENT);
+            }
+            Err(e) => eprintln!("Could not write points, buffering them: {e}"),
+        }
+    }
+
+    buffer
+        .push(points.clone())
+        .map_err(|e| ApiError::new(ErrorCode::Internal, e))?;
+    // Buffered points are written later, but they count all the same.
+    records.update(&points, &calibration);
+
+    Ok::<_, ApiError>(StatusCode::ACCEPTED)
+}
@@ -0,0 +1,223 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Query,
+    },
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use serde::Deserialize;
+use tokio::sync::watch;
+
+use influxdb_temp_client::{DataPoint, Field};
+
+use crate::{
+    admin::Stats,
+    problem::{ApiError, ErrorCode},
+    scope::ReadAccess,
+    HttpPassword, SharedState,
+};
+
+pub type Latest = watch::Receiver<Option<DataPoint>>;
+
+/// Periodically poll InfluxDB for the most recent point and publish it to all
+/// live subscribers whenever a newer one shows up.
+pub fn spawn_poller(client: SharedState, interval: Duration, stats: Arc<Stats>) -> Latest {
+    let (sender, rx) = watch::channel(None);
+
+    tokio::spawn(async move {
+        let mut last_time = None;
+        let mut interval = tokio::time::interval(interval);
+
+        loop {
+            interval.tick().await;
+
+            let point = match client.lock().await.get_current().await {
+                Ok(point) => {
+                    stats.task_ran("live_poller", Ok(()));
+                    match point {
+                        Some(point) => point,
+                        None => continue,
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Could not poll latest point: {e}");
+                    stats.task_ran("live_poller", Err(e.message()));
+                    continue;
+                }
+            };
+
+            if last_time.map(|t| point.time > t).unwrap_or(true) {
+                last_time = Some(point.time);
+                sender.send_replace(Some(point));
+            }
+        }
+    });
+
+    rx
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LiveQuery {
+    password: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+struct Subscription {
+    fields: Vec<Field>,
+    /// Minimum amount of milliseconds between two pushed points.
+    min_interval_ms: u64,
+    /// Only push a point if at least one field moved this much since the
+    /// previously pushed point, or a field appeared or disappeared. Fields
+    /// without a threshold are only compared by their presence.
+    threshold: HashMap<Field, f64>,
+}
+
+impl Default for Subscription {
+    fn default() -> Self {
+        Self {
+            fields: Field::ALL.to_vec(),
+            min_interval_ms: 0,
+            threshold: HashMap::new(),
+        }
+    }
+}
+
+impl Subscription {
+    fn should_push(&self, last: Option<&(Instant, DataPoint)>, point: &DataPoint) -> bool {
+        let Some((last_sent, last_point)) = last else {
+            return true;
+        };
+
+        if last_sent.elapsed() < Duration::from_millis(self.min_interval_ms) {
+            return false;
+        }
+
+        if self.threshold.is_empty() {
+            return true;
+        }
+
+        self.fields.iter().any(
+            |field| match (field.value(last_point), field.value(point)) {
+                (Some(last), Some(new)) => self
+                    .threshold
+                    .get(field)
+                    .map_or(false, |threshold| (new - last).abs() >= *threshold),
+                (None, None) => false,
+                _ => true,
+            },
+        )
+    }
+
+    fn render(&self, point: &DataPoint) -> String {
+        let mut message = serde_json::Map::new();
+        message.insert("time".to_string(), point.time.into());
+        for field in &self.fields {
+            message.insert(field.name().to_string(), field.value(point).into());
+        }
+        serde_json::Value::Object(message).to_string()
+    }
+}
+
+pub async fn live(
+    ws: WebSocketUpgrade,
+    Query(query): Query<LiveQuery>,
+    Extension(latest): Extension<Latest>,
+    Extension(password): Extension<HttpPassword>,
+) -> impl IntoResponse {
+    if !password.accepts(&query.password) {
+        return Err(ApiError::new(ErrorCode::Unauthorized, "Invalid password"));
+    }
+
+    Ok(ws.on_upgrade(move |socket| handle_socket(socket, latest)))
+}
+
+async fn handle_socket(mut socket: WebSocket, mut latest: Latest) {
+    // Only push points that arrive after subscribing.
+    latest.borrow_and_update();
+
+    let mut subscription = Subscription::default();
+    let mut last_sent: Option<(Instant, DataPoint)> = None;
+
+    loop {
+        tokio::select! {
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => match serde_json::from_str(&text) {
+                    Ok(new) => {
+                        subscription = new;
+                        last_sent = None;
+                    }
+                    Err(e) => {
+                        let error = serde_json::json!({ "error": e.to_string() }).to_string();
+                        if socket.send(Message::Text(error)).await.is_err() {
+                            return;
+                        }
+                    }
+                },
+                Some(Ok(_)) => {}
+                Some(Err(_)) | None => return,
+            },
+            changed = latest.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+
+                let Some(point) = *latest.borrow_and_update() else {
+                    continue;
+                };
+
+                if !subscription.should_push(last_sent.as_ref(), &point) {
+                    continue;
+                }
+
+                if socket.send(Message::Text(subscription.render(&point))).await.is_err() {
+                    return;
+                }
+
+                last_sent = Some((Instant::now(), point));
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PollQuery {
+    since: i64,
+    timeout_ms: Option<u64>,
+}
+
+/// Hold the request until a point newer than `since` is available, or respond
+/// with 204 once the timeout elapses.
+pub async fn poll(
+    Query(query): Query<PollQuery>,
+    Extension(mut latest): Extension<Latest>,
+    _: ReadAccess,
+) -> impl IntoResponse {
+    let timeout = Duration::from_millis(query.timeout_ms.unwrap_or(30_000).min(120_000));
+
+    let newer = async {
+        loop {
+            let current = *latest.borrow_and_update();
+            if let Some(point) = current.filter(|p| p.time > query.since) {
+                return Some(point);
+            }
+
+            if latest.changed().await.is_err() {
+                return None;
+            }
+        }
+    };
+
+    match tokio::time::timeout(timeout, newer).await {
+        Ok(Some(point)) => Ok(Json(point).into_response()),
+        _ => Ok::<_, ApiError>(StatusCode::NO_CONTENT.into_response()),
+    }
+}
@@ -1,153 +1,1531 @@
-mod client;
+mod access;
+mod admin;
+mod alerts;
+mod analysis;
+mod annotations;
+mod backend;
+mod cache;
+mod calendar;
+mod calibration;
+mod check;
+mod climate;
+mod co2;
+mod comfort;
+mod config;
+mod correction;
+mod encoding;
+mod export;
+#[cfg(feature = "http3")]
+mod http3;
+mod import;
+mod ingest;
+mod live;
+mod notify;
+mod outdoor;
+mod problem;
+mod public;
+mod query;
+mod quota;
+mod records;
+#[cfg(feature = "sentry")]
+mod reporting;
+mod rollup;
+mod scope;
+mod server;
+mod share;
+mod singleflight;
+mod spikes;
+mod summary;
+mod tasks;
+#[cfg(feature = "otel")]
+mod telemetry;
+mod tenant;
+mod vault;
+mod years;
 
 use std::{
+    collections::HashMap,
+    path::PathBuf,
     str::FromStr,
     sync::Arc,
     time::{Duration, Instant},
 };
 
 use axum::{
-    extract::Path,
-    headers::{authorization::Bearer, Authorization},
-    http::StatusCode,
-    response::IntoResponse,
-    routing::{get, get_service},
-    Extension, Router, TypedHeader,
+    body::Body,
+    extract::{Path, Query},
+    handler::HandlerWithoutStateExt,
+    http::{header, HeaderValue, Request, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    routing::{get, get_service, post},
+    Extension, Json, Router,
 };
 
-use clap::Parser;
-use client::Client;
+use chrono::{Offset, TimeZone, Utc};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use duration_string::DurationString;
-use serde::Serialize;
-use tokio::sync::Mutex;
-use tower_http::{add_extension::AddExtensionLayer, services::ServeDir};
+use influxdb_temp_client::{
+    band_points, downsample,
+    format::{self, CompactSeries, ResponseFormat},
+    raw_points, transform, Annotation, BackendError, DataPoint, Field, Limit, MetricPoint,
+    RangeOptions, TimeRange, TimeSeriesBackend, DEFAULT_POINTS,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{watch, Mutex};
+use tower::ServiceExt;
+use tower_http::{
+    add_extension::AddExtensionLayer,
+    compression::CompressionLayer,
+    services::{ServeDir, ServeFile},
+    trace::TraceLayer,
+    CompressionLevel,
+};
+
+use problem::{ApiError, ErrorCode};
+use scope::{ReadAccess, Scope};
 
 #[derive(Parser)]
 struct Opts {
-    #[clap(env = "INFLUXDB_TOKEN")]
-    pub api_token: String,
-    #[clap(env = "INFLUXDB_HOST")]
-    pub host: String,
-    #[clap(env = "INFLUXDB_ORG")]
-    pub org: String,
-    #[clap(env = "HTTP_PASSWORD")]
-    pub http_password: String,
-    #[clap(env = "HTTP_PORT", default_value = "3000")]
+    #[clap(flatten)]
+    pub backend: backend::BackendOpts,
+    #[clap(flatten)]
+    pub vault: vault::VaultOpts,
+    #[clap(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the HTTP server.
+    Serve(ServeOpts),
+    /// Validate the configuration and the connection to InfluxDB.
+    Check(check::CheckOpts),
+    /// Fetch data once and print it to stdout.
+    Query(query::QueryOpts),
+    /// Write historical readings from a CSV file to InfluxDB.
+    Import(import::ImportOpts),
+    /// Write a range of data to a file.
+    Export(export::ExportOpts),
+}
+
+#[derive(Args)]
+struct ServeOpts {
+    /// Required unless it is read from a file or from Vault.
+    #[clap(long, env = "HTTP_PASSWORD")]
+    pub http_password: Option<String>,
+    /// File to read the HTTP password from, e.g. a Docker or Kubernetes
+    /// secret.
+    #[clap(long, env = "HTTP_PASSWORD_FILE", conflicts_with = "http_password")]
+    pub http_password_file: Option<PathBuf>,
+    /// Password for the `/admin` endpoints. They are disabled if not set.
+    #[clap(long, env = "ADMIN_PASSWORD")]
+    pub admin_password: Option<String>,
+    /// File to read the admin password from.
+    #[clap(long, env = "ADMIN_PASSWORD_FILE", conflicts_with = "admin_password")]
+    pub admin_password_file: Option<PathBuf>,
+    /// Additional read-only API keys as `name=token`, which are subject to
+    /// quotas.
+    #[clap(long = "api-key", env = "API_KEYS", value_delimiter = ',', value_parser = parse_api_key)]
+    pub api_keys: Vec<(String, String)>,
+    /// API keys as `name=token` that may also ingest points and use the
+    /// `/admin` endpoints.
+    #[clap(long = "admin-api-key", env = "ADMIN_API_KEYS", value_delimiter = ',', value_parser = parse_api_key)]
+    pub admin_api_keys: Vec<(String, String)>,
+    #[clap(flatten)]
+    pub quota: quota::QuotaOpts,
+    #[clap(flatten)]
+    pub access: access::AccessOpts,
+    #[clap(flatten)]
+    pub share: share::ShareOpts,
+    #[clap(flatten)]
+    pub calibration: calibration::CalibrationOpts,
+    #[clap(flatten)]
+    pub spikes: spikes::SpikeOpts,
+    /// TOML file with `[[token]]` tables of public tokens, each bound to a
+    /// field and a maximum range.
+    #[clap(long, env = "PUBLIC_TOKENS_FILE")]
+    pub public_tokens: Option<PathBuf>,
+    /// TOML file with `[[tenant]]` tables of further houses to serve, each
+    /// under its own prefix with its own bucket, token and password.
+    #[clap(long, env = "TENANTS_FILE")]
+    pub tenants: Option<PathBuf>,
+    #[clap(long, env = "HTTP_PORT", default_value = "3000")]
     pub http_port: u32,
+    /// Directory to serve the frontend from.
+    #[clap(long, env = "STATIC_DIR", default_value = "./static")]
+    pub static_dir: PathBuf,
+    /// Only serve the API, e.g. when the frontend is served by a reverse
+    /// proxy.
+    #[clap(long, env = "NO_STATIC", conflicts_with = "static_dir")]
+    pub no_static: bool,
+    /// Serve `index.html` for unknown pages, so that a frontend with
+    /// client-side routing can handle them.
+    #[clap(long, env = "SPA_FALLBACK", conflicts_with = "no_static")]
+    pub spa_fallback: bool,
+    #[clap(long, env = "ZSTD_LEVEL", default_value = "3")]
+    pub zstd_level: u32,
+    #[clap(long, env = "BROTLI_LEVEL", default_value = "4")]
+    pub brotli_level: u32,
+    #[clap(long, env = "GZIP_LEVEL", default_value = "6")]
+    pub gzip_level: u32,
+    #[clap(long, env = "LIVE_POLL_INTERVAL", default_value = "10s")]
+    pub live_poll_interval: DurationString,
+    /// TOML file with alert rules. Reloaded when it changes.
+    #[clap(long, env = "ALERTS_FILE")]
+    pub alerts_file: Option<PathBuf>,
+    /// SQLite database to store fired alerts in. Kept in memory if not set.
+    #[clap(long, env = "ALERTS_DB")]
+    pub alerts_db: Option<PathBuf>,
+    /// Maximum span of a single range query.
+    #[clap(long, env = "MAX_RANGE", default_value = "90d")]
+    pub max_range: DurationString,
+    /// Maximum amount of points a single range query is expected to return.
+    #[clap(long, env = "MAX_POINTS", default_value = "10000")]
+    pub max_points: u64,
+    /// What to do with queries that would return more than `max_points`.
+    #[clap(long, value_enum, env = "COST_ACTION", default_value = "widen")]
+    pub cost_action: CostAction,
+    /// Longest gap that `?interpolate=linear` fills.
+    #[clap(long, env = "MAX_INTERPOLATION_GAP", default_value = "15m")]
+    pub max_interpolation_gap: DurationString,
+    #[clap(flatten)]
+    pub ingest: ingest::IngestOpts,
+    #[clap(flatten)]
+    pub outdoor: outdoor::OutdoorOpts,
+    #[clap(flatten)]
+    pub comfort: comfort::ComfortOpts,
+    #[clap(flatten)]
+    pub co2: co2::Co2Opts,
+    /// Additional fields of the measurement to serve at `/metric/:name`, e.g.
+    /// `pressure,voc_index,pm25`.
+    #[clap(long = "metric", env = "METRICS", value_delimiter = ',')]
+    pub metrics: Vec<String>,
+    #[clap(flatten)]
+    pub server: server::ServerOpts,
+    /// OTLP (gRPC) endpoint to export traces to.
+    #[cfg(feature = "otel")]
+    #[clap(long, env = "OTEL_EXPORTER_OTLP_ENDPOINT")]
+    pub otlp_endpoint: Option<String>,
+    /// Sentry DSN to report panics and server errors to.
+    #[cfg(feature = "sentry")]
+    #[clap(long, env = "SENTRY_DSN")]
+    pub sentry_dsn: Option<String>,
 }
 
-type SharedState = Arc<Mutex<Client>>;
+/// Where the API is served, so that unknown endpoints aren't mistaken for
+/// static files.
+const API_PREFIX: &str = "/api";
+
+type SharedState = Arc<Mutex<dyn TimeSeriesBackend>>;
+
+#[derive(Debug, Clone)]
+struct ApiKey {
+    name: String,
+    scope: Scope,
+}
 
 #[derive(Debug, Clone)]
-struct HttpPassword(String);
+struct HttpPassword {
+    /// Grants read access. Can be updated from Vault while running.
+    password: watch::Receiver<String>,
+    /// Grants admin access.
+    admin_password: Option<String>,
+    /// Additional API keys, by token.
+    api_keys: Arc<HashMap<String, ApiKey>>,
+}
+
+impl HttpPassword {
+    /// What `token` may do, if it is valid at all.
+    fn scope(&self, token: &str) -> Option<Scope> {
+        if self.admin_password.as_deref() == Some(token) {
+            return Some(Scope::Admin);
+        }
+
+        if let Some(key) = self.api_keys.get(token) {
+            return Some(key.scope);
+        }
+
+        (token == *self.password.borrow()).then_some(Scope::Read)
+    }
+
+    fn accepts(&self, token: &str) -> bool {
+        self.scope(token).is_some()
+    }
+
+    fn admin_enabled(&self) -> bool {
+        self.admin_password.is_some() || self.api_keys.values().any(|k| k.scope == Scope::Admin)
+    }
+
+    /// The name of the API key `token`, if it is one.
+    fn api_key(&self, token: &str) -> Option<&str> {
+        self.api_keys.get(token).map(|k| k.name.as_str())
+    }
+}
+
+/// `value`, or the contents of `file` without surrounding whitespace.
+fn secret(value: Option<String>, file: Option<&std::path::Path>) -> Option<String> {
+    let Some(path) = file else {
+        return value;
+    };
+
+    match std::fs::read_to_string(path) {
+        Ok(secret) => Some(secret.trim().to_string()),
+        Err(e) => {
+            eprintln!("Could not read {}: {e}", path.display());
+            std::process::exit(1);
+        }
+    }
+}
+
+fn parse_api_key(input: &str) -> Result<(String, String), String> {
+    match input.split_once('=') {
+        Some((name, token)) if !token.is_empty() => Ok((name.to_string(), token.to_string())),
+        _ => Err(format!("Expected <name>=<token>, got {input}.")),
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum CostAction {
+    /// Reject the query with 400.
+    Reject,
+    /// Use a larger aggregation window.
+    Widen,
+}
+
+/// The `points` of queries that want their range as finely grained as the
+/// limits allow, to process the points further before returning them.
+const RAW_POINTS: u64 = u64::MAX;
+
+#[derive(Debug, Clone)]
+struct QueryLimits {
+    max_range: Duration,
+    max_points: u64,
+    cost_action: CostAction,
+    max_interpolation_gap: Duration,
+    /// Identical range queries that are running, which new ones join.
+    inflight: singleflight::SingleFlight<(TimeRange, RangeOptions), FetchResult>,
+}
+
+/// Fields that can be queried at `/metric/:name`.
+#[derive(Debug, Clone)]
+struct Metrics(Arc<Vec<String>>);
+
+impl Metrics {
+    fn new(extra: Vec<String>) -> Self {
+        let mut names: Vec<_> = Field::ALL.iter().map(|f| f.name().to_string()).collect();
+        names.extend(extra);
+        Self(Arc::new(names))
+    }
+
+    fn check(&self, name: &str) -> Result<(), ApiError> {
+        if self.0.iter().any(|n| n == name) {
+            Ok(())
+        } else {
+            Err(ApiError::new(
+                ErrorCode::UnknownMetric,
+                format!("Unknown metric {name}."),
+            ))
+        }
+    }
+}
+
+impl QueryLimits {
+    /// Reject ranges longer than `max_range`. Returns the span of `range`.
+    fn check_range(&self, range: &TimeRange) -> Result<Duration, ApiError> {
+        let (start, stop) = range.bounds();
+        let span = match stop.checked_sub(start) {
+            Some(span) => Duration::from_millis(span.max(0) as u64),
+            None => Duration::MAX,
+        };
+
+        if span > self.max_range {
+            return Err(ApiError::new(
+                ErrorCode::RangeTooLarge,
+                format!(
+                    "Range of {} exceeds the maximum of {}. Query a shorter range, or split it into multiple requests.",
+                    DurationString::from(span),
+                    DurationString::from(self.max_range),
+                ),
+            ));
+        }
+
+        Ok(span)
+    }
+
+    fn check(&self, range: &TimeRange, options: &mut RangeOptions) -> Result<(), ApiError> {
+        let span = self.check_range(range)?;
+
+        let window = options.window(range).max(1);
+        let expected = span.as_millis() as u64 / window;
+
+        // Raw queries ask for more points than they return, so only the
+        // window is up to them.
+        let action = match options.points {
+            Some(RAW_POINTS) => CostAction::Widen,
+            _ => self.cost_action,
+        };
+
+        if expected > self.max_points {
+            match action {
+                CostAction::Reject => {
+                    return Err(ApiError::new(
+                        ErrorCode::TooManyPoints,
+                        format!(
+                            "Query would return about {expected} points, more than the maximum of {}. Use a larger window or a shorter range.",
+                            self.max_points
+                        ),
+                    ))
+                }
+                CostAction::Widen => {
+                    let max_points = self.max_points.max(1);
+                    let wider = (span.as_millis() as u64).div_ceil(max_points);
+                    options.window_ms = Some(wider);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
 
 #[tokio::main]
 async fn main() {
-    let opts = Opts::parse();
+    let mut opts = Opts::parse();
+    calendar::init(opts.backend.timezone);
+
+    let secrets = vault::load(&opts.vault).await;
+    let http_password = secrets.as_ref().and_then(|s| s.http_password.clone());
+    #[cfg(feature = "influxql")]
+    {
+        opts.backend.vault_influxql_password =
+            secrets.as_ref().and_then(|s| s.influxql_password.clone());
+    }
+    opts.backend.vault_token = secrets.and_then(|s| s.influxdb_token);
 
-    run(opts).await;
+    match opts.command {
+        Command::Serve(serve_opts) => {
+            let rollups = opts.backend.rollups();
+            let backend = opts.backend.open(rollups.clone()).await;
+            let rollups = rollups.map(|rollups| rollup::Setup {
+                client: opts.backend.influxdb(),
+                rollups,
+                tasks: (opts.backend.rollup_mode == backend::RollupMode::Tasks)
+                    .then(|| opts.backend.task_api()),
+            });
+            let tenants = match &serve_opts.tenants {
+                Some(path) => open_tenants(path, &opts.backend).await,
+                None => Vec::new(),
+            };
+            serve(backend, rollups, tenants, http_password, serve_opts).await
+        }
+        Command::Check(check_opts) => check::run(opts.backend.influxdb(), check_opts).await,
+        Command::Query(query_opts) => query::run(opts.backend.connect().await, query_opts).await,
+        Command::Import(import_opts) => import::run(opts.backend.influxdb(), import_opts).await,
+        Command::Export(export_opts) => {
+            export::run(opts.backend.connect().await, export_opts).await
+        }
+    }
 }
 
-async fn run(opts: Opts) {
-    let client = influxdb2::Client::new(opts.host, opts.org, opts.api_token);
-    let mut client = Client::new(client);
+async fn open_tenants(
+    path: &std::path::Path,
+    base: &backend::BackendOpts,
+) -> Vec<(tenant::Tenant, Box<dyn TimeSeriesBackend>)> {
+    let tenants = match tenant::load(path) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
 
-    client.get_current_temp().await.unwrap();
-    client
-        .get_data_in_span(Duration::from_secs(1000))
-        .await
-        .unwrap()
-        .next();
+    let mut opened = Vec::new();
+    for tenant in tenants {
+        println!("Serving tenant {} at {}", tenant.name, tenant.prefix);
+        let backend = tenant.backend(base).open(None).await;
+        opened.push((tenant, backend));
+    }
+
+    opened
+}
+
+async fn serve(
+    backend: Box<dyn TimeSeriesBackend>,
+    rollups: Option<rollup::Setup>,
+    tenants: Vec<(tenant::Tenant, Box<dyn TimeSeriesBackend>)>,
+    vault_password: Option<watch::Receiver<String>>,
+    opts: ServeOpts,
+) {
+    let password = match vault_password {
+        Some(password) => password,
+        None => match secret(opts.http_password, opts.http_password_file.as_deref()) {
+            Some(password) => watch::channel(password).1,
+            None => {
+                eprintln!("HTTP_PASSWORD, HTTP_PASSWORD_FILE or a Vault secret is required");
+                std::process::exit(2);
+            }
+        },
+    };
+
+    let public_tokens = match &opts.public_tokens {
+        Some(path) => match public::load(path) {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        },
+        None => public::PublicTokens::default(),
+    };
+
+    let calibration = match calibration::Calibration::new(&opts.calibration) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(2);
+        }
+    };
+
+    let spikes = match spikes::SpikeFilter::new(&opts.spikes) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(2);
+        }
+    };
+
+    let stats = admin::Stats::new();
+    let client: SharedState = Arc::new(Mutex::new(admin::Monitored::new(
+        calibrated(backend, &calibration),
+        stats.clone(),
+    )));
+
+    #[cfg(feature = "otel")]
+    if let Some(endpoint) = &opts.otlp_endpoint {
+        telemetry::init(endpoint);
+    }
+
+    #[cfg(feature = "sentry")]
+    let _sentry = opts.sentry_dsn.as_deref().map(reporting::init);
+
+    {
+        let mut backend = client.lock().await;
+        backend.get_current().await.unwrap();
+        backend
+            .get_range(
+                TimeRange::Span(Duration::from_secs(1000)),
+                &RangeOptions::default(),
+            )
+            .await
+            .unwrap();
+    }
+
+    let latest = live::spawn_poller(
+        client.clone(),
+        opts.live_poll_interval.into(),
+        stats.clone(),
+    );
+
+    let history = match alerts::History::open(opts.alerts_db.as_deref()) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
 
-    let client = Arc::new(Mutex::new(client));
+    if let Some(path) = opts.alerts_file.clone() {
+        alerts::spawn(path, latest.clone(), history.clone(), client.clone());
+    }
+
+    if let Some(rollups) = rollups {
+        rollup::spawn(rollups, stats.clone());
+    }
+
+    let buffer = ingest::buffer(
+        opts.ingest.ingest_buffer_size,
+        opts.ingest.ingest_spill_file.clone(),
+    );
+    ingest::spawn_flusher(
+        client.clone(),
+        buffer.clone(),
+        opts.ingest.ingest_flush_interval.into(),
+        stats.clone(),
+    );
 
-    let app = Router::new()
+    let api = Router::new()
+        .route("/config.json", get(config::config))
         .route("/temp/current", get(current_temp))
+        .route("/temp/live", get(live::live))
+        .route("/temp/poll", get(live::poll))
         .route("/data/range/:range", get(data_range))
         .route("/data/from/:start/to/:stop", get(data_range_start_end))
-        .fallback(get_service(ServeDir::new("./static")).handle_error(handle_error))
+        .route("/ingest", post(ingest::ingest))
+        .route("/meta/retention", get(retention))
+        .route("/records", get(records::records))
+        .route("/annotations", post(annotations::create))
+        .route("/annotations/range/:range", get(annotations::range))
+        .route(
+            "/annotations/from/:start/to/:stop",
+            get(annotations::range_start_end),
+        )
+        .route("/alerts/history", get(alerts::history))
+        .route("/alerts/feed.atom", get(alerts::feed))
+        .route("/compare/outdoor/:range", get(outdoor::compare))
+        .route("/compare/years/from/:start/to/:stop", get(years::compare))
+        .route("/comfort/index/:range", get(comfort::index))
+        .route("/co2/status", get(co2::status))
+        .route("/co2/recommendation", get(co2::recommendation))
+        .route("/stats/twa/:field/:range", get(analysis::twa))
+        .route("/analysis/degree-days/:range", get(analysis::degree_days))
+        .route("/analysis/mold-risk/:range", get(analysis::mold_risk_index))
+        .route("/analysis/air-exchange/:range", get(analysis::air_exchange))
+        .route("/analysis/occupancy/:range", get(analysis::occupancy))
+        .route("/public/:token/current", get(public::current))
+        .route("/public/:token/range/:range", get(public::range))
+        .route("/share", post(share::create))
+        .route("/share/:signature", get(share::shared))
+        .route("/admin/stats", get(admin::stats))
+        .route("/admin/metrics", get(admin::metrics))
+        .route("/admin/cache/clear", post(admin::clear_cache))
+        .route("/admin/points/delete", post(correction::delete))
+        .route("/admin/points/overwrite", post(correction::overwrite))
+        .route("/metric/:name/range/:range", get(named_metric_range))
+        .route(
+            "/metric/:name/from/:start/to/:stop",
+            get(named_metric_range_start_end),
+        )
+        .nest(
+            metric_prefix(Field::Temperature),
+            metric_routes(Field::Temperature),
+        )
+        .nest(
+            metric_prefix(Field::Humidity),
+            metric_routes(Field::Humidity),
+        )
+        .nest(metric_prefix(Field::Co2), metric_routes(Field::Co2))
+        .layer(axum::middleware::from_fn(calibration::note));
+
+    // The unprefixed paths are kept for existing clients, but unknown paths
+    // there fall through to the static files.
+    let mut routes = Router::new()
+        .nest(API_PREFIX, api.clone().fallback(api_not_found))
+        .merge(api.clone());
+
+    // Tenants share everything but the state that belongs to their data,
+    // which their inner layers replace.
+    for (tenant, backend) in tenants {
+        let Some(password) = tenant.http_password() else {
+            eprintln!("Tenant {} has no HTTP password", tenant.name);
+            std::process::exit(2);
+        };
+
+        let calibration = tenant.calibration();
+        let client: SharedState = Arc::new(Mutex::new(admin::Monitored::new(
+            calibrated(backend, &calibration),
+            stats.clone(),
+        )));
+        let latest = live::spawn_poller(
+            client.clone(),
+            opts.live_poll_interval.into(),
+            stats.clone(),
+        );
+
+        let buffer = ingest::buffer(
+            opts.ingest.ingest_buffer_size,
+            tenant.ingest_spill_file.clone(),
+        );
+        ingest::spawn_flusher(
+            client.clone(),
+            buffer.clone(),
+            opts.ingest.ingest_flush_interval.into(),
+            stats.clone(),
+        );
+
+        let history = match alerts::History::open(None) {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        };
+
+        let tenant_routes = Router::new()
+            .nest(API_PREFIX, api.clone().fallback(api_not_found))
+            .merge(api.clone())
+            .layer(AddExtensionLayer::new(client))
+            .layer(AddExtensionLayer::new(latest))
+            .layer(AddExtensionLayer::new(buffer))
+            .layer(AddExtensionLayer::new(history))
+            .layer(AddExtensionLayer::new(calibration))
+            .layer(AddExtensionLayer::new(records::RecordsCache::default()))
+            // Public tokens are configured for the default tenant only.
+            .layer(AddExtensionLayer::new(public::PublicTokens::default()))
+            .layer(AddExtensionLayer::new(HttpPassword {
+                password: watch::channel(password).1,
+                admin_password: tenant.admin_password(),
+                api_keys: Arc::default(),
+            }))
+            .layer(AddExtensionLayer::new(QueryLimits {
+                max_range: opts.max_range.into(),
+                max_points: opts.max_points,
+                cost_action: opts.cost_action,
+                max_interpolation_gap: opts.max_interpolation_gap.into(),
+                inflight: singleflight::SingleFlight::new(),
+            }))
+            .layer(AddExtensionLayer::new(config::ApiBase(format!(
+                "{}{API_PREFIX}",
+                tenant.prefix
+            ))));
+
+        routes = routes.nest(&tenant.prefix, tenant_routes);
+    }
+
+    let routes = if opts.no_static {
+        routes
+    } else {
+        // Serve `.br` and `.gz` siblings of static files as-is when the
+        // client accepts them, instead of compressing them on every request.
+        let files = ServeDir::new(&opts.static_dir)
+            .precompressed_br()
+            .precompressed_gzip();
+
+        if opts.spa_fallback {
+            let index = opts.static_dir.join("index.html");
+            let index = move |request: Request<Body>| spa_index(index.clone(), request);
+            routes.fallback(get_service(files.fallback(index.into_service())))
+        } else {
+            routes.fallback(get_service(files))
+        }
+    };
+
+    let app = routes
         .layer(AddExtensionLayer::new(client))
-        .layer(AddExtensionLayer::new(HttpPassword(opts.http_password)));
+        .layer(AddExtensionLayer::new(latest))
+        .layer(AddExtensionLayer::new(buffer))
+        .layer(AddExtensionLayer::new(history))
+        .layer(AddExtensionLayer::new(records::RecordsCache::default()))
+        .layer(AddExtensionLayer::new(outdoor::Outdoor::new(&opts.outdoor)))
+        .layer(AddExtensionLayer::new(opts.comfort))
+        .layer(AddExtensionLayer::new(opts.co2))
+        .layer(AddExtensionLayer::new(share::Signer::new(&opts.share)))
+        .layer(AddExtensionLayer::new(public_tokens))
+        .layer(AddExtensionLayer::new(calibration))
+        .layer(AddExtensionLayer::new(spikes))
+        .layer(axum::middleware::from_fn(admin::count_requests))
+        .layer(AddExtensionLayer::new(stats))
+        .layer(axum::middleware::from_fn(quota::enforce))
+        .layer(AddExtensionLayer::new(quota::Quotas::new(&opts.quota)))
+        .layer(AddExtensionLayer::new(HttpPassword {
+            password,
+            admin_password: secret(opts.admin_password, opts.admin_password_file.as_deref()),
+            api_keys: Arc::new(
+                opts.api_keys
+                    .into_iter()
+                    .map(|key| (key, Scope::Read))
+                    .chain(
+                        opts.admin_api_keys
+                            .into_iter()
+                            .map(|key| (key, Scope::Admin)),
+                    )
+                    .map(|((name, token), scope)| (token, ApiKey { name, scope }))
+                    .collect(),
+            ),
+        }))
+        .layer(AddExtensionLayer::new(Metrics::new(opts.metrics)))
+        .layer(AddExtensionLayer::new(config::ApiBase(
+            API_PREFIX.to_string(),
+        )))
+        .layer(AddExtensionLayer::new(QueryLimits {
+            max_range: opts.max_range.into(),
+            max_points: opts.max_points,
+            cost_action: opts.cost_action,
+            max_interpolation_gap: opts.max_interpolation_gap.into(),
+            inflight: singleflight::SingleFlight::new(),
+        }))
+        // Outside of the quota middleware, so that connections are filtered
+        // before they are authenticated.
+        .layer(axum::middleware::from_fn(access::enforce))
+        .layer(AddExtensionLayer::new(access::AccessList::new(
+            &opts.access,
+        )))
+        // Outside of everything that can fail a request, but inside
+        // compression so that error bodies are still plain text here.
+        .layer(axum::middleware::from_fn(problem::problems));
+
+    // Inside compression too, so that reported bodies are readable.
+    #[cfg(feature = "sentry")]
+    let app = app.layer(axum::middleware::from_fn(reporting::report_server_errors));
+
+    let app = app
+        // Each layer only handles a single algorithm so that they can be tuned
+        // separately. `encoding::negotiate` leaves only the one the client
+        // prefers in `Accept-Encoding`, so at most one of them applies.
+        .layer(
+            CompressionLayer::new()
+                .no_br()
+                .no_gzip()
+                .quality(CompressionLevel::Precise(opts.zstd_level)),
+        )
+        .layer(
+            CompressionLayer::new()
+                .no_zstd()
+                .no_gzip()
+                .quality(CompressionLevel::Precise(opts.brotli_level)),
+        )
+        .layer(
+            CompressionLayer::new()
+                .no_zstd()
+                .no_br()
+                .quality(CompressionLevel::Precise(opts.gzip_level)),
+        )
+        .layer(axum::middleware::from_fn(encoding::negotiate))
+        .layer(
+            TraceLayer::new_for_http().make_span_with(|request: &axum::http::Request<_>| {
+                tracing::info_span!(
+                    "request",
+                    method = %request.method(),
+                    uri = %request.uri(),
+                    client = tracing::field::Empty,
+                )
+            }),
+        );
 
     let addr = format!("[::]:{}", opts.http_port).parse().unwrap();
 
     println!("Starting server on port {}", opts.http_port);
 
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
+    server::serve(app, addr, opts.server).await;
 }
 
-async fn handle_error(_err: std::io::Error) -> impl axum::response::IntoResponse {
-    (
-        axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-        "Something went wrong...",
+/// `backend` with `calibration` applied, if there is any.
+fn calibrated(
+    backend: Box<dyn TimeSeriesBackend>,
+    calibration: &calibration::Calibration,
+) -> Box<dyn TimeSeriesBackend> {
+    if calibration.is_empty() {
+        backend
+    } else {
+        Box::new(calibration::Calibrated::new(backend, calibration.clone()))
+    }
+}
+
+async fn api_not_found(uri: Uri) -> ApiError {
+    ApiError::new(
+        ErrorCode::NotFound,
+        format!("No endpoint at {API_PREFIX}{}", uri.path()),
     )
 }
 
-fn check_password(
-    password: String,
-    input: TypedHeader<Authorization<Bearer>>,
-) -> Result<(), (axum::http::StatusCode, String)> {
-    let input_password = input.token();
+/// Serve `index` for page loads of paths that aren't files. Other requests,
+/// e.g. for missing assets or mistyped API paths, still get a 404.
+async fn spa_index(index: PathBuf, request: Request<Body>) -> Response {
+    let page_load = request
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .map_or(false, |accept| accept.contains("text/html"));
 
-    if password != input_password {
-        return Err((StatusCode::UNAUTHORIZED, "Invalid password".to_string()));
-    } else {
-        Ok(())
+    if !page_load {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    match ServeFile::new(index)
+        .precompressed_br()
+        .precompressed_gzip()
+        .oneshot(request)
+        .await
+    {
+        Ok(response) => response.into_response(),
+        Err(infallible) => match infallible {},
     }
 }
 
-async fn current_temp(Extension(client): Extension<SharedState>) -> impl IntoResponse {
-    match client.lock().await.get_current_temp().await {
-        Some(temp) => Ok(format!("{:.02}", temp)),
-        None => Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "Could not get current temperature".to_string(),
+async fn current_temp(
+    Extension(client): Extension<SharedState>,
+    Extension(latest): Extension<live::Latest>,
+) -> impl IntoResponse {
+    let (point, stale) = match client.lock().await.get_current().await {
+        Ok(point) => (point, false),
+        // Fall back to the last point the live poller saw.
+        Err(e) => match *latest.borrow() {
+            Some(point) => {
+                eprintln!("Serving stale current temperature: {e}");
+                (Some(point), true)
+            }
+            None => return Err(ApiError::backend(e)),
+        },
+    };
+
+    match point {
+        Some(DataPoint {
+            temperature: Some(temp),
+            ..
+        }) => Ok(mark_stale(format!("{:.02}", temp).into_response(), stale)),
+        _ => Err(ApiError::new(
+            ErrorCode::NoData,
+            "Could not get current temperature",
         )),
     }
 }
 
-fn to_json<S: Serialize>(input: Vec<S>) -> Result<String, (StatusCode, String)> {
+/// Add a `Warning` header to responses that were served from the local cache
+/// because the backend was unreachable.
+fn mark_stale(mut response: Response, stale: bool) -> Response {
+    if stale {
+        response.headers_mut().insert(
+            header::WARNING,
+            HeaderValue::from_static("110 - \"Response is Stale\""),
+        );
+    }
+    response
+}
+
+async fn retention(Extension(client): Extension<SharedState>, _: ReadAccess) -> impl IntoResponse {
+    match client.lock().await.get_retention().await {
+        Ok(retention) => Ok(Json(retention)),
+        Err(e) => Err(ApiError::backend(e)),
+    }
+}
+
+fn to_json<S: Serialize>(input: &S) -> Result<String, ApiError> {
+    let _span = tracing::info_span!("serialize").entered();
+
     let start = Instant::now();
-    let output = match serde_json::to_string(&input) {
+    let output = match serde_json::to_string(input) {
         Ok(v) => v,
-        Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, format!("{e}"))),
+        Err(e) => return Err(ApiError::new(ErrorCode::Internal, e.to_string())),
     };
     println!("Took {} ms to serialize", start.elapsed().as_millis());
 
     Ok(output)
 }
 
-async fn data_range_start_end(
-    Path((start, stop)): Path<(u64, u64)>,
-    Extension(client): Extension<SharedState>,
-    Extension(HttpPassword(password)): Extension<HttpPassword>,
-    auth: TypedHeader<Authorization<Bearer>>,
-) -> impl IntoResponse {
-    check_password(password, auth)?;
+/// Add `X-Point-Count` and `X-Truncated` headers.
+fn mark_count(mut response: Response, count: usize, truncated: bool) -> Response {
+    let headers = response.headers_mut();
+    headers.insert("x-point-count", HeaderValue::from(count));
+    headers.insert(
+        "x-truncated",
+        HeaderValue::from_static(if truncated { "true" } else { "false" }),
+    );
+    response
+}
+
+/// Points in an object with the flags that are otherwise only headers, for
+/// clients that can't read those.
+#[derive(Debug, Serialize)]
+struct Envelope<T> {
+    points: T,
+    stale: bool,
+}
+
+/// `points` as JSON, wrapped in an [`Envelope`] if requested.
+fn points_json<S: Serialize>(points: S, stale: bool, envelope: bool) -> Result<String, ApiError> {
+    if envelope {
+        to_json(&Envelope { points, stale })
+    } else {
+        to_json(&points)
+    }
+}
 
-    let start_time = Instant::now();
-    let temps: Vec<_> = match client.lock().await.get_data_from_to(start, stop).await {
-        Ok(v) => v.collect(),
-        Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, format!("{e}"))),
+fn respond(fetched: Fetched, format: ResponseFormat, envelope: bool) -> Result<Response, ApiError> {
+    let Fetched {
+        points,
+        stale,
+        truncated,
+        ..
+    } = fetched;
+
+    let response = match format {
+        ResponseFormat::Json => points_json(&points, stale, envelope)?.into_response(),
+        ResponseFormat::Compact => {
+            let mut series = CompactSeries::new(&points);
+            series.stale = stale;
+            to_json(&series)?.into_response()
+        }
+        ResponseFormat::Binary => (
+            [(header::CONTENT_TYPE, "application/octet-stream")],
+            format::encode_binary(&points),
+        )
+            .into_response(),
     };
 
+    let response = mark_count(response, points.len(), truncated);
+    Ok(mark_stale(response, stale))
+}
+
+#[derive(Debug, Serialize)]
+struct Annotated<T> {
+    points: T,
+    annotations: Vec<Annotation>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stale: Option<bool>,
+}
+
+/// Like [`respond`], but with the points and `annotations` in one object.
+fn respond_annotated(
+    fetched: Fetched,
+    format: ResponseFormat,
+    envelope: bool,
+    annotations: Option<Vec<Annotation>>,
+) -> Result<Response, ApiError> {
+    let Some(annotations) = annotations else {
+        return respond(fetched, format, envelope);
+    };
+
+    let Fetched {
+        points,
+        stale,
+        truncated,
+        ..
+    } = fetched;
+
+    let response = match format {
+        ResponseFormat::Json => to_json(&Annotated {
+            points: &points,
+            annotations,
+            stale: envelope.then_some(stale),
+        })?,
+        ResponseFormat::Compact => {
+            let mut series = CompactSeries::new(&points);
+            series.stale = stale;
+            to_json(&Annotated {
+                points: series,
+                annotations,
+                stale: None,
+            })?
+        }
+        ResponseFormat::Binary => {
+            return Err(ApiError::new(
+                ErrorCode::BadRequest,
+                "Annotations can't be included in binary responses.",
+            ))
+        }
+    };
+
+    let response = mark_count(response.into_response(), points.len(), truncated);
+    Ok(mark_stale(response, stale))
+}
+
+/// How to reduce a range to the requested amount of points.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Downsample {
+    /// Largest-Triangle-Three-Buckets on raw data, which keeps spikes that
+    /// averaging would flatten.
+    Lttb,
+}
+
+/// What to drop from a range before it is aggregated.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Filter {
+    /// Values outside the plausible range of their field, or that jump away
+    /// from both neighbours by more than the field's maximum step.
+    Spikes,
+}
+
+/// The `filter` parameter of endpoints that serve stored aggregates, which
+/// can't be filtered.
+#[derive(Debug, Deserialize)]
+struct NoFilter {
+    filter: Option<Filter>,
+}
+
+impl NoFilter {
+    /// Reject a filter, instead of serving unfiltered values as if they were.
+    fn check(&self) -> Result<(), ApiError> {
+        match self.filter {
+            Some(_) => Err(ApiError::new(
+                ErrorCode::BadRequest,
+                "This endpoint can't be filtered.",
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The `format` parameter of endpoints that only respond with JSON.
+#[derive(Debug, Deserialize)]
+struct JsonOnly {
+    #[serde(default)]
+    format: ResponseFormat,
+}
+
+impl JsonOnly {
+    /// Reject other formats, instead of ignoring them.
+    fn check(&self) -> Result<(), ApiError> {
+        match self.format {
+            ResponseFormat::Json => Ok(()),
+            _ => Err(ApiError::new(
+                ErrorCode::BadRequest,
+                "This endpoint only responds with JSON.",
+            )),
+        }
+    }
+}
+
+/// Where the windows of a resampled range start.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Align {
+    Epoch,
+    /// Midnight in the configured time zone, as of the start of the range.
+    Midnight,
+}
+
+/// How to fill gaps in a range.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Interpolation {
+    Linear,
+}
+
+#[derive(Debug, Deserialize)]
+struct RangeParams {
+    since: Option<i64>,
+    #[serde(default)]
+    format: ResponseFormat,
+    fields: Option<String>,
+    /// Only return the oldest `limit` points.
+    limit: Option<usize>,
+    /// Only return the newest `last` points.
+    last: Option<usize>,
+    /// About how many points to return, e.g. the width of the chart.
+    points: Option<u64>,
+    /// Aggregation window, e.g. `5m`. Derived from `points` if not set.
+    window: Option<DurationString>,
+    /// Put the timestamps exactly on the boundaries of the window, counted
+    /// from the epoch or from local midnight, so that ranges of different
+    /// servers can be joined.
+    resample: Option<Align>,
+    /// Never return more than this many points.
+    max_points: Option<usize>,
+    downsample: Option<Downsample>,
+    /// E.g. `ema:0.2`: an exponential moving average where the newest value
+    /// weighs 0.2.
+    smooth: Option<String>,
+    filter: Option<Filter>,
+    /// Fill gaps up to the configured maximum, for a regular series.
+    interpolate: Option<Interpolation>,
+    /// Include the annotations in the range next to the points.
+    #[serde(default)]
+    annotations: bool,
+    /// Return JSON points in an object that also says whether they are
+    /// stale, instead of only in the `Warning` header.
+    #[serde(default)]
+    envelope: bool,
+}
+
+impl RangeParams {
+    fn options(&self) -> Result<RangeOptions, ApiError> {
+        let fields = match &self.fields {
+            Some(fields) => fields
+                .split(',')
+                .map(str::parse)
+                .collect::<Result<_, _>>()
+                .map_err(|e| ApiError::new(ErrorCode::UnknownField, e))?,
+            None => Vec::new(),
+        };
+
+        let limit = match (self.limit, self.last) {
+            (None, None) => None,
+            (Some(n), None) => Some(Limit::First(n)),
+            (None, Some(n)) => Some(Limit::Last(n)),
+            (Some(_), Some(_)) => {
+                return Err(ApiError::new(
+                    ErrorCode::BadRequest,
+                    "Only one of limit and last can be given.",
+                ))
+            }
+        };
+
+        if self.downsample.is_some() && self.resample.is_some() {
+            return Err(ApiError::new(
+                ErrorCode::BadRequest,
+                "Only one of downsample and resample can be given.",
+            ));
+        }
+
+        let window_ms = self.window_ms()?;
+
+        // Downsampling, filtering and resampling need the raw data, or as
+        // close to it as the query limits allow. `points` and `window` are
+        // their target instead.
+        let raw = self.downsample.is_some() || self.filter.is_some() || self.resample.is_some();
+        let (points, window_ms) = if raw {
+            (Some(RAW_POINTS), None)
+        } else {
+            (self.points, window_ms)
+        };
+
+        Ok(RangeOptions {
+            since: self.since,
+            fields,
+            window_ms,
+            limit,
+            points,
+            ..Default::default()
+        })
+    }
+
+    fn window_ms(&self) -> Result<Option<u64>, ApiError> {
+        match self.window.map(Duration::from) {
+            Some(window) if window.is_zero() => Err(ApiError::new(
+                ErrorCode::BadRequest,
+                "The window must be longer than zero.",
+            )),
+            window => Ok(window.map(|w| w.as_millis() as u64)),
+        }
+    }
+
+    /// The weight of the newest value in the moving average, if smoothing
+    /// was requested.
+    fn smoothing(&self) -> Result<Option<f64>, ApiError> {
+        let Some(smooth) = &self.smooth else {
+            return Ok(None);
+        };
+
+        match smooth.split_once(':') {
+            Some(("ema", alpha)) => match alpha.parse::<f64>() {
+                Ok(alpha) if alpha > 0. && alpha <= 1. => Ok(Some(alpha)),
+                _ => Err(ApiError::new(
+                    ErrorCode::BadRequest,
+                    format!("Invalid EMA weight {alpha}, expected a number in (0, 1]."),
+                )),
+            },
+            _ => Err(ApiError::new(
+                ErrorCode::BadRequest,
+                format!("Unknown smoothing {smooth}, expected ema:<weight>."),
+            )),
+        }
+    }
+
+    /// The window and origin to aggregate the points of `range` into after
+    /// they were fetched raw for filtering or resampling, unless they are
+    /// downsampled instead.
+    fn aggregation(&self, range: &TimeRange) -> Result<Option<(u64, i64)>, ApiError> {
+        if self.downsample.is_some() || (self.filter.is_none() && self.resample.is_none()) {
+            return Ok(None);
+        }
+
+        let window_ms = match self.window_ms()? {
+            Some(window_ms) => window_ms,
+            None => range.window_for(self.points.unwrap_or(DEFAULT_POINTS)),
+        };
+
+        let origin_ms = match self.resample {
+            Some(Align::Midnight) => local_midnight(range),
+            Some(Align::Epoch) | None => 0,
+        };
+
+        Ok(Some((window_ms, origin_ms)))
+    }
+
+    /// The amount of points to downsample to, if downsampling was requested.
+    fn downsample_to(&self) -> Option<usize> {
+        self.downsample
+            .map(|_| self.points.unwrap_or(DEFAULT_POINTS) as usize)
+    }
+
+    /// Fetch `range` with `options`. Filtered and downsampled ranges are
+    /// fetched raw, so that spikes are dropped before they end up in a
+    /// window, or kept by the downsampling.
+    async fn fetch(
+        &self,
+        client: &SharedState,
+        limits: &QueryLimits,
+        range: TimeRange,
+        options: &RangeOptions,
+    ) -> FetchResult {
+        if self.filter.is_some() || self.downsample.is_some() {
+            fetch_raw(client, limits, range, options).await
+        } else {
+            fetch(client, limits, range, options).await
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Fetched {
+    points: Vec<DataPoint>,
+    /// The backend was unreachable and `points` come from the local cache.
+    stale: bool,
+    /// Points were dropped because of the client's `max_points`.
+    truncated: bool,
+    /// The aggregation window of `points`.
+    window_ms: u64,
+}
+
+impl Fetched {
+    /// Drop implausible values.
+    fn filter(mut self, filter: Option<Filter>, spikes: &spikes::SpikeFilter) -> Self {
+        if let Some(Filter::Spikes) = filter {
+            transform::drop_spikes(&mut self.points, spikes.plausible());
+        }
+        self
+    }
+
+    /// Aggregate into windows of `window_ms` that start at `origin_ms`, if
+    /// given.
+    fn aggregate(mut self, aggregation: Option<(u64, i64)>) -> Self {
+        if let Some((window_ms, origin_ms)) = aggregation {
+            self.points = transform::mean_windows(&self.points, window_ms, origin_ms);
+            self.window_ms = window_ms;
+        }
+        self
+    }
+
+    /// Fill gaps of up to `max_gap` at the aggregation window.
+    fn interpolate(mut self, interpolation: Option<Interpolation>, max_gap: Duration) -> Self {
+        if let Some(Interpolation::Linear) = interpolation {
+            transform::interpolate(&mut self.points, self.window_ms, max_gap.as_millis() as u64);
+        }
+        self
+    }
+
+    /// Apply an exponential moving average with weight `alpha`.
+    fn smooth(mut self, alpha: Option<f64>) -> Self {
+        if let Some(alpha) = alpha {
+            transform::ema(&mut self.points, alpha);
+        }
+        self
+    }
+
+    /// Downsample `fields` (all fields if empty) to about `points` points.
+    fn downsample(mut self, fields: &[Field], points: Option<usize>) -> Self {
+        if let Some(points) = points {
+            let fields = if fields.is_empty() {
+                &Field::ALL[..]
+            } else {
+                fields
+            };
+            self.points = downsample::lttb_points(&self.points, fields, points);
+        }
+        self
+    }
+
+    /// Drop all but the first `max_points` points.
+    fn cap(mut self, max_points: Option<usize>) -> Self {
+        if let Some(max) = max_points {
+            self.truncated = self.points.len() > max;
+            self.points.truncate(max);
+        }
+        self
+    }
+}
+
+/// The origin of windows that start at local midnight: the UTC offset of the
+/// configured time zone at the start of `range`, negated. Windows that divide a
+/// day then start at local midnight, until the offset changes within the
+/// range.
+fn local_midnight(range: &TimeRange) -> i64 {
+    let (start, _) = range.bounds();
+
+    let offset = match Utc.timestamp_millis_opt(start).single() {
+        Some(start) => calendar::timezone()
+            .offset_from_utc_datetime(&start.naive_utc())
+            .fix()
+            .local_minus_utc(),
+        None => 0,
+    };
+
+    -(offset as i64) * 1000
+}
+
+/// Logs queries that are dropped before they finish.
+///
+/// When a client disconnects, the server drops the handler future and with it
+/// the in-flight query, so nothing is fetched or serialized for nobody. This
+/// must stay that way: don't move queries into spawned tasks.
+struct CancelGuard {
+    start: Instant,
+    done: bool,
+}
+
+impl Drop for CancelGuard {
+    fn drop(&mut self) {
+        if !self.done {
+            println!(
+                "Client disconnected, cancelled query after {} ms",
+                self.start.elapsed().as_millis()
+            );
+        }
+    }
+}
+
+type FetchResult = Result<Fetched, ApiError>;
+
+async fn fetch(
+    client: &SharedState,
+    limits: &QueryLimits,
+    range: TimeRange,
+    options: &RangeOptions,
+) -> FetchResult {
+    let mut options = options.clone();
+    limits.check(&range, &mut options)?;
+
+    let client = client.clone();
+    let key = (range, options.clone());
+    limits
+        .inflight
+        .run(key, move || async move {
+            fetch_uncoalesced(&client, range, &options).await
+        })
+        .await
+}
+
+async fn fetch_uncoalesced(
+    client: &SharedState,
+    range: TimeRange,
+    options: &RangeOptions,
+) -> FetchResult {
+    let start = Instant::now();
+    let mut guard = CancelGuard { start, done: false };
+    let mut client = client.lock().await;
+
+    let (mut temps, stale) = match client.get_range(range, options).await {
+        Ok(v) => (v, false),
+        Err(e) => match client.get_cached_range(range, options).await {
+            Ok(Some(v)) => {
+                eprintln!("Serving stale data: {e}");
+                (v, true)
+            }
+            _ => {
+                guard.done = true;
+                return Err(ApiError::backend(e));
+            }
+        },
+    };
+    guard.done = true;
+    options.apply_limit(&mut temps);
+
     println!(
         "Took {} ms to fetch {} temperature measurements",
-        start_time.elapsed().as_millis(),
+        start.elapsed().as_millis(),
         temps.len()
     );
 
-    to_json(temps)
+    Ok(Fetched {
+        points: temps,
+        stale,
+        truncated: false,
+        window_ms: options.window(&range),
+    })
 }
 
-fn get_range(input: &str) -> Result<Duration, (StatusCode, String)> {
+/// Fetch the stored points of the fields of `options` in `range`, merged by
+/// time. Falls back to [`fetch`] for backends that can't return them, and
+/// for ranges that the limits only allow in wider windows.
+async fn fetch_raw(
+    client: &SharedState,
+    limits: &QueryLimits,
+    range: TimeRange,
+    options: &RangeOptions,
+) -> FetchResult {
+    let mut checked = options.clone();
+    limits.check(&range, &mut checked)?;
+    if checked.window_ms != options.window_ms {
+        return fetch(client, limits, range, options).await;
+    }
+
+    let fields = if options.fields.is_empty() {
+        Field::ALL.to_vec()
+    } else {
+        options.fields.clone()
+    };
+
+    let start = Instant::now();
+    let (start_ms, stop_ms) = range.bounds();
+
+    let result = raw_points(&mut *client.lock().await, &fields, start_ms, stop_ms).await;
+    let mut points = match result {
+        Ok(v) => v,
+        Err(BackendError::Unsupported(_)) => return fetch(client, limits, range, options).await,
+        Err(e) => return Err(ApiError::backend(e)),
+    };
+
+    if let Some(since) = options.since {
+        points.retain(|p| p.time > since);
+    }
+    options.apply_limit(&mut points);
+
+    println!(
+        "Took {} ms to fetch {} raw measurements",
+        start.elapsed().as_millis(),
+        points.len()
+    );
+
+    Ok(Fetched {
+        points,
+        stale: false,
+        truncated: false,
+        window_ms: options.window(&range),
+    })
+}
+
+async fn data_range_start_end(
+    Path((start, stop)): Path<(u64, u64)>,
+    Query(params): Query<RangeParams>,
+    Extension(client): Extension<SharedState>,
+    Extension(limits): Extension<QueryLimits>,
+    Extension(spikes): Extension<spikes::SpikeFilter>,
+    _: ReadAccess,
+) -> impl IntoResponse {
+    let options = params.options()?;
+
+    let range = between(start, stop)?;
+    let temps = params.fetch(&client, &limits, range, &options).await?;
+    let annotations = annotations::overlapping(&client, range, params.annotations).await?;
+
+    let temps = temps
+        .filter(params.filter, &spikes)
+        .aggregate(params.aggregation(&range)?)
+        .interpolate(params.interpolate, limits.max_interpolation_gap)
+        .smooth(params.smoothing()?)
+        .downsample(&options.fields, params.downsample_to())
+        .cap(params.max_points);
+
+    respond_annotated(temps, params.format, params.envelope, annotations)
+}
+
+fn between(start: u64, stop: u64) -> Result<TimeRange, ApiError> {
+    for time in [start, stop] {
+        let valid = i64::try_from(time)
+            .ok()
+            .and_then(|ms| Utc.timestamp_millis_opt(ms).single());
+        if valid.is_none() {
+            return Err(ApiError::new(
+                ErrorCode::BadTimeRange,
+                format!("Timestamp {time} is out of range."),
+            ));
+        }
+    }
+
+    if start >= stop {
+        return Err(ApiError::new(
+            ErrorCode::BadTimeRange,
+            format!("Start ({start}) must be before stop ({stop})."),
+        ));
+    }
+
+    Ok(TimeRange::Between {
+        start_ms: start,
+        stop_ms: stop,
+    })
+}
+
+/// A duration like `7d`, or `today` for the time since midnight.
+fn get_range(input: &str) -> Result<Duration, ApiError> {
+    if input == "today" {
+        return Ok(calendar::since_midnight(Utc::now()));
+    }
+
     match DurationString::from_str(&input) {
         Ok(duration) => Ok(duration.into()),
-        Err(e) => Err((
-            StatusCode::BAD_REQUEST,
+        Err(e) => Err(ApiError::new(
+            ErrorCode::BadRangeSyntax,
             format!("Could not convert {input} into a duration ({e})."),
         )),
     }
@@ -155,27 +1533,284 @@ fn get_range(input: &str) -> Result<Duration, (StatusCode, String)> {
 
 async fn data_range(
     Path(path): Path<String>,
+    Query(params): Query<RangeParams>,
     Extension(client): Extension<SharedState>,
-    Extension(HttpPassword(password)): Extension<HttpPassword>,
-    auth: TypedHeader<Authorization<Bearer>>,
+    Extension(limits): Extension<QueryLimits>,
+    Extension(spikes): Extension<spikes::SpikeFilter>,
+    _: ReadAccess,
 ) -> impl IntoResponse {
-    check_password(password, auth)?;
-    let duration = match get_range(&path) {
-        Ok(duration) => duration.into(),
-        Err(e) => return Err(e),
+    let options = params.options()?;
+
+    let range = TimeRange::Span(get_range(&path)?);
+    let temps = params.fetch(&client, &limits, range, &options).await?;
+    let annotations = annotations::overlapping(&client, range, params.annotations).await?;
+
+    let temps = temps
+        .filter(params.filter, &spikes)
+        .aggregate(params.aggregation(&range)?)
+        .interpolate(params.interpolate, limits.max_interpolation_gap)
+        .smooth(params.smoothing()?)
+        .downsample(&options.fields, params.downsample_to())
+        .cap(params.max_points);
+
+    respond_annotated(temps, params.format, params.envelope, annotations)
+}
+
+/// Where the routes of [`metric_routes`] are nested for `field`.
+fn metric_prefix(field: Field) -> &'static str {
+    match field {
+        Field::Temperature => "/temp",
+        Field::Humidity => "/humidity",
+        Field::Co2 => "/co2",
+    }
+}
+
+fn metric_routes(field: Field) -> Router {
+    Router::new()
+        .route("/range/:range", get(metric_range))
+        .route("/from/:start/to/:stop", get(metric_range_start_end))
+        .route("/band/:range", get(metric_band))
+        .route("/band/from/:start/to/:stop", get(metric_band_start_end))
+        .route("/weekly/:range", get(climate::weekly))
+        .route("/monthly/:range", get(climate::monthly))
+        .layer(AddExtensionLayer::new(field))
+}
+
+/// Respond with `[time, value]` pairs of `field`. Other formats than JSON are
+/// column oriented already, and are encoded as usual.
+fn project(
+    field: Field,
+    fetched: Fetched,
+    format: ResponseFormat,
+    envelope: bool,
+) -> Result<Response, ApiError> {
+    if !matches!(format, ResponseFormat::Json) {
+        return respond(fetched, format, envelope);
+    }
+
+    let points: Vec<_> = fetched
+        .points
+        .iter()
+        .filter_map(|p| MetricPoint::project(field, p))
+        .collect();
+
+    let response = mark_count(
+        points_json(&points, fetched.stale, envelope)?.into_response(),
+        points.len(),
+        fetched.truncated,
+    );
+    Ok(mark_stale(response, fetched.stale))
+}
+
+async fn metric_range(
+    Path(path): Path<String>,
+    Query(params): Query<RangeParams>,
+    Extension(field): Extension<Field>,
+    Extension(client): Extension<SharedState>,
+    Extension(limits): Extension<QueryLimits>,
+    Extension(spikes): Extension<spikes::SpikeFilter>,
+    _: ReadAccess,
+) -> impl IntoResponse {
+    let options = RangeOptions {
+        fields: vec![field],
+        ..params.options()?
     };
 
-    let start = Instant::now();
-    let temps: Vec<_> = match client.lock().await.get_data_in_span(duration).await {
-        Ok(v) => v.collect(),
-        Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, format!("{e}"))),
+    let range = TimeRange::Span(get_range(&path)?);
+    let temps = params.fetch(&client, &limits, range, &options).await?;
+
+    let temps = temps
+        .filter(params.filter, &spikes)
+        .aggregate(params.aggregation(&range)?)
+        .interpolate(params.interpolate, limits.max_interpolation_gap)
+        .smooth(params.smoothing()?)
+        .downsample(&[field], params.downsample_to())
+        .cap(params.max_points);
+
+    project(field, temps, params.format, params.envelope)
+}
+
+async fn metric_range_start_end(
+    Path((start, stop)): Path<(u64, u64)>,
+    Query(params): Query<RangeParams>,
+    Extension(field): Extension<Field>,
+    Extension(client): Extension<SharedState>,
+    Extension(limits): Extension<QueryLimits>,
+    Extension(spikes): Extension<spikes::SpikeFilter>,
+    _: ReadAccess,
+) -> impl IntoResponse {
+    let options = RangeOptions {
+        fields: vec![field],
+        ..params.options()?
     };
 
-    println!(
-        "Took {} ms to fetch {} temperature measurements",
-        start.elapsed().as_millis(),
-        temps.len()
-    );
+    let range = between(start, stop)?;
+    let temps = params.fetch(&client, &limits, range, &options).await?;
+
+    let temps = temps
+        .filter(params.filter, &spikes)
+        .aggregate(params.aggregation(&range)?)
+        .interpolate(params.interpolate, limits.max_interpolation_gap)
+        .smooth(params.smoothing()?)
+        .downsample(&[field], params.downsample_to())
+        .cap(params.max_points);
+
+    project(field, temps, params.format, params.envelope)
+}
+
+async fn fetch_band(
+    client: &SharedState,
+    limits: &QueryLimits,
+    spikes: &spikes::SpikeFilter,
+    field: Field,
+    range: TimeRange,
+    params: &RangeParams,
+) -> Result<Response, ApiError> {
+    JsonOnly {
+        format: params.format,
+    }
+    .check()?;
 
-    to_json(temps)
+    if params.downsample.is_some() || params.resample.is_some() {
+        return Err(ApiError::new(
+            ErrorCode::BadRequest,
+            "Bands can't be downsampled or resampled.",
+        ));
+    }
+
+    let mut options = RangeOptions {
+        fields: vec![field],
+        ..params.options()?
+    };
+
+    // Spikes are dropped from the raw points, which are then combined into
+    // bands here instead of by the backend.
+    if let Some((window_ms, _)) = params.aggregation(&range)? {
+        let raw = RangeOptions {
+            limit: None,
+            ..options.clone()
+        };
+        let fetched = fetch_raw(client, limits, range, &raw)
+            .await?
+            .filter(params.filter, spikes);
+
+        let points: Vec<_> = fetched
+            .points
+            .iter()
+            .filter_map(|p| MetricPoint::project(field, p))
+            .collect();
+        let mut bands = band_points(&points, window_ms as i64);
+        options.apply_limit(&mut bands);
+
+        return Ok(mark_stale(to_json(&bands)?.into_response(), fetched.stale));
+    }
+
+    limits.check(&range, &mut options)?;
+
+    let bands = client
+        .lock()
+        .await
+        .get_metric_band(field.name(), range, &options)
+        .await
+        .map_err(ApiError::backend)?;
+
+    Ok(to_json(&bands)?.into_response())
+}
+
+/// The minimum, mean and maximum of `field` per window, to draw a band.
+async fn metric_band(
+    Path(path): Path<String>,
+    Query(params): Query<RangeParams>,
+    Extension(field): Extension<Field>,
+    Extension(client): Extension<SharedState>,
+    Extension(limits): Extension<QueryLimits>,
+    Extension(spikes): Extension<spikes::SpikeFilter>,
+    _: ReadAccess,
+) -> impl IntoResponse {
+    let range = TimeRange::Span(get_range(&path)?);
+    fetch_band(&client, &limits, &spikes, field, range, &params).await
+}
+
+async fn metric_band_start_end(
+    Path((start, stop)): Path<(u64, u64)>,
+    Query(params): Query<RangeParams>,
+    Extension(field): Extension<Field>,
+    Extension(client): Extension<SharedState>,
+    Extension(limits): Extension<QueryLimits>,
+    Extension(spikes): Extension<spikes::SpikeFilter>,
+    _: ReadAccess,
+) -> impl IntoResponse {
+    let range = between(start, stop)?;
+    fetch_band(&client, &limits, &spikes, field, range, &params).await
+}
+
+async fn fetch_metric(
+    client: &SharedState,
+    limits: &QueryLimits,
+    name: &str,
+    range: TimeRange,
+    options: &RangeOptions,
+    downsample_to: Option<usize>,
+) -> Result<Response, ApiError> {
+    let mut options = options.clone();
+    limits.check(&range, &mut options)?;
+
+    let mut points = client
+        .lock()
+        .await
+        .get_metric_range(name, range, &options)
+        .await
+        .map_err(ApiError::backend)?;
+
+    if let Some(to) = downsample_to {
+        points = downsample::lttb(&points, to);
+    }
+
+    Ok(to_json(&points)?.into_response())
+}
+
+async fn named_metric_range(
+    Path((name, path)): Path<(String, String)>,
+    Query(params): Query<RangeParams>,
+    Extension(metrics): Extension<Metrics>,
+    Extension(client): Extension<SharedState>,
+    Extension(limits): Extension<QueryLimits>,
+    _: ReadAccess,
+) -> impl IntoResponse {
+    metrics.check(&name)?;
+
+    let range = TimeRange::Span(get_range(&path)?);
+    let options = params.options()?;
+    fetch_metric(
+        &client,
+        &limits,
+        &name,
+        range,
+        &options,
+        params.downsample_to(),
+    )
+    .await
+}
+
+async fn named_metric_range_start_end(
+    Path((name, start, stop)): Path<(String, u64, u64)>,
+    Query(params): Query<RangeParams>,
+    Extension(metrics): Extension<Metrics>,
+    Extension(client): Extension<SharedState>,
+    Extension(limits): Extension<QueryLimits>,
+    _: ReadAccess,
+) -> impl IntoResponse {
+    metrics.check(&name)?;
+
+    let range = between(start, stop)?;
+    let options = params.options()?;
+    fetch_metric(
+        &client,
+        &limits,
+        &name,
+        range,
+        &options,
+        params.downsample_to(),
+    )
+    .await
 }
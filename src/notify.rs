@@ -0,0 +1,534 @@
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use chrono::{TimeZone, Utc};
+use futures_util::future::join_all;
+use serde::Deserialize;
+
+use crate::alerts::{Event, EventKind, Severity};
+
+/// A message to deliver through the configured channels.
+#[derive(Debug, Clone)]
+pub struct Notification {
+    pub title: String,
+    pub body: String,
+    pub severity: Severity,
+    /// The alert this notification is about, if any.
+    pub event: Option<Event>,
+    /// Files for channels that support attachments. Ignored by others.
+    pub attachments: Vec<Attachment>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    pub name: String,
+    pub content_type: &'static str,
+    pub data: Vec<u8>,
+}
+
+impl From<&Event> for Notification {
+    fn from(event: &Event) -> Self {
+        Self {
+            title: render("[{severity}] {rule} {state}", event),
+            body: render(
+                "{field} is {value} ({comparison} {threshold}) at {time}",
+                event,
+            ),
+            severity: event.rule.severity,
+            event: Some(event.clone()),
+            attachments: Vec::new(),
+        }
+    }
+}
+
+/// Replace `{rule}`, `{field}`, `{value}`, `{severity}`, `{state}`,
+/// `{comparison}`, `{threshold}` and `{time}` in `template`.
+pub fn render(template: &str, event: &Event) -> String {
+    let state = match event.kind {
+        EventKind::Fired => "fired",
+        EventKind::Resolved => "resolved",
+    };
+
+    let time = Utc
+        .timestamp_millis_opt(event.time)
+        .single()
+        .map(|t| t.to_rfc3339())
+        .unwrap_or_default();
+
+    template
+        .replace("{rule}", &event.rule.name)
+        .replace("{field}", event.rule.field.name())
+        .replace("{value}", &format!("{:.02}", event.value))
+        .replace("{severity}", &event.rule.severity.to_string())
+        .replace("{state}", state)
+        .replace("{comparison}", &event.rule.comparison.to_string())
+        .replace("{threshold}", &event.rule.threshold.to_string())
+        .replace("{time}", &time)
+}
+
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    /// The name of the channel, for logging.
+    fn name(&self) -> &'static str;
+
+    async fn send(&self, notification: &Notification) -> Result<(), String>;
+}
+
+/// Notification channels, as configured in the alerts file.
+#[derive(Debug, Default, Deserialize)]
+pub struct ChannelConfig {
+    #[cfg(feature = "email")]
+    pub email: Option<EmailConfig>,
+    pub telegram: Option<TelegramConfig>,
+    pub discord: Option<DiscordConfig>,
+    pub gotify: Option<GotifyConfig>,
+    pub ntfy: Option<NtfyConfig>,
+}
+
+/// How long a channel may take to accept a connection, and to answer.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+impl ChannelConfig {
+    pub fn build(&self) -> Result<Vec<Box<dyn Notifier>>, String> {
+        let mut notifiers: Vec<Box<dyn Notifier>> = Vec::new();
+
+        let client = reqwest::Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .map_err(|e| format!("Could not create HTTP client: {e}"))?;
+
+        #[cfg(feature = "email")]
+        if let Some(email) = &self.email {
+            notifiers.push(Box::new(EmailNotifier::new(email)?));
+        }
+
+        if let Some(telegram) = &self.telegram {
+            notifiers.push(Box::new(TelegramNotifier {
+                config: telegram.clone(),
+                client: client.clone(),
+            }));
+        }
+
+        if let Some(discord) = &self.discord {
+            notifiers.push(Box::new(DiscordNotifier {
+                config: discord.clone(),
+                client: client.clone(),
+            }));
+        }
+
+        if let Some(gotify) = &self.gotify {
+            notifiers.push(Box::new(GotifyNotifier {
+                config: gotify.clone(),
+                client: client.clone(),
+            }));
+        }
+
+        if let Some(ntfy) = &self.ntfy {
+            notifiers.push(Box::new(NtfyNotifier::new(ntfy, client.clone())?));
+        }
+
+        Ok(notifiers)
+    }
+}
+
+/// Deliver `notification` through every channel at once in the background,
+/// so that a slow channel doesn't hold up the others.
+pub fn dispatch(notifiers: &Arc<Vec<Box<dyn Notifier>>>, notification: Notification) {
+    let notifiers = notifiers.clone();
+
+    tokio::spawn(async move {
+        let notification = &notification;
+        let sends = notifiers.iter().map(|notifier| async move {
+            if let Err(e) = notifier.send(&notification).await {
+                eprintln!("Could not send notification via {}: {e}", notifier.name());
+            }
+        });
+        join_all(sends).await;
+    });
+}
+
+/// ```toml
+/// [email]
+/// server = "smtp.example.com"
+/// username = "alerts@example.com"
+/// password = "..."
+/// from = "Temperature <alerts@example.com>"
+/// to = ["me@example.com"]
+/// subject = "[{severity}] {rule} {state}"
+/// body = "{field} is {value} at {time}"
+/// ```
+#[cfg(feature = "email")]
+#[derive(Debug, Clone, Deserialize)]
+pub struct EmailConfig {
+    pub server: String,
+    /// Defaults to 587 (STARTTLS).
+    pub port: Option<u16>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+    pub subject: Option<String>,
+    pub body: Option<String>,
+}
+
+#[cfg(feature = "email")]
+struct EmailNotifier {
+    config: EmailConfig,
+    transport: lettre::AsyncSmtpTransport<lettre::Tokio1Executor>,
+}
+
+#[cfg(feature = "email")]
+impl EmailNotifier {
+    fn new(config: &EmailConfig) -> Result<Self, String> {
+        use lettre::{transport::smtp::authentication::Credentials, AsyncSmtpTransport};
+
+        let mut transport =
+            AsyncSmtpTransport::<lettre::Tokio1Executor>::starttls_relay(&config.server)
+                .map_err(|e| format!("Invalid SMTP server {}: {e}", config.server))?
+                .port(config.port.unwrap_or(587));
+
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            transport = transport.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+
+        Ok(Self {
+            config: config.clone(),
+            transport: transport.build(),
+        })
+    }
+}
+
+#[cfg(feature = "email")]
+#[async_trait]
+impl Notifier for EmailNotifier {
+    fn name(&self) -> &'static str {
+        "email"
+    }
+
+    async fn send(&self, notification: &Notification) -> Result<(), String> {
+        use lettre::{
+            message::{header::ContentType, MultiPart, SinglePart},
+            AsyncTransport, Message,
+        };
+
+        // Templates only apply to alerts.
+        let template =
+            |template: &Option<String>, default: &String| match (template, &notification.event) {
+                (Some(template), Some(event)) => render(template, event),
+                _ => default.clone(),
+            };
+
+        let subject = template(&self.config.subject, &notification.title);
+        let body = template(&self.config.body, &notification.body);
+
+        let mut builder = Message::builder()
+            .from(
+                self.config
+                    .from
+                    .parse()
+                    .map_err(|e| format!("Invalid from address: {e}"))?,
+            )
+            .subject(subject);
+
+        for to in &self.config.to {
+            builder = builder.to(to
+                .parse()
+                .map_err(|e| format!("Invalid address {to}: {e}"))?);
+        }
+
+        let message = if notification.attachments.is_empty() {
+            builder.body(body)
+        } else {
+            let mut parts = MultiPart::mixed().singlepart(SinglePart::plain(body));
+
+            for attachment in &notification.attachments {
+                let content_type = ContentType::parse(attachment.content_type)
+                    .map_err(|e| format!("Invalid content type: {e}"))?;
+                parts = parts.singlepart(
+                    lettre::message::Attachment::new(attachment.name.clone())
+                        .body(attachment.data.clone(), content_type),
+                );
+            }
+
+            builder.multipart(parts)
+        }
+        .map_err(|e| format!("{e}"))?;
+
+        self.transport
+            .send(message)
+            .await
+            .map(|_| ())
+            .map_err(|e| format!("{e}"))
+    }
+}
+
+/// ```toml
+/// [telegram]
+/// bot_token = "123456:ABC..."
+/// chat_id = -1001234567890
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct TelegramConfig {
+    pub bot_token: String,
+    /// A numeric chat id, or `@channelname`.
+    pub chat_id: serde_json::Value,
+}
+
+struct TelegramNotifier {
+    config: TelegramConfig,
+    client: reqwest::Client,
+}
+
+#[async_trait]
+impl Notifier for TelegramNotifier {
+    fn name(&self) -> &'static str {
+        "telegram"
+    }
+
+    async fn send(&self, notification: &Notification) -> Result<(), String> {
+        let url = format!(
+            "https://api.telegram.org/bot{}/sendMessage",
+            self.config.bot_token
+        );
+
+        let message = serde_json::json!({
+            "chat_id": self.config.chat_id,
+            "text": format!("{}\n{}", notification.title, notification.body),
+        });
+
+        // Don't include the URL in errors, it contains the token.
+        self.client
+            .post(url)
+            .json(&message)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map(|_| ())
+            .map_err(|e| format!("{}", e.without_url()))
+    }
+}
+
+/// ```toml
+/// [discord]
+/// webhook_url = "https://discord.com/api/webhooks/..."
+/// # Linked from the embed title, may contain the same placeholders as templates.
+/// chart_url = "https://temp.example.com/?field={field}"
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct DiscordConfig {
+    pub webhook_url: String,
+    pub chart_url: Option<String>,
+}
+
+struct DiscordNotifier {
+    config: DiscordConfig,
+    client: reqwest::Client,
+}
+
+impl DiscordNotifier {
+    fn color(severity: Severity) -> u32 {
+        match severity {
+            Severity::Info => 0x3498db,
+            Severity::Warning => 0xf1c40f,
+            Severity::Critical => 0xe74c3c,
+        }
+    }
+
+    fn embed(&self, notification: &Notification) -> serde_json::Value {
+        let mut embed = serde_json::json!({
+            "title": notification.title,
+            "description": notification.body,
+            "color": Self::color(notification.severity),
+        });
+
+        if let Some(event) = &notification.event {
+            let field = |name: &str, value: String| serde_json::json!({ "name": name, "value": value, "inline": true });
+
+            embed["fields"] = serde_json::json!([
+                field(event.rule.field.name(), format!("{:.02}", event.value)),
+                field(
+                    "Threshold",
+                    format!("{} {}", event.rule.comparison, event.rule.threshold)
+                ),
+                field("Severity", event.rule.severity.to_string()),
+            ]);
+
+            if let Some(time) = Utc.timestamp_millis_opt(event.time).single() {
+                embed["timestamp"] = time.to_rfc3339().into();
+            }
+
+            if let Some(chart_url) = &self.config.chart_url {
+                embed["url"] = render(chart_url, event).into();
+            }
+        }
+
+        embed
+    }
+}
+
+#[async_trait]
+impl Notifier for DiscordNotifier {
+    fn name(&self) -> &'static str {
+        "discord"
+    }
+
+    async fn send(&self, notification: &Notification) -> Result<(), String> {
+        let message = serde_json::json!({ "embeds": [self.embed(notification)] });
+
+        // Don't include the URL in errors, it contains the webhook token.
+        self.client
+            .post(&self.config.webhook_url)
+            .json(&message)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map(|_| ())
+            .map_err(|e| format!("{}", e.without_url()))
+    }
+}
+
+/// Priority to send for each alert severity. Unset severities use the
+/// service's defaults.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Priorities {
+    pub info: Option<u8>,
+    pub warning: Option<u8>,
+    pub critical: Option<u8>,
+}
+
+impl Priorities {
+    fn get(&self, severity: Severity, defaults: [u8; 3]) -> u8 {
+        match severity {
+            Severity::Info => self.info.unwrap_or(defaults[0]),
+            Severity::Warning => self.warning.unwrap_or(defaults[1]),
+            Severity::Critical => self.critical.unwrap_or(defaults[2]),
+        }
+    }
+}
+
+/// ```toml
+/// [gotify]
+/// url = "https://gotify.example.com"
+/// token = "..."
+///
+/// [gotify.priorities]
+/// critical = 10
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct GotifyConfig {
+    pub url: String,
+    /// An application token.
+    pub token: String,
+    #[serde(default)]
+    pub priorities: Priorities,
+}
+
+struct GotifyNotifier {
+    config: GotifyConfig,
+    client: reqwest::Client,
+}
+
+#[async_trait]
+impl Notifier for GotifyNotifier {
+    fn name(&self) -> &'static str {
+        "gotify"
+    }
+
+    async fn send(&self, notification: &Notification) -> Result<(), String> {
+        let url = format!("{}/message", self.config.url.trim_end_matches('/'));
+
+        let message = serde_json::json!({
+            "title": notification.title,
+            "message": notification.body,
+            "priority": self.config.priorities.get(notification.severity, [2, 5, 8]),
+        });
+
+        self.client
+            .post(url)
+            .header("X-Gotify-Key", &self.config.token)
+            .json(&message)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map(|_| ())
+            .map_err(|e| format!("{e}"))
+    }
+}
+
+/// ```toml
+/// [ntfy]
+/// url = "https://ntfy.sh/my-temperature-alerts"
+/// # Optional access token.
+/// token = "tk_..."
+///
+/// [ntfy.priorities]
+/// warning = 3
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct NtfyConfig {
+    /// The server URL, including the topic.
+    pub url: String,
+    pub token: Option<String>,
+    #[serde(default)]
+    pub priorities: Priorities,
+}
+
+struct NtfyNotifier {
+    config: NtfyConfig,
+    /// The server root, which JSON messages are published to.
+    server: String,
+    topic: String,
+    client: reqwest::Client,
+}
+
+impl NtfyNotifier {
+    fn new(config: &NtfyConfig, client: reqwest::Client) -> Result<Self, String> {
+        let (server, topic) = match config.url.trim_end_matches('/').rsplit_once('/') {
+            Some((server, topic)) if !topic.is_empty() && server.contains("://") => (server, topic),
+            _ => return Err(format!("ntfy URL {} does not include a topic", config.url)),
+        };
+
+        Ok(Self {
+            server: format!("{server}/"),
+            topic: topic.to_string(),
+            config: config.clone(),
+            client,
+        })
+    }
+}
+
+#[async_trait]
+impl Notifier for NtfyNotifier {
+    fn name(&self) -> &'static str {
+        "ntfy"
+    }
+
+    async fn send(&self, notification: &Notification) -> Result<(), String> {
+        let priority = self.config.priorities.get(notification.severity, [2, 4, 5]);
+
+        // Published as JSON rather than with headers, which can't hold
+        // non-ASCII titles.
+        let message = serde_json::json!({
+            "topic": self.topic,
+            "title": notification.title,
+            "message": notification.body,
+            "priority": priority,
+            "tags": [notification.severity.to_string()],
+        });
+
+        let mut request = self.client.post(&self.server).json(&message);
+
+        if let Some(token) = &self.config.token {
+            request = request.bearer_auth(token);
+        }
+
+        request
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map(|_| ())
+            .map_err(|e| format!("{e}"))
+    }
+}
@@ -0,0 +1,239 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use axum::{extract::Path, response::IntoResponse, Extension};
+use chrono::{Duration, NaiveDate, TimeZone, Utc};
+use clap::Args;
+use serde::{Deserialize, Serialize};
+
+use influxdb_temp_client::{DataPoint, RangeOptions, TimeRange};
+
+use crate::{
+    fetch, get_range, mark_stale,
+    problem::{ApiError, ErrorCode},
+    scope::ReadAccess,
+    to_json, QueryLimits, SharedState,
+};
+
+const HOUR_MS: i64 = 3_600_000;
+
+/// How far back the forecast API has data. Older days are fetched from the
+/// archive API.
+const FORECAST_PAST_DAYS: i64 = 90;
+
+#[derive(Args)]
+pub struct OutdoorOpts {
+    /// Latitude to fetch outdoor weather for from Open-Meteo. Outdoor
+    /// comparisons are disabled if not set.
+    #[clap(long, env = "OUTDOOR_LATITUDE", requires = "outdoor_longitude")]
+    pub outdoor_latitude: Option<f64>,
+    #[clap(long, env = "OUTDOOR_LONGITUDE", requires = "outdoor_latitude")]
+    pub outdoor_longitude: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Reading {
+    temperature: Option<f64>,
+    humidity: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Hourly {
+    time: Vec<i64>,
+    temperature_2m: Vec<Option<f64>>,
+    relative_humidity_2m: Vec<Option<f64>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ForecastResponse {
+    hourly: Hourly,
+}
+
+/// Hourly outdoor weather from Open-Meteo. Days that are over are cached in
+/// memory, the current day is fetched on every request.
+pub struct Outdoor {
+    client: reqwest::Client,
+    latitude: f64,
+    longitude: f64,
+    readings: Mutex<BTreeMap<i64, Reading>>,
+    cached_days: Mutex<HashSet<NaiveDate>>,
+}
+
+impl Outdoor {
+    pub fn new(opts: &OutdoorOpts) -> Option<Arc<Self>> {
+        Some(Arc::new(Self {
+            client: reqwest::Client::new(),
+            latitude: opts.outdoor_latitude?,
+            longitude: opts.outdoor_longitude?,
+            readings: Mutex::new(BTreeMap::new()),
+            cached_days: Mutex::new(HashSet::new()),
+        }))
+    }
+
+    async fn fetch_days(&self, start: NaiveDate, end: NaiveDate) -> Result<(), String> {
+        let today = Utc::now().date_naive();
+
+        let base = if start < today - Duration::days(FORECAST_PAST_DAYS) {
+            "https://archive-api.open-meteo.com/v1/archive"
+        } else {
+            "https://api.open-meteo.com/v1/forecast"
+        };
+
+        let response: ForecastResponse = self
+            .client
+            .get(base)
+            .query(&[
+                ("latitude", self.latitude.to_string()),
+                ("longitude", self.longitude.to_string()),
+                ("start_date", start.to_string()),
+                ("end_date", end.to_string()),
+                ("hourly", "temperature_2m,relative_humidity_2m".to_string()),
+                ("timeformat", "unixtime".to_string()),
+                ("timezone", "UTC".to_string()),
+            ])
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| format!("Could not fetch outdoor weather: {e}"))?
+            .json()
+            .await
+            .map_err(|e| format!("Invalid outdoor weather response: {e}"))?;
+
+        let hourly = response.hourly;
+        // Hours with a reading per day. The archive lags a few days behind,
+        // and returns those hours without values.
+        let mut hours: HashMap<NaiveDate, usize> = HashMap::new();
+        let mut readings = self.readings.lock().unwrap();
+        for (i, time) in hourly.time.iter().enumerate() {
+            let reading = Reading {
+                temperature: hourly.temperature_2m.get(i).copied().flatten(),
+                humidity: hourly.relative_humidity_2m.get(i).copied().flatten(),
+            };
+            readings.insert(time * 1000, reading);
+
+            if reading.temperature.is_none() && reading.humidity.is_none() {
+                continue;
+            }
+            if let Some(time) = Utc.timestamp_opt(*time, 0).single() {
+                *hours.entry(time.date_naive()).or_default() += 1;
+            }
+        }
+
+        // Only days that are over and complete won't change anymore, the
+        // others are fetched again next time.
+        let mut cached_days = self.cached_days.lock().unwrap();
+        for (day, hours) in hours {
+            if (start..=end).contains(&day) && day < today && hours == 24 {
+                cached_days.insert(day);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Make sure the readings between `start` and `stop` (in milliseconds)
+    /// are available.
+    async fn load(&self, start: i64, stop: i64) -> Result<(), String> {
+        let date = |ms: i64| {
+            Utc.timestamp_millis_opt(ms)
+                .single()
+                .map(|t| t.date_naive())
+                .ok_or_else(|| format!("Invalid timestamp {ms}"))
+        };
+
+        // An extra hour on both sides to interpolate the edges.
+        let (start, end) = (date(start - HOUR_MS)?, date(stop + HOUR_MS)?);
+
+        let missing: Vec<_> = {
+            let cached_days = self.cached_days.lock().unwrap();
+            start
+                .iter_days()
+                .take_while(|d| *d <= end)
+                .filter(|d| !cached_days.contains(d))
+                .collect()
+        };
+
+        match (missing.first(), missing.last()) {
+            (Some(first), Some(last)) => self.fetch_days(*first, *last).await,
+            _ => Ok(()),
+        }
+    }
+
+    /// The outdoor reading at `time`, interpolated between the surrounding
+    /// hours.
+    fn at(&self, time: i64) -> Option<Reading> {
+        let readings = self.readings.lock().unwrap();
+        let (t0, before) = readings.range(..=time).next_back()?;
+        let (t1, after) = readings.range(time..).next()?;
+
+        if t0 == t1 {
+            return Some(*before);
+        }
+
+        let f = (time - t0) as f64 / (t1 - t0) as f64;
+        let lerp =
+            |a: Option<f64>, b: Option<f64>| Some(((a? + (b? - a?) * f) * 100.).round() / 100.);
+
+        Some(Reading {
+            temperature: lerp(before.temperature, after.temperature),
+            humidity: lerp(before.humidity, after.humidity),
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ComparePoint {
+    time: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    humidity: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    outdoor_temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    outdoor_humidity: Option<f64>,
+}
+
+/// Indoor points with the outdoor weather at the same time.
+pub async fn compare(
+    Path(path): Path<String>,
+    Extension(outdoor): Extension<Option<Arc<Outdoor>>>,
+    Extension(client): Extension<SharedState>,
+    Extension(limits): Extension<QueryLimits>,
+    _: ReadAccess,
+) -> impl IntoResponse {
+    let Some(outdoor) = outdoor else {
+        return Err(ApiError::new(
+            ErrorCode::NotConfigured,
+            "Outdoor weather is not configured",
+        ));
+    };
+
+    let range = TimeRange::Span(get_range(&path)?);
+    let fetched = fetch(&client, &limits, range, &RangeOptions::default()).await?;
+
+    let (start, stop) = range.bounds();
+    if let Err(e) = outdoor.load(start, stop).await {
+        eprintln!("{e}");
+        return Err(ApiError::new(ErrorCode::UpstreamError, e));
+    }
+
+    let points: Vec<_> = fetched
+        .points
+        .iter()
+        .map(|p: &DataPoint| {
+            let outside = outdoor.at(p.time);
+            ComparePoint {
+                time: p.time,
+                temperature: p.temperature,
+                humidity: p.humidity,
+                outdoor_temperature: outside.and_then(|o| o.temperature),
+                outdoor_humidity: outside.and_then(|o| o.humidity),
+            }
+        })
+        .collect();
+
+    Ok(mark_stale(to_json(&points)?.into_response(), fetched.stale))
+}
@@ -0,0 +1,225 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use axum::{
+    body::{self, Body},
+    http::{header, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use influxdb_temp_client::BackendError;
+use serde::Serialize;
+
+const REQUEST_ID: &str = "x-request-id";
+
+/// Stable, machine-readable reasons for a failed request. New variants may be
+/// added, but existing ones keep their meaning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    BadRequest,
+    BadRangeSyntax,
+    /// The start of a range is not before its stop.
+    BadTimeRange,
+    RangeTooLarge,
+    TooManyPoints,
+    UnknownField,
+    UnknownMetric,
+    Unauthorized,
+    AdminDisabled,
+    Forbidden,
+    /// The signature of a shared URL doesn't match.
+    BadSignature,
+    ShareExpired,
+    NotFound,
+    /// There is no recent enough data to answer.
+    NoData,
+    /// The feature behind the endpoint is not configured.
+    NotConfigured,
+    /// The backend can't answer this, e.g. a metric that only InfluxDB
+    /// stores.
+    NotSupported,
+    QuotaExceeded,
+    UpstreamError,
+    UpstreamTimeout,
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ErrorCode::BadRequest
+            | ErrorCode::BadRangeSyntax
+            | ErrorCode::BadTimeRange
+            | ErrorCode::RangeTooLarge
+            | ErrorCode::TooManyPoints
+            | ErrorCode::UnknownField => StatusCode::BAD_REQUEST,
+            ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorCode::AdminDisabled
+            | ErrorCode::Forbidden
+            | ErrorCode::BadSignature
+            | ErrorCode::ShareExpired => StatusCode::FORBIDDEN,
+            ErrorCode::UnknownMetric
+            | ErrorCode::NotFound
+            | ErrorCode::NoData
+            | ErrorCode::NotConfigured => StatusCode::NOT_FOUND,
+            ErrorCode::QuotaExceeded => StatusCode::TOO_MANY_REQUESTS,
+            ErrorCode::NotSupported => StatusCode::NOT_IMPLEMENTED,
+            ErrorCode::UpstreamError => StatusCode::BAD_GATEWAY,
+            ErrorCode::UpstreamTimeout => StatusCode::GATEWAY_TIMEOUT,
+            ErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// The code of error responses that don't set one, such as rejections
+    /// of extractors and middleware.
+    fn from_status(status: StatusCode) -> Self {
+        match status {
+            StatusCode::UNAUTHORIZED => ErrorCode::Unauthorized,
+            StatusCode::FORBIDDEN => ErrorCode::Forbidden,
+            StatusCode::NOT_FOUND | StatusCode::METHOD_NOT_ALLOWED => ErrorCode::NotFound,
+            StatusCode::TOO_MANY_REQUESTS => ErrorCode::QuotaExceeded,
+            StatusCode::NOT_IMPLEMENTED => ErrorCode::NotSupported,
+            StatusCode::BAD_GATEWAY => ErrorCode::UpstreamError,
+            StatusCode::GATEWAY_TIMEOUT => ErrorCode::UpstreamTimeout,
+            s if s.is_client_error() => ErrorCode::BadRequest,
+            _ => ErrorCode::Internal,
+        }
+    }
+}
+
+/// The error of a handler: a code, and a message for humans.
+#[derive(Debug, Clone)]
+pub struct ApiError {
+    pub code: ErrorCode,
+    pub detail: String,
+}
+
+impl ApiError {
+    pub fn new(code: ErrorCode, detail: impl Into<String>) -> Self {
+        Self {
+            code,
+            detail: detail.into(),
+        }
+    }
+
+    /// A failed query to the backend.
+    pub fn backend(error: impl Into<BackendError>) -> Self {
+        let error = error.into();
+        let code = match error {
+            BackendError::Timeout(_) => ErrorCode::UpstreamTimeout,
+            BackendError::Unsupported(_) => ErrorCode::NotSupported,
+            BackendError::Failed(_) => ErrorCode::UpstreamError,
+        };
+
+        Self::new(code, error)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.code.status(), Extension(self.code), self.detail).into_response()
+    }
+}
+
+/// An RFC 7807 problem details object.
+#[derive(Debug, Serialize)]
+struct Problem {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    title: &'static str,
+    status: u16,
+    code: ErrorCode,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+    instance: String,
+    request_id: String,
+}
+
+/// A new request ID that is unique within this process and unlikely to
+/// collide with those of other instances.
+fn new_request_id() -> String {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(NEXT.fetch_add(1, Ordering::Relaxed));
+    format!("{:016x}", hasher.finish())
+}
+
+/// Tag every request with an ID, taken from `X-Request-Id` if the client or
+/// a reverse proxy set one, and turn plain-text error responses into
+/// `application/problem+json` bodies that include it and an [`ErrorCode`].
+pub async fn problems(mut request: Request<Body>, next: Next<Body>) -> Response {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty() && v.len() <= 128)
+        .map(str::to_string)
+        .unwrap_or_else(new_request_id);
+
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        request.headers_mut().insert(REQUEST_ID, value);
+    }
+
+    let instance = request.uri().path().to_string();
+    let mut response = next.run(request).await;
+
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID, value);
+    }
+
+    let status = response.status();
+    let code = response
+        .extensions()
+        .get::<ErrorCode>()
+        .copied()
+        .unwrap_or_else(|| ErrorCode::from_status(status));
+    let plain = match response.headers().get(header::CONTENT_TYPE) {
+        Some(content_type) => content_type
+            .to_str()
+            .map_or(false, |v| v.starts_with("text/plain")),
+        None => true,
+    };
+
+    if !(status.is_client_error() || status.is_server_error()) || !plain {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let detail = match body::to_bytes(body).await {
+        Ok(bytes) if !bytes.is_empty() => Some(String::from_utf8_lossy(&bytes).into_owned()),
+        Ok(_) => None,
+        Err(e) => {
+            eprintln!("Could not read error response: {e}");
+            None
+        }
+    };
+
+    let problem = Problem {
+        kind: "about:blank",
+        title: status.canonical_reason().unwrap_or("Error"),
+        status: status.as_u16(),
+        code,
+        detail,
+        instance,
+        request_id,
+    };
+
+    let body = match serde_json::to_vec(&problem) {
+        Ok(body) => body,
+        Err(e) => return (status, format!("Could not serialize error: {e}")).into_response(),
+    };
+
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/problem+json"),
+    );
+
+    Response::from_parts(parts, body::boxed(Body::from(body)))
+}
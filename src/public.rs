@@ -0,0 +1,159 @@
+use std::{collections::HashMap, path::Path as FsPath, sync::Arc, time::Duration};
+
+use axum::{
+    extract::{Path, Query},
+    http::{header, HeaderValue},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use duration_string::DurationString;
+use serde::Deserialize;
+
+use influxdb_temp_client::{Field, MetricPoint, RangeOptions, TimeRange};
+
+use crate::{
+    get_range, live,
+    problem::{ApiError, ErrorCode},
+    project,
+    spikes::SpikeFilter,
+    QueryLimits, RangeParams, SharedState,
+};
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TokenConfig {
+    name: String,
+    token: String,
+    field: String,
+    /// Longest range that can be queried with the token.
+    max_range: DurationString,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokensFile {
+    token: Vec<TokenConfig>,
+}
+
+/// A token that can be shared publicly, e.g. in a widget on a homepage, as
+/// it only grants access to recent data of one field.
+#[derive(Debug)]
+struct PublicToken {
+    name: String,
+    field: Field,
+    max_range: Duration,
+}
+
+/// Public tokens, by token.
+#[derive(Debug, Clone, Default)]
+pub struct PublicTokens(Arc<HashMap<String, PublicToken>>);
+
+impl PublicTokens {
+    fn get(&self, token: &str) -> Result<&PublicToken, ApiError> {
+        self.0
+            .get(token)
+            .ok_or_else(|| ApiError::new(ErrorCode::Unauthorized, "Unknown public token"))
+    }
+}
+
+/// Read the `[[token]]` tables of `path`.
+pub fn load(path: &FsPath) -> Result<PublicTokens, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+
+    let file: TokensFile =
+        toml::from_str(&text).map_err(|e| format!("Could not parse {}: {e}", path.display()))?;
+
+    let mut tokens = HashMap::new();
+    for config in file.token {
+        let field = config
+            .field
+            .parse()
+            .map_err(|e| format!("Public token {}: {e}", config.name))?;
+
+        let token = PublicToken {
+            name: config.name,
+            field,
+            max_range: config.max_range.into(),
+        };
+
+        if let Some(previous) = tokens.insert(config.token, token) {
+            return Err(format!(
+                "Public token {} has the same token as another one",
+                previous.name
+            ));
+        }
+    }
+
+    Ok(PublicTokens(Arc::new(tokens)))
+}
+
+/// Allow pages on other origins to fetch the data.
+fn allow_any_origin(mut response: Response) -> Response {
+    response.headers_mut().insert(
+        header::ACCESS_CONTROL_ALLOW_ORIGIN,
+        HeaderValue::from_static("*"),
+    );
+    response
+}
+
+/// The latest value of the field of the token, as seen by the live poller.
+pub async fn current(
+    Path(token): Path<String>,
+    Extension(tokens): Extension<PublicTokens>,
+    Extension(latest): Extension<live::Latest>,
+) -> impl IntoResponse {
+    let token = tokens.get(&token)?;
+
+    let point = latest
+        .borrow()
+        .as_ref()
+        .and_then(|p| MetricPoint::project(token.field, p));
+
+    match point {
+        Some(point) => Ok(allow_any_origin(Json(point).into_response())),
+        None => Err(ApiError::new(
+            ErrorCode::NoData,
+            format!("No recent {}", token.field.name()),
+        )),
+    }
+}
+
+pub async fn range(
+    Path((token, path)): Path<(String, String)>,
+    Query(params): Query<RangeParams>,
+    Extension(tokens): Extension<PublicTokens>,
+    Extension(client): Extension<SharedState>,
+    Extension(limits): Extension<QueryLimits>,
+    Extension(spikes): Extension<SpikeFilter>,
+) -> impl IntoResponse {
+    let token = tokens.get(&token)?;
+
+    let span = get_range(&path)?;
+    if span > token.max_range {
+        return Err(ApiError::new(
+            ErrorCode::RangeTooLarge,
+            format!(
+                "Public token {} can query at most {}",
+                token.name,
+                DurationString::from(token.max_range)
+            ),
+        ));
+    }
+
+    let options = RangeOptions {
+        fields: vec![token.field],
+        ..params.options()?
+    };
+
+    let range = TimeRange::Span(span);
+    let temps = params.fetch(&client, &limits, range, &options).await?;
+    let temps = temps
+        .filter(params.filter, &spikes)
+        .aggregate(params.aggregation(&range)?)
+        .interpolate(params.interpolate, limits.max_interpolation_gap)
+        .smooth(params.smoothing()?)
+        .downsample(&[token.field], params.downsample_to())
+        .cap(params.max_points);
+
+    project(token.field, temps, params.format, params.envelope).map(allow_any_origin)
+}
@@ -0,0 +1,95 @@
+use std::io::{self, Write};
+
+use clap::{Args, ValueEnum};
+use duration_string::DurationString;
+
+use influxdb_temp_client::{DataPoint, Field, RangeOptions, TimeRange};
+
+use crate::SharedState;
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Format {
+    Json,
+    Csv,
+}
+
+#[derive(Args)]
+pub struct QueryOpts {
+    #[clap(long, default_value = "1d")]
+    pub range: DurationString,
+    /// Fields to output. Defaults to all fields.
+    #[clap(long = "field", value_delimiter = ',')]
+    pub fields: Vec<Field>,
+    #[clap(long, value_enum, default_value = "json")]
+    pub format: Format,
+}
+
+pub async fn run(backend: SharedState, opts: QueryOpts) {
+    let fields = if opts.fields.is_empty() {
+        Field::ALL.to_vec()
+    } else {
+        opts.fields
+    };
+
+    let options = RangeOptions {
+        fields: fields.clone(),
+        ..Default::default()
+    };
+
+    let range = TimeRange::Span(opts.range.into());
+    let data = match backend.lock().await.get_range(range, &options).await {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("Could not fetch data: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    match opts.format {
+        Format::Json => print_json(&data, &fields),
+        Format::Csv => write_csv(io::stdout().lock(), &data, &fields).unwrap(),
+    }
+}
+
+fn print_json(data: &[DataPoint], fields: &[Field]) {
+    let rows: Vec<_> = data
+        .iter()
+        .map(|point| {
+            let mut row = serde_json::Map::new();
+            row.insert("time".to_string(), point.time.into());
+            for field in fields {
+                row.insert(field.name().to_string(), field.value(point).into());
+            }
+            row
+        })
+        .collect();
+
+    println!("{}", serde_json::to_string(&rows).unwrap());
+}
+
+pub fn write_csv<W: Write>(mut out: W, data: &[DataPoint], fields: &[Field]) -> io::Result<()> {
+    write_csv_header(&mut out, fields)?;
+    write_csv_rows(&mut out, data, fields)
+}
+
+pub fn write_csv_header<W: Write>(mut out: W, fields: &[Field]) -> io::Result<()> {
+    let header: Vec<_> = fields.iter().map(|f| f.name()).collect();
+    writeln!(out, "time,{}", header.join(","))
+}
+
+pub fn write_csv_rows<W: Write>(
+    mut out: W,
+    data: &[DataPoint],
+    fields: &[Field],
+) -> io::Result<()> {
+    for point in data {
+        let values: Vec<_> = fields
+            .iter()
+            .map(|f| f.value(point).map(|v| v.to_string()).unwrap_or_default())
+            .collect();
+
+        writeln!(out, "{},{}", point.time, values.join(","))?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,179 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::{self, Body, BoxBody, Bytes, HttpBody},
+    headers::{authorization::Bearer, Authorization, HeaderMapExt},
+    http::{header, HeaderMap, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use clap::Args;
+use duration_string::DurationString;
+use hyper::body::SizeHint;
+
+use crate::{access::ClientIp, HttpPassword};
+
+#[derive(Args)]
+pub struct QuotaOpts {
+    /// Length of the rolling window that quotas apply to.
+    #[clap(long, env = "QUOTA_WINDOW", default_value = "1h")]
+    pub quota_window: DurationString,
+    /// Maximum amount of requests per API key in the window.
+    #[clap(long, env = "QUOTA_REQUESTS")]
+    pub quota_requests: Option<u64>,
+    /// Maximum amount of response bytes per API key in the window.
+    #[clap(long, env = "QUOTA_BYTES")]
+    pub quota_bytes: Option<u64>,
+}
+
+/// Usage of every API key over a rolling window. Requests authenticated with
+/// the HTTP password are not limited.
+pub struct Quotas {
+    window: Duration,
+    max_requests: Option<u64>,
+    max_bytes: Option<u64>,
+    /// The start and the bytes sent so far of every request in the window.
+    usage: Mutex<HashMap<String, VecDeque<(Instant, Arc<AtomicU64>)>>>,
+}
+
+impl Quotas {
+    pub fn new(opts: &QuotaOpts) -> Arc<Self> {
+        Arc::new(Self {
+            window: opts.quota_window.into(),
+            max_requests: opts.quota_requests,
+            max_bytes: opts.quota_bytes,
+            usage: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Check whether `key` may make another request. Returns how long to wait
+    /// if it may not.
+    fn check(&self, key: &str) -> Result<(), Duration> {
+        let mut usage = self.usage.lock().unwrap();
+        let Some(requests) = usage.get_mut(key) else {
+            return Ok(());
+        };
+
+        while let Some((time, _)) = requests.front() {
+            if time.elapsed() < self.window {
+                break;
+            }
+            requests.pop_front();
+        }
+
+        let over_requests = self
+            .max_requests
+            .is_some_and(|max| requests.len() as u64 >= max);
+        let over_bytes = self.max_bytes.is_some_and(|max| {
+            requests
+                .iter()
+                .map(|(_, b)| b.load(Ordering::Relaxed))
+                .sum::<u64>()
+                >= max
+        });
+
+        match requests.front() {
+            Some((oldest, _)) if over_requests || over_bytes => {
+                Err(self.window.saturating_sub(oldest.elapsed()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Record a request of `key`. Returns the counter for the bytes of its
+    /// response.
+    fn record(&self, key: &str) -> Arc<AtomicU64> {
+        let bytes = Arc::new(AtomicU64::new(0));
+        let mut usage = self.usage.lock().unwrap();
+        usage
+            .entry(key.to_string())
+            .or_default()
+            .push_back((Instant::now(), bytes.clone()));
+        bytes
+    }
+}
+
+/// A response body that counts the bytes that are actually sent, which
+/// aren't known up front for streamed responses.
+struct Counted {
+    inner: BoxBody,
+    bytes: Arc<AtomicU64>,
+}
+
+impl HttpBody for Counted {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let poll = Pin::new(&mut self.inner).poll_data(cx);
+        if let Poll::Ready(Some(Ok(data))) = &poll {
+            self.bytes.fetch_add(data.len() as u64, Ordering::Relaxed);
+        }
+        poll
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+pub async fn enforce(
+    Extension(quotas): Extension<Arc<Quotas>>,
+    Extension(password): Extension<HttpPassword>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let key = request
+        .headers()
+        .typed_get::<Authorization<Bearer>>()
+        .and_then(|auth| password.api_key(auth.token()).map(str::to_string));
+
+    let Some(key) = key else {
+        return next.run(request).await;
+    };
+
+    if let Err(retry_after) = quotas.check(&key) {
+        if let Some(ClientIp(ip)) = request.extensions().get() {
+            println!("Quota for API key {key} exceeded, rejecting request from {ip}");
+        }
+
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(
+                header::RETRY_AFTER,
+                retry_after.as_secs().max(1).to_string(),
+            )],
+            format!("Quota for API key {key} exceeded"),
+        )
+            .into_response();
+    }
+
+    let bytes = quotas.record(&key);
+    next.run(request)
+        .await
+        .map(|inner| body::boxed(Counted { inner, bytes }))
+}
@@ -0,0 +1,108 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{extract::Query, response::IntoResponse, Extension, Json};
+
+use influxdb_temp_client::{DataPoint, Field, MetricPoint, Records};
+
+use crate::{
+    calibration::Calibration, problem::ApiError, scope::ReadAccess, NoFilter, SharedState,
+};
+
+/// How long records are served before all points are scanned again, for
+/// points that reach the backend without being ingested here.
+const MAX_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// The most recent scan for records, shared by all requests so that only one
+/// scan runs at a time.
+#[derive(Clone, Default)]
+pub struct RecordsCache {
+    scanned: Arc<Mutex<Option<(Instant, Vec<Records>)>>>,
+    /// Held while scanning, without blocking requests for fresh records.
+    scan: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl RecordsCache {
+    /// Scan again on the next request, e.g. after bad points were removed.
+    pub fn clear(&self) {
+        *self.scanned.lock().unwrap() = None;
+    }
+
+    /// Take ingested `points` into account, so that new records show up
+    /// before the next scan.
+    pub fn update(&self, points: &[DataPoint], calibration: &Calibration) {
+        let mut scanned = self.scanned.lock().unwrap();
+        let Some((_, records)) = scanned.as_mut() else {
+            return;
+        };
+
+        for point in points {
+            let mut point = *point;
+            calibration.apply_point(&mut point);
+
+            for field in Field::ALL {
+                let Some(value) = field.value(&point) else {
+                    continue;
+                };
+
+                match records.iter_mut().find(|r| r.field == field) {
+                    Some(record) => {
+                        if value < record.min.1 {
+                            record.min = MetricPoint(point.time, value);
+                        }
+                        if value > record.max.1 {
+                            record.max = MetricPoint(point.time, value);
+                        }
+                    }
+                    None => records.push(Records {
+                        field,
+                        min: MetricPoint(point.time, value),
+                        max: MetricPoint(point.time, value),
+                    }),
+                }
+            }
+        }
+    }
+
+    fn fresh(&self) -> Option<Vec<Records>> {
+        match self.scanned.lock().unwrap().as_ref() {
+            Some((scanned, records)) if scanned.elapsed() < MAX_AGE => Some(records.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// The lowest and highest value of every field ever recorded, with their
+/// times.
+pub async fn records(
+    Query(filter): Query<NoFilter>,
+    Extension(client): Extension<SharedState>,
+    Extension(cache): Extension<RecordsCache>,
+    _: ReadAccess,
+) -> impl IntoResponse {
+    filter.check()?;
+
+    if let Some(records) = cache.fresh() {
+        return Ok(Json(records));
+    }
+
+    let _scan = cache.scan.lock().await;
+    // Another request may have scanned while this one waited.
+    if let Some(records) = cache.fresh() {
+        return Ok(Json(records));
+    }
+
+    let start = Instant::now();
+    // Only hold the backend while starting the scan, not while it runs.
+    let scan = client.lock().await.scan_records();
+    let records = scan.await.map_err(ApiError::backend)?;
+    println!(
+        "Took {} ms to scan for records",
+        start.elapsed().as_millis()
+    );
+
+    *cache.scanned.lock().unwrap() = Some((Instant::now(), records.clone()));
+    Ok::<_, ApiError>(Json(records))
+}
@@ -0,0 +1,79 @@
+use axum::{
+    body::{boxed, Body, Full},
+    extract::MatchedPath,
+    http::{header, Request, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use sentry::ClientInitGuard;
+
+pub fn init(dsn: &str) -> ClientInitGuard {
+    let guard = sentry::init((
+        dsn,
+        sentry::ClientOptions {
+            release: sentry::release_name!(),
+            send_default_pii: false,
+            before_send: Some(std::sync::Arc::new(|mut event| {
+                if let Some(request) = &mut event.request {
+                    request.headers.remove(header::AUTHORIZATION.as_str());
+                    request.headers.remove(header::COOKIE.as_str());
+                    request.cookies = None;
+                }
+                Some(event)
+            })),
+            ..Default::default()
+        },
+    ));
+
+    println!("Reporting errors to Sentry");
+
+    guard
+}
+
+/// Report every response with a 5xx status to Sentry, including the route
+/// that produced it and the error message in the body. Requests for what the
+/// backend doesn't support (501) aren't errors of the server.
+pub async fn report_server_errors(
+    matched_path: Option<MatchedPath>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let method = request.method().clone();
+    let route = matched_path
+        .as_ref()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_else(|| request.uri().path().to_string());
+
+    let response = next.run(request).await;
+
+    let status = response.status();
+    if !status.is_server_error() || status == StatusCode::NOT_IMPLEMENTED {
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(v) => v,
+        Err(e) => format!("Could not read response body: {e}").into(),
+    };
+
+    sentry::with_scope(
+        |scope| {
+            scope.set_tag("route", &route);
+            scope.set_tag("method", &method);
+            scope.set_tag("status", parts.status.as_u16());
+        },
+        || {
+            sentry::capture_message(
+                &format!(
+                    "{method} {route} returned {}: {}",
+                    parts.status,
+                    String::from_utf8_lossy(&body)
+                ),
+                sentry::Level::Error,
+            )
+        },
+    );
+
+    Response::from_parts(parts, boxed(Full::from(body)))
+}
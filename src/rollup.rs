@@ -0,0 +1,155 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::Utc;
+use influxdb_temp_client::{Client, Resolution, Rollups};
+
+use crate::{admin::Stats, tasks::TaskApi};
+
+/// How often the most recent windows are rolled up again.
+const INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// How much history to roll up per query when filling the rollups.
+const BACKFILL_CHUNK_MS: i64 = 30 * 24 * 60 * 60 * 1000;
+
+fn align(time: i64, window: i64) -> i64 {
+    time - time.rem_euclid(window)
+}
+
+/// Roll up everything that isn't rolled up yet.
+async fn backfill(client: &Client, rollups: &Rollups) -> Result<(), String> {
+    let now = Utc::now().timestamp_millis();
+    let Some(oldest) = client.get_retention().await?.oldest else {
+        return Ok(());
+    };
+
+    for resolution in Resolution::ALL {
+        let window = resolution.window_ms() as i64;
+
+        // Windows are timestamped with their end, and the most recent one
+        // may have been incomplete.
+        let start = match client.latest_rollup(rollups.bucket(), resolution).await? {
+            Some(latest) => latest - window,
+            None => oldest,
+        };
+
+        let mut start = align(start.max(oldest), window);
+        while start < now {
+            let stop = (start + BACKFILL_CHUNK_MS).min(now);
+            client
+                .rollup(rollups.bucket(), resolution, start, stop)
+                .await?;
+            start = stop;
+        }
+    }
+
+    Ok(())
+}
+
+/// Roll up the previous and the current window again, which may have
+/// received points since the last run.
+async fn refresh(client: &Client, rollups: &Rollups) -> Result<(), String> {
+    let now = Utc::now().timestamp_millis();
+
+    for resolution in Resolution::ALL {
+        let window = resolution.window_ms() as i64;
+        let start = align(now, window) - window;
+        client
+            .rollup(rollups.bucket(), resolution, start, now)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// The name of the InfluxDB task of `resolution` in [`Stats`].
+fn task_stat(resolution: Resolution) -> &'static str {
+    match resolution {
+        Resolution::Hourly => "rollup_task_1h",
+        Resolution::Daily => "rollup_task_1d",
+    }
+}
+
+/// Create or update the InfluxDB tasks that keep `rollups` up to date.
+async fn setup_tasks(api: &TaskApi, rollups: &Rollups) -> Result<(), String> {
+    for resolution in Resolution::ALL {
+        api.ensure(
+            &rollups.task_name(resolution),
+            &rollups.task_flux(resolution)?,
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Record the status of the InfluxDB tasks in `stats`.
+async fn check_tasks(api: &TaskApi, rollups: &Rollups, stats: &Stats) {
+    for resolution in Resolution::ALL {
+        let name = task_stat(resolution);
+        match api.find(&rollups.task_name(resolution)).await {
+            Ok(Some(task)) => stats.task_reported(name, task.last_run(), task.last_error()),
+            Ok(None) => stats.task_reported(name, None, Some("missing".to_string())),
+            Err(e) => stats.task_reported(name, None, Some(e)),
+        }
+    }
+}
+
+pub struct Setup {
+    pub client: Client,
+    pub rollups: Rollups,
+    /// Keep the rollups up to date with InfluxDB tasks.
+    pub tasks: Option<TaskApi>,
+}
+
+/// Fill the rollups and keep them up to date. Queries are served from the
+/// rollups once they have been filled.
+pub fn spawn(setup: Setup, stats: Arc<Stats>) {
+    let Setup {
+        client,
+        rollups,
+        mut tasks,
+    } = setup;
+
+    tokio::spawn(async move {
+        // Set up the tasks first, so that nothing is missed between the
+        // backfill and their first run.
+        if let Some(api) = &tasks {
+            if let Err(e) = setup_tasks(api, &rollups).await {
+                eprintln!("{e}, updating rollups from the server instead");
+                tasks = None;
+            }
+        }
+
+        while let Err(e) = backfill(&client, &rollups).await {
+            eprintln!("Could not fill rollups: {e}");
+            stats.task_ran("rollup", Err(&e));
+            tokio::time::sleep(INTERVAL).await;
+        }
+
+        println!("Serving long ranges from rollups in {}", rollups.bucket());
+        rollups.set_ready();
+        stats.task_ran("rollup", Ok(()));
+
+        let mut interval = tokio::time::interval(INTERVAL);
+        // The first tick completes immediately, right after the backfill.
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+
+            if let Some(api) = &tasks {
+                check_tasks(api, &rollups, &stats).await;
+                continue;
+            }
+
+            let result = refresh(&client, &rollups).await;
+            if let Err(e) = &result {
+                eprintln!("Could not update rollups: {e}");
+            }
+            stats.task_ran(
+                "rollup",
+                result.as_ref().map(|_| ()).map_err(String::as_str),
+            );
+        }
+    });
+}
@@ -0,0 +1,92 @@
+use std::fmt;
+
+use async_trait::async_trait;
+use axum::{
+    extract::FromRequestParts,
+    headers::{authorization::Bearer, Authorization},
+    http::request::Parts,
+    Extension, TypedHeader,
+};
+
+use crate::{
+    problem::{ApiError, ErrorCode},
+    HttpPassword,
+};
+
+/// What a token may do. Each scope includes the ones before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Scope {
+    /// Query data.
+    Read,
+    /// Change state: ingestion and the `/admin` endpoints.
+    Admin,
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scope::Read => write!(f, "read"),
+            Scope::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+/// Check that the bearer token of the request has `required`.
+async fn authorize<S: Send + Sync>(
+    parts: &mut Parts,
+    state: &S,
+    required: Scope,
+) -> Result<(), ApiError> {
+    let Extension(password) = Extension::<HttpPassword>::from_request_parts(parts, state)
+        .await
+        .map_err(|e| ApiError::new(ErrorCode::Internal, e.to_string()))?;
+
+    if required == Scope::Admin && !password.admin_enabled() {
+        return Err(ApiError::new(
+            ErrorCode::AdminDisabled,
+            "No admin password or admin API key is configured",
+        ));
+    }
+
+    let TypedHeader(Authorization(bearer)) =
+        TypedHeader::<Authorization<Bearer>>::from_request_parts(parts, state)
+            .await
+            .map_err(|_| ApiError::new(ErrorCode::Unauthorized, "Missing bearer token"))?;
+
+    match password.scope(bearer.token()) {
+        Some(scope) if scope >= required => Ok(()),
+        Some(_) => Err(ApiError::new(
+            ErrorCode::Forbidden,
+            format!("This token lacks the {required} scope"),
+        )),
+        None => Err(ApiError::new(ErrorCode::Unauthorized, "Invalid password")),
+    }
+}
+
+/// Rejects requests without a token that may read data.
+pub struct ReadAccess;
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ReadAccess {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        authorize(parts, state, Scope::Read)
+            .await
+            .map(|_| ReadAccess)
+    }
+}
+
+/// Rejects requests without an admin token.
+pub struct AdminAccess;
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for AdminAccess {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        authorize(parts, state, Scope::Admin)
+            .await
+            .map(|_| AdminAccess)
+    }
+}
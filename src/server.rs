@@ -0,0 +1,145 @@
+use std::{future::poll_fn, net::SocketAddr, path::PathBuf, pin::Pin, sync::Arc, time::Duration};
+
+use axum::{extract::ConnectInfo, Extension, Router};
+use axum_server::{
+    accept::Accept,
+    tls_rustls::{RustlsAcceptor, RustlsConfig},
+};
+use clap::Args;
+use duration_string::DurationString;
+use hyper::server::{
+    accept::Accept as _,
+    conn::{AddrIncoming, Http},
+};
+use tokio::sync::Semaphore;
+use tower::Layer;
+
+#[derive(Args)]
+pub struct ServerOpts {
+    /// PEM encoded certificate chain. Enables TLS (and HTTP/2 through ALPN).
+    #[clap(long, env = "TLS_CERT", requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+    /// PEM encoded private key belonging to `tls_cert`.
+    #[clap(long, env = "TLS_KEY", requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+    /// Accept HTTP/2 with prior knowledge (h2c) on the plaintext listener.
+    #[clap(long, env = "H2C")]
+    pub h2c: bool,
+    /// UDP port to serve HTTP/3 on. Requires TLS to be configured.
+    #[cfg(feature = "http3")]
+    #[clap(long, env = "HTTP3_PORT", requires = "tls_cert")]
+    pub http3_port: Option<u16>,
+    /// Disable HTTP/1.1 keep-alive, closing connections after every response.
+    #[clap(long, env = "NO_KEEP_ALIVE")]
+    pub no_keep_alive: bool,
+    /// Interval of the TCP keep-alive probes and HTTP/2 PING frames used to
+    /// detect dead peers. Idle connections to live peers are not closed.
+    #[clap(long, env = "TCP_KEEPALIVE", default_value = "60s")]
+    pub tcp_keepalive: DurationString,
+    /// Maximum amount of simultaneously served connections.
+    #[clap(long, env = "MAX_CONNECTIONS", default_value = "64")]
+    pub max_connections: usize,
+    /// Maximum size of the HTTP/1.1 request head buffer, in bytes (minimum 8192).
+    #[clap(long, env = "MAX_HEADER_SIZE", default_value = "16384")]
+    pub max_header_size: usize,
+}
+
+pub async fn serve(app: Router, addr: SocketAddr, opts: ServerOpts) {
+    let keep_alive: Duration = opts.tcp_keepalive.into();
+
+    let mut http = Http::new();
+    http.http1_only(opts.tls_cert.is_none() && !opts.h2c)
+        .http1_keep_alive(!opts.no_keep_alive)
+        .max_buf_size(opts.max_header_size.max(8192))
+        .http2_keep_alive_interval(Some(keep_alive))
+        .http2_keep_alive_timeout(keep_alive);
+
+    let permits = Arc::new(Semaphore::new(opts.max_connections));
+
+    let tls = match (opts.tls_cert, opts.tls_key) {
+        (Some(cert), Some(key)) => match RustlsConfig::from_pem_file(cert, key).await {
+            Ok(v) => Some(v),
+            Err(e) => {
+                eprintln!("Could not load the TLS certificate and key: {e}");
+                std::process::exit(1);
+            }
+        },
+        _ => None,
+    };
+
+    // Only advertise HTTP/3 once it is actually being served.
+    #[cfg(feature = "http3")]
+    let app = match (&tls, opts.http3_port) {
+        (Some(config), Some(port)) => {
+            use axum::http::{header::ALT_SVC, HeaderValue};
+            use tower_http::set_header::SetResponseHeaderLayer;
+
+            let mut h3_addr = addr;
+            h3_addr.set_port(port);
+
+            let endpoint = match crate::http3::bind(h3_addr, &config.get_inner()) {
+                Ok(v) => v,
+                Err(e) => {
+                    eprintln!("Could not serve HTTP/3 on UDP port {port}: {e}");
+                    std::process::exit(1);
+                }
+            };
+            tokio::spawn(crate::http3::serve(app.clone(), endpoint, permits.clone()));
+
+            let alt_svc = HeaderValue::try_from(format!("h3=\":{port}\"")).unwrap();
+            app.layer(SetResponseHeaderLayer::if_not_present(ALT_SVC, alt_svc))
+        }
+        _ => app,
+    };
+
+    let mut incoming = match AddrIncoming::bind(&addr) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("Could not listen on {addr}: {e}");
+            std::process::exit(1);
+        }
+    };
+    incoming.set_nodelay(true).set_keepalive(Some(keep_alive));
+
+    if tls.is_some() {
+        println!("Serving HTTP/1.1 and HTTP/2 over TLS");
+    } else if opts.h2c {
+        println!("Serving HTTP/1.1 and h2c");
+    }
+
+    let acceptor = tls.map(RustlsAcceptor::new);
+
+    loop {
+        // Wait for a free slot before taking a socket off the listen queue, so
+        // that connections over the limit stay in the kernel's backlog
+        // instead of being held open without being served.
+        let permit = permits.clone().acquire_owned().await.unwrap();
+
+        let stream = match poll_fn(|cx| Pin::new(&mut incoming).poll_accept(cx)).await {
+            Some(Ok(v)) => v,
+            Some(Err(e)) => {
+                eprintln!("Could not accept connection: {e}");
+                continue;
+            }
+            None => break,
+        };
+
+        let service = Extension(ConnectInfo(stream.remote_addr())).layer(app.clone());
+        let acceptor = acceptor.clone();
+        let http = http.clone();
+
+        tokio::spawn(async move {
+            let _permit = permit;
+
+            let _ = match acceptor {
+                Some(acceptor) => {
+                    let Ok((stream, service)) = acceptor.accept(stream, service).await else {
+                        return;
+                    };
+                    http.serve_connection(stream, service).with_upgrades().await
+                }
+                None => http.serve_connection(stream, service).with_upgrades().await,
+            };
+        });
+    }
+}
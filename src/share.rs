@@ -0,0 +1,178 @@
+use std::{fmt::Write, sync::Arc, time::Duration};
+
+use axum::{
+    extract::{Path, Query},
+    response::IntoResponse,
+    Extension, Json,
+};
+use chrono::Utc;
+use clap::Args;
+use duration_string::DurationString;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use influxdb_temp_client::{RangeOptions, TimeRange};
+
+use crate::{
+    config::ApiBase,
+    fetch, get_range,
+    problem::{ApiError, ErrorCode},
+    respond,
+    scope::ReadAccess,
+    QueryLimits, SharedState,
+};
+
+#[derive(Args)]
+pub struct ShareOpts {
+    /// Secret to sign share URLs with. Sharing is disabled if not set, and
+    /// changing it invalidates all shared URLs.
+    #[clap(long, env = "SHARE_SECRET")]
+    pub share_secret: Option<String>,
+    /// Longest time a shared URL can be valid for.
+    #[clap(long, env = "SHARE_MAX_EXPIRY", default_value = "30d")]
+    pub share_max_expiry: DurationString,
+}
+
+#[derive(Clone)]
+pub struct Signer {
+    key: Option<Arc<[u8]>>,
+    max_expiry: Duration,
+}
+
+impl Signer {
+    pub fn new(opts: &ShareOpts) -> Self {
+        Self {
+            key: opts.share_secret.as_deref().map(|s| s.as_bytes().into()),
+            max_expiry: opts.share_max_expiry.into(),
+        }
+    }
+
+    /// The MAC of a share of `range` under `base` until `expires`. The base
+    /// is included so that shares of one tenant aren't valid for another.
+    fn mac(&self, base: &str, range: &str, expires: i64) -> Result<Hmac<Sha256>, ApiError> {
+        let Some(key) = &self.key else {
+            return Err(ApiError::new(
+                ErrorCode::NotConfigured,
+                "Sharing is disabled",
+            ));
+        };
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(key)
+            .map_err(|e| ApiError::new(ErrorCode::Internal, e.to_string()))?;
+        mac.update(format!("{base}\n{range}\n{expires}").as_bytes());
+        Ok(mac)
+    }
+
+    fn sign(&self, base: &str, range: &str, expires: i64) -> Result<String, ApiError> {
+        let signature = self.mac(base, range, expires)?.finalize().into_bytes();
+
+        let mut hex = String::with_capacity(signature.len() * 2);
+        for byte in signature {
+            let _ = write!(hex, "{byte:02x}");
+        }
+        Ok(hex)
+    }
+
+    fn verify(
+        &self,
+        base: &str,
+        signature: &str,
+        range: &str,
+        expires: i64,
+    ) -> Result<(), ApiError> {
+        let mac = self.mac(base, range, expires)?;
+
+        let valid = from_hex(signature).map_or(false, |bytes| mac.verify_slice(&bytes).is_ok());
+        if !valid {
+            return Err(ApiError::new(
+                ErrorCode::BadSignature,
+                "Invalid share signature",
+            ));
+        }
+
+        if expires <= Utc::now().timestamp() {
+            return Err(ApiError::new(
+                ErrorCode::ShareExpired,
+                "This link has expired",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+fn from_hex(input: &str) -> Option<Vec<u8>> {
+    if input.len() % 2 != 0 {
+        return None;
+    }
+
+    (0..input.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(input.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ShareRequest {
+    /// E.g. `7d`: the most recent week at the time the URL is opened.
+    range: String,
+    /// How long the URL is valid for. Defaults to the maximum.
+    expires_in: Option<DurationString>,
+}
+
+#[derive(Debug, Serialize)]
+struct ShareResponse {
+    url: String,
+    /// Unix timestamp in seconds.
+    expires: i64,
+}
+
+/// Mint a URL that grants read access to `range` without a password.
+pub async fn create(
+    Extension(signer): Extension<Signer>,
+    Extension(limits): Extension<QueryLimits>,
+    Extension(ApiBase(base)): Extension<ApiBase>,
+    _: ReadAccess,
+    Json(request): Json<ShareRequest>,
+) -> impl IntoResponse {
+    let span = get_range(&request.range)?;
+    limits.check_range(&TimeRange::Span(span))?;
+    let range = DurationString::from(span).to_string();
+
+    let valid_for = request
+        .expires_in
+        .map_or(signer.max_expiry, Duration::from)
+        .min(signer.max_expiry);
+    let expires = Utc::now().timestamp() + valid_for.as_secs() as i64;
+
+    let signature = signer.sign(&base, &range, expires)?;
+
+    Ok::<_, ApiError>(Json(ShareResponse {
+        url: format!("{base}/share/{signature}?range={range}&expires={expires}"),
+        expires,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SharedQuery {
+    range: String,
+    expires: i64,
+}
+
+/// The data of a shared URL.
+pub async fn shared(
+    Path(signature): Path<String>,
+    Query(query): Query<SharedQuery>,
+    Extension(signer): Extension<Signer>,
+    Extension(client): Extension<SharedState>,
+    Extension(limits): Extension<QueryLimits>,
+    Extension(ApiBase(base)): Extension<ApiBase>,
+) -> impl IntoResponse {
+    signer.verify(&base, &signature, &query.range, query.expires)?;
+
+    let range = TimeRange::Span(get_range(&query.range)?);
+    let temps = fetch(&client, &limits, range, &RangeOptions::default()).await?;
+
+    respond(temps, Default::default(), false)
+}
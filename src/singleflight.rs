@@ -0,0 +1,141 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    hash::Hash,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use futures_util::{
+    future::{BoxFuture, Shared},
+    FutureExt,
+};
+
+/// Coalesces identical concurrent operations: callers with the same key
+/// share the result of the first one instead of running their own.
+///
+/// Nothing is spawned. The operation is driven by whoever awaits it, and is
+/// dropped, along with its entry, once every caller is gone.
+pub struct SingleFlight<K, V: Clone> {
+    inflight: Arc<Mutex<HashMap<K, Entry<V>>>>,
+    joined: Arc<AtomicU64>,
+    next_id: Arc<AtomicU64>,
+}
+
+struct Entry<V: Clone> {
+    id: u64,
+    shared: Shared<BoxFuture<'static, V>>,
+    waiters: usize,
+}
+
+/// Removes the entry of a call once it finished, or once its last waiter
+/// was dropped, e.g. because the client disconnected.
+struct Waiter<'a, K, V: Clone> {
+    inflight: &'a Mutex<HashMap<K, Entry<V>>>,
+    id: u64,
+    done: bool,
+}
+
+impl<K, V: Clone> Drop for Waiter<'_, K, V> {
+    fn drop(&mut self) {
+        let mut removed = Vec::new();
+        let mut inflight = self.inflight.lock().unwrap();
+
+        inflight.retain(|_, entry| {
+            if entry.id != self.id {
+                return true;
+            }
+
+            entry.waiters -= 1;
+            // Whoever finishes first removes the entry, so that later calls
+            // run the operation again.
+            if self.done || entry.waiters == 0 {
+                removed.push(entry.shared.clone());
+                false
+            } else {
+                true
+            }
+        });
+
+        // Drop an abandoned operation outside of the lock.
+        drop(inflight);
+        drop(removed);
+    }
+}
+
+impl<K, V: Clone> Clone for SingleFlight<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            inflight: self.inflight.clone(),
+            joined: self.joined.clone(),
+            next_id: self.next_id.clone(),
+        }
+    }
+}
+
+impl<K, V: Clone> fmt::Debug for SingleFlight<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SingleFlight")
+            .field("inflight", &self.inflight.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl<K: Hash + Eq, V: Clone + Send + Sync + 'static> SingleFlight<K, V> {
+    pub fn new() -> Self {
+        Self {
+            inflight: Arc::new(Mutex::new(HashMap::new())),
+            joined: Arc::new(AtomicU64::new(0)),
+            next_id: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// How many calls shared the result of another one.
+    pub fn joined(&self) -> u64 {
+        self.joined.load(Ordering::Relaxed)
+    }
+
+    /// The result of `operation`, or of an identical one that is already
+    /// running.
+    pub async fn run<F, Fut>(&self, key: K, operation: F) -> V
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = V> + Send + 'static,
+    {
+        let (id, shared) = {
+            let mut inflight = self.inflight.lock().unwrap();
+            match inflight.get_mut(&key) {
+                Some(entry) => {
+                    self.joined.fetch_add(1, Ordering::Relaxed);
+                    entry.waiters += 1;
+                    (entry.id, entry.shared.clone())
+                }
+                None => {
+                    let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+                    let shared = operation().boxed().shared();
+                    let entry = Entry {
+                        id,
+                        shared: shared.clone(),
+                        waiters: 1,
+                    };
+                    inflight.insert(key, entry);
+                    (id, shared)
+                }
+            }
+        };
+
+        let mut waiter = Waiter {
+            inflight: &self.inflight,
+            id,
+            done: false,
+        };
+
+        let result = shared.await;
+        waiter.done = true;
+
+        result
+    }
+}
@@ -0,0 +1,96 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use clap::Args;
+
+use influxdb_temp_client::{transform::Plausible, Field};
+
+#[derive(Args)]
+pub struct SpikeOpts {
+    /// Plausible values of a field as `field=min..max`, where either bound
+    /// may be left out. `?filter=spikes` drops values outside of them.
+    #[clap(
+        long = "plausible-range",
+        env = "PLAUSIBLE_RANGES",
+        value_delimiter = ',',
+        value_parser = parse_range,
+        default_value = "temperature=-40..85,humidity=0..100,co2=0.."
+    )]
+    pub ranges: Vec<(Field, Option<f64>, Option<f64>)>,
+    /// Largest plausible change of a field from one sample to the next, as
+    /// `field=step`. `?filter=spikes` drops values that differ more from both
+    /// of their neighbours.
+    #[clap(long = "max-step", env = "MAX_STEPS", value_delimiter = ',', value_parser = parse_step)]
+    pub steps: Vec<(Field, f64)>,
+}
+
+fn parse_bound(bound: &str) -> Result<Option<f64>, String> {
+    if bound.is_empty() {
+        return Ok(None);
+    }
+
+    bound
+        .parse()
+        .map(Some)
+        .map_err(|e| format!("Invalid bound {bound}: {e}"))
+}
+
+fn parse_range(input: &str) -> Result<(Field, Option<f64>, Option<f64>), String> {
+    let Some((field, range)) = input.split_once('=') else {
+        return Err(format!("Expected <field>=<min>..<max>, got {input}."));
+    };
+    let Some((min, max)) = range.split_once("..") else {
+        return Err(format!("Expected <min>..<max>, got {range}."));
+    };
+
+    Ok((field.parse()?, parse_bound(min)?, parse_bound(max)?))
+}
+
+fn parse_step(input: &str) -> Result<(Field, f64), String> {
+    match input.split_once('=') {
+        Some((field, step)) => Ok((
+            field.parse()?,
+            step.parse()
+                .map_err(|e| format!("Invalid step {step}: {e}"))?,
+        )),
+        None => Err(format!("Expected <field>=<step>, got {input}.")),
+    }
+}
+
+/// What `?filter=spikes` considers plausible, per field.
+#[derive(Debug, Clone, Default)]
+pub struct SpikeFilter(Arc<BTreeMap<Field, Plausible>>);
+
+impl SpikeFilter {
+    pub fn new(opts: &SpikeOpts) -> Result<Self, String> {
+        let mut plausible = BTreeMap::new();
+
+        for &(field, min, max) in &opts.ranges {
+            if let (Some(min), Some(max)) = (min, max) {
+                if min >= max {
+                    return Err(format!("The plausible range of {} is empty", field.name()));
+                }
+            }
+
+            let bounds: &mut Plausible = plausible.entry(field).or_default();
+            bounds.min = min;
+            bounds.max = max;
+        }
+
+        for &(field, step) in &opts.steps {
+            if step <= 0. {
+                return Err(format!(
+                    "The maximum step of {} must be positive",
+                    field.name()
+                ));
+            }
+
+            plausible.entry(field).or_default().max_step = Some(step);
+        }
+
+        Ok(Self(Arc::new(plausible)))
+    }
+
+    pub fn plausible(&self) -> &BTreeMap<Field, Plausible> {
+        &self.0
+    }
+}
@@ -0,0 +1,256 @@
+use std::{fmt::Write, sync::Arc};
+
+use chrono::{DateTime, Datelike, Duration, NaiveTime, TimeZone, Utc};
+use serde::Deserialize;
+
+use influxdb_temp_client::{DataPoint, Field, RangeOptions, TimeRange};
+
+use crate::{
+    alerts::{History, Severity},
+    notify::{self, Attachment, Notification, Notifier},
+    SharedState,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Period {
+    Daily,
+    /// Sent on Mondays.
+    Weekly,
+}
+
+impl Period {
+    fn duration(&self) -> Duration {
+        match self {
+            Period::Daily => Duration::days(1),
+            Period::Weekly => Duration::weeks(1),
+        }
+    }
+}
+
+/// A periodic summary, as configured in the alerts file:
+///
+/// ```toml
+/// [summary]
+/// period = "daily"
+/// at = "07:30"
+/// chart = true
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct SummaryConfig {
+    pub period: Period,
+    /// Time of day to send the summary at, as `HH:MM` in UTC. Defaults to
+    /// midnight.
+    pub at: Option<String>,
+    /// Attach a chart of every field to channels that support attachments.
+    #[serde(default)]
+    pub chart: bool,
+}
+
+impl SummaryConfig {
+    /// The first time after `now` that a summary is due.
+    pub fn next_after(&self, now: DateTime<Utc>) -> Result<DateTime<Utc>, String> {
+        let at = match &self.at {
+            Some(at) => NaiveTime::parse_from_str(at, "%H:%M")
+                .map_err(|e| format!("Invalid summary time {at}: {e}"))?,
+            None => NaiveTime::MIN,
+        };
+
+        let mut next = Utc.from_utc_datetime(&now.date_naive().and_time(at));
+
+        if self.period == Period::Weekly {
+            let days = (7 - next.weekday().num_days_from_monday()) % 7;
+            next += Duration::days(days as i64);
+        }
+
+        while next <= now {
+            next += self.period.duration();
+        }
+
+        Ok(next)
+    }
+}
+
+#[derive(Debug)]
+struct FieldSummary {
+    field: Field,
+    min: f64,
+    max: f64,
+    mean: f64,
+}
+
+fn summarize(field: Field, points: &[DataPoint]) -> Option<FieldSummary> {
+    let values: Vec<f64> = points.iter().filter_map(|p| field.value(p)).collect();
+
+    if values.is_empty() {
+        return None;
+    }
+
+    Some(FieldSummary {
+        field,
+        min: values.iter().copied().fold(f64::INFINITY, f64::min),
+        max: values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+        mean: values.iter().sum::<f64>() / values.len() as f64,
+    })
+}
+
+/// Render `field` as a line chart.
+fn chart(summary: &FieldSummary, points: &[DataPoint]) -> String {
+    const WIDTH: f64 = 800.;
+    const HEIGHT: f64 = 300.;
+    const PADDING: f64 = 40.;
+
+    let (Some(first), Some(last)) = (points.first(), points.last()) else {
+        return String::new();
+    };
+
+    let time_span = ((last.time - first.time) as f64).max(1.);
+    let value_span = (summary.max - summary.min).max(f64::EPSILON);
+
+    let mut line = String::new();
+    for point in points {
+        let Some(value) = summary.field.value(point) else {
+            continue;
+        };
+
+        let x = PADDING + (point.time - first.time) as f64 / time_span * (WIDTH - 2. * PADDING);
+        let y = HEIGHT - PADDING - (value - summary.min) / value_span * (HEIGHT - 2. * PADDING);
+        let _ = write!(line, "{x:.1},{y:.1} ");
+    }
+
+    format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{WIDTH}" height="{HEIGHT}" font-family="sans-serif" font-size="12">
+<rect width="100%" height="100%" fill="white"/>
+<text x="{PADDING}" y="20">{name}</text>
+<text x="4" y="{PADDING}">{max:.1}</text>
+<text x="4" y="{bottom}">{min:.1}</text>
+<polyline points="{line}" fill="none" stroke="#e74c3c" stroke-width="1.5"/>
+</svg>
+"##,
+        name = summary.field.name(),
+        max = summary.max,
+        min = summary.min,
+        bottom = HEIGHT - PADDING,
+        line = line.trim_end(),
+    )
+}
+
+/// Summarize the `period` ending at `stop` and send it through `notifiers`.
+pub fn send(
+    client: SharedState,
+    history: Arc<History>,
+    notifiers: Arc<Vec<Box<dyn Notifier>>>,
+    config: SummaryConfig,
+    stop: DateTime<Utc>,
+) {
+    tokio::spawn(async move {
+        let start = stop - config.period.duration();
+        let range = TimeRange::Between {
+            start_ms: start.timestamp_millis() as u64,
+            stop_ms: stop.timestamp_millis() as u64,
+        };
+
+        let points = match client
+            .lock()
+            .await
+            .get_range(range, &RangeOptions::default())
+            .await
+        {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("Could not fetch data for summary: {e}");
+                return;
+            }
+        };
+
+        let alerts = match history.since(start.timestamp_millis()) {
+            Ok(v) => v
+                .iter()
+                .filter(|r| r.fired_at < stop.timestamp_millis())
+                .count(),
+            Err(e) => {
+                eprintln!("{e}");
+                return;
+            }
+        };
+
+        let title = match config.period {
+            Period::Daily => format!("Daily summary for {}", start.format("%Y-%m-%d")),
+            Period::Weekly => format!(
+                "Weekly summary for {} to {}",
+                start.format("%Y-%m-%d"),
+                stop.format("%Y-%m-%d")
+            ),
+        };
+
+        let mut summaries: Vec<_> = Field::ALL
+            .into_iter()
+            .filter_map(|f| summarize(f, &points))
+            .collect();
+
+        // The mean is over the aggregated points, as charted, but the
+        // extremes are those of the stored points, which the means smooth
+        // out.
+        let options = RangeOptions {
+            window_ms: Some(config.period.duration().num_milliseconds() as u64),
+            ..RangeOptions::default()
+        };
+        for summary in &mut summaries {
+            let bands = client
+                .lock()
+                .await
+                .get_metric_band(summary.field.name(), range, &options)
+                .await;
+
+            match bands {
+                Ok(bands) if !bands.is_empty() => {
+                    summary.min = bands.iter().map(|b| b.min).fold(f64::INFINITY, f64::min);
+                    summary.max = bands
+                        .iter()
+                        .map(|b| b.max)
+                        .fold(f64::NEG_INFINITY, f64::max);
+                }
+                Ok(_) => {}
+                Err(e) => eprintln!("Could not fetch extremes for summary: {e}"),
+            }
+        }
+
+        let mut body = String::new();
+        for s in &summaries {
+            let _ = writeln!(
+                body,
+                "{}: min {:.02}, max {:.02}, mean {:.02}",
+                s.field.name(),
+                s.min,
+                s.max,
+                s.mean
+            );
+        }
+        let _ = write!(body, "{alerts} alert(s) fired");
+
+        let attachments = if config.chart {
+            summaries
+                .iter()
+                .map(|s| Attachment {
+                    name: format!("{}.svg", s.field.name()),
+                    content_type: "image/svg+xml",
+                    data: chart(s, &points).into_bytes(),
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        println!("Sending {}", title.to_lowercase());
+        notify::dispatch(
+            &notifiers,
+            Notification {
+                title,
+                body,
+                severity: Severity::Info,
+                event: None,
+                attachments,
+            },
+        );
+    });
+}
@@ -0,0 +1,130 @@
+use std::sync::{Arc, RwLock};
+
+use chrono::DateTime;
+use serde::Deserialize;
+use serde_json::json;
+
+/// An InfluxDB task, as returned by the Tasks API.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Task {
+    id: String,
+    flux: String,
+    status: String,
+    /// RFC 3339 time of the last completed run.
+    latest_completed: Option<String>,
+    last_run_status: Option<String>,
+    last_run_error: Option<String>,
+}
+
+impl Task {
+    /// When the task last completed a run, in milliseconds.
+    pub fn last_run(&self) -> Option<i64> {
+        let time = self.latest_completed.as_deref()?;
+        DateTime::parse_from_rfc3339(time)
+            .ok()
+            .map(|t| t.timestamp_millis())
+    }
+
+    /// The error of the last run, if it failed.
+    pub fn last_error(&self) -> Option<String> {
+        match self.last_run_status.as_deref() {
+            Some("failed") => Some(
+                self.last_run_error
+                    .clone()
+                    .unwrap_or_else(|| "failed".to_string()),
+            ),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Tasks {
+    tasks: Vec<Task>,
+}
+
+/// Manages tasks through the InfluxDB Tasks API, which the `influxdb2` crate
+/// doesn't cover.
+#[derive(Debug, Clone)]
+pub struct TaskApi {
+    client: reqwest::Client,
+    host: String,
+    org: String,
+    token: Arc<RwLock<String>>,
+}
+
+impl TaskApi {
+    pub fn new(client: reqwest::Client, host: String, org: String, token: String) -> Self {
+        Self {
+            client,
+            host: host.trim_end_matches('/').to_string(),
+            org,
+            token: Arc::new(RwLock::new(token)),
+        }
+    }
+
+    /// Use `token` from now on, also in clones.
+    pub fn set_token(&self, token: String) {
+        *self.token.write().unwrap() = token;
+    }
+
+    fn authorization(&self) -> String {
+        format!("Token {}", self.token.read().unwrap())
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/api/v2/tasks{path}", self.host)
+    }
+
+    /// The task called `name`, if it exists.
+    pub async fn find(&self, name: &str) -> Result<Option<Task>, String> {
+        let tasks: Tasks = self
+            .client
+            .get(self.url(""))
+            .header("Authorization", self.authorization())
+            .query(&[("name", name), ("org", &self.org)])
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| format!("Could not list tasks: {e}"))?
+            .json()
+            .await
+            .map_err(|e| format!("Invalid task list: {e}"))?;
+
+        Ok(tasks.tasks.into_iter().next())
+    }
+
+    /// Create the task called `name` with the script `flux`, or update it if
+    /// it exists but differs or is inactive.
+    pub async fn ensure(&self, name: &str, flux: &str) -> Result<(), String> {
+        let request = match self.find(name).await? {
+            None => {
+                println!("Creating InfluxDB task {name}");
+                self.client.post(self.url("")).json(&json!({
+                    "org": self.org,
+                    "flux": flux,
+                    "status": "active",
+                }))
+            }
+            Some(task) if task.flux != flux || task.status != "active" => {
+                println!("Updating InfluxDB task {name}");
+                self.client
+                    .patch(self.url(&format!("/{}", task.id)))
+                    .json(&json!({
+                        "flux": flux,
+                        "status": "active",
+                    }))
+            }
+            Some(_) => return Ok(()),
+        };
+
+        request
+            .header("Authorization", self.authorization())
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map(|_| ())
+            .map_err(|e| format!("Could not set up task {name}: {e}"))
+    }
+}
@@ -0,0 +1,129 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use influxdb_temp_client::Field;
+use serde::Deserialize;
+
+use crate::{
+    backend::{BackendOpts, RollupMode},
+    calibration::{Calibration, Correction},
+    secret, API_PREFIX,
+};
+
+/// A house with its own data and password, served under `prefix` by the same
+/// process.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Tenant {
+    pub name: String,
+    /// E.g. `/cabin`, which serves the API at `/cabin/api`.
+    pub prefix: String,
+    pub bucket: String,
+    influxdb_token: Option<String>,
+    influxdb_token_file: Option<PathBuf>,
+    http_password: Option<String>,
+    http_password_file: Option<PathBuf>,
+    /// Password for ingestion and the `/admin` endpoints.
+    admin_password: Option<String>,
+    admin_password_file: Option<PathBuf>,
+    /// SQLite database to cache aggregated ranges in. Must not be shared
+    /// with other tenants.
+    cache_db: Option<PathBuf>,
+    /// File to spill buffered points to.
+    pub ingest_spill_file: Option<PathBuf>,
+    /// Corrections of the sensor, e.g. `temperature = { offset = -0.8 }`.
+    #[serde(default)]
+    calibration: BTreeMap<Field, Correction>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TenantsFile {
+    tenant: Vec<Tenant>,
+}
+
+impl Tenant {
+    pub fn http_password(&self) -> Option<String> {
+        secret(
+            self.http_password.clone(),
+            self.http_password_file.as_deref(),
+        )
+    }
+
+    pub fn calibration(&self) -> Calibration {
+        Calibration::from(self.calibration.clone())
+    }
+
+    pub fn admin_password(&self) -> Option<String> {
+        secret(
+            self.admin_password.clone(),
+            self.admin_password_file.as_deref(),
+        )
+    }
+
+    /// `base` with the bucket, token and cache of this tenant. Without a
+    /// token of its own, the tenant uses the token of `base`, including one
+    /// from a file or Vault. Rollups and tasks are only set up for the
+    /// default tenant.
+    pub fn backend(&self, base: &BackendOpts) -> BackendOpts {
+        let mut opts = base.clone();
+        opts.bucket = self.bucket.clone();
+        if self.influxdb_token.is_some() || self.influxdb_token_file.is_some() {
+            opts.vault_token = None;
+            opts.token_file = self.influxdb_token_file.clone();
+            opts.api_token = self.influxdb_token.clone();
+        }
+        opts.cache_db = self.cache_db.clone();
+        opts.rollup_bucket = None;
+        opts.rollup_mode = RollupMode::Server;
+        opts
+    }
+}
+
+fn validate(tenants: &[Tenant]) -> Result<(), String> {
+    for (i, tenant) in tenants.iter().enumerate() {
+        let prefix = &tenant.prefix;
+
+        if !prefix.starts_with('/') || prefix.len() < 2 || prefix.ends_with('/') {
+            return Err(format!(
+                "Prefix {prefix:?} of tenant {} must start with / and not end with it",
+                tenant.name
+            ));
+        }
+
+        if prefix == API_PREFIX || prefix.starts_with(&format!("{API_PREFIX}/")) {
+            return Err(format!(
+                "Prefix {prefix:?} of tenant {} overlaps with {API_PREFIX}",
+                tenant.name
+            ));
+        }
+
+        if tenants[..i].iter().any(|t| &t.prefix == prefix) {
+            return Err(format!("Prefix {prefix:?} is used by multiple tenants"));
+        }
+
+        tenant
+            .calibration()
+            .validate()
+            .map_err(|e| format!("Tenant {}: {e}", tenant.name))?;
+
+        if tenant.http_password.is_none() && tenant.http_password_file.is_none() {
+            return Err(format!("Tenant {} has no HTTP password", tenant.name));
+        }
+    }
+
+    Ok(())
+}
+
+/// Read the `[[tenant]]` tables of `path`.
+pub fn load(path: &Path) -> Result<Vec<Tenant>, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+
+    let file: TenantsFile =
+        toml::from_str(&text).map_err(|e| format!("Could not parse {}: {e}", path.display()))?;
+
+    validate(&file.tenant)?;
+    Ok(file.tenant)
+}
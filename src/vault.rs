@@ -0,0 +1,205 @@
+use std::{collections::HashMap, time::Duration};
+
+use clap::Args;
+use serde::Deserialize;
+use tokio::sync::watch;
+
+/// Key of the InfluxDB token in the secret.
+const INFLUXDB_TOKEN: &str = "influxdb_token";
+/// Key of the HTTP password in the secret.
+const HTTP_PASSWORD: &str = "http_password";
+/// Key of the InfluxDB 1.x password in the secret.
+#[cfg(feature = "influxql")]
+const INFLUXQL_PASSWORD: &str = "influxql_password";
+
+/// How often to read the secret again if the Vault token has no lease.
+const DEFAULT_REFRESH: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Args)]
+pub struct VaultOpts {
+    /// Vault server to read the InfluxDB token and HTTP password from, as
+    /// `influxdb_token` and `http_password` of a kv v2 secret, and the
+    /// InfluxQL password as `influxql_password`. Values that are missing in
+    /// the secret are taken from the other options.
+    #[clap(long, env = "VAULT_ADDR", requires = "vault_token")]
+    pub vault_addr: Option<String>,
+    #[clap(long, env = "VAULT_TOKEN")]
+    pub vault_token: Option<String>,
+    /// Mount of the kv v2 secrets engine.
+    #[clap(long, env = "VAULT_MOUNT", default_value = "secret")]
+    pub vault_mount: String,
+    /// Path of the secret within the mount.
+    #[clap(
+        long,
+        env = "VAULT_SECRET_PATH",
+        default_value = "influxdb-temp-server"
+    )]
+    pub vault_secret_path: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct KvData {
+    data: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct KvResponse {
+    data: KvData,
+}
+
+#[derive(Debug, Deserialize)]
+struct Auth {
+    lease_duration: u64,
+    renewable: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct RenewResponse {
+    auth: Auth,
+}
+
+struct Vault {
+    client: reqwest::Client,
+    addr: String,
+    token: String,
+    mount: String,
+    path: String,
+}
+
+impl Vault {
+    fn url(&self, path: &str) -> String {
+        format!("{}/v1/{path}", self.addr.trim_end_matches('/'))
+    }
+
+    async fn read(&self) -> Result<HashMap<String, String>, String> {
+        let response: KvResponse = self
+            .client
+            .get(self.url(&format!("{}/data/{}", self.mount, self.path)))
+            .header("X-Vault-Token", &self.token)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| format!("Could not read secret from Vault: {e}"))?
+            .json()
+            .await
+            .map_err(|e| format!("Invalid secret from Vault: {e}"))?;
+
+        Ok(response.data.data)
+    }
+
+    /// Renew the lease of the Vault token. Returns how long it lasts, if it
+    /// can be renewed at all.
+    async fn renew(&self) -> Result<Option<Duration>, String> {
+        let response: RenewResponse = self
+            .client
+            .post(self.url("auth/token/renew-self"))
+            .header("X-Vault-Token", &self.token)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| format!("Could not renew the Vault token: {e}"))?
+            .json()
+            .await
+            .map_err(|e| format!("Invalid response from Vault: {e}"))?;
+
+        let auth = response.auth;
+        Ok((auth.renewable && auth.lease_duration > 0)
+            .then(|| Duration::from_secs(auth.lease_duration)))
+    }
+}
+
+/// Secrets read from Vault. They are updated whenever the secret changes.
+pub struct Secrets {
+    pub influxdb_token: Option<watch::Receiver<String>>,
+    pub http_password: Option<watch::Receiver<String>>,
+    #[cfg(feature = "influxql")]
+    pub influxql_password: Option<watch::Receiver<String>>,
+}
+
+/// Read the secrets from Vault, if it is configured, and keep them up to date
+/// in the background: the Vault token is renewed halfway through its lease,
+/// and the secret is read again every time.
+pub async fn load(opts: &VaultOpts) -> Option<Secrets> {
+    let vault = Vault {
+        client: reqwest::Client::new(),
+        addr: opts.vault_addr.clone()?,
+        token: opts.vault_token.clone()?,
+        mount: opts.vault_mount.clone(),
+        path: opts.vault_secret_path.clone(),
+    };
+
+    let secret = match vault.read().await {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+
+    let channel = |key: &str| {
+        let value = secret.get(key)?.clone();
+        println!("Read {key} from Vault");
+        Some(watch::channel(value))
+    };
+
+    let (influxdb_token_tx, influxdb_token) = channel(INFLUXDB_TOKEN).unzip();
+    let (http_password_tx, http_password) = channel(HTTP_PASSWORD).unzip();
+    #[cfg(feature = "influxql")]
+    let (influxql_password_tx, influxql_password) = channel(INFLUXQL_PASSWORD).unzip();
+
+    #[allow(unused_mut)]
+    let mut senders = vec![
+        (INFLUXDB_TOKEN, influxdb_token_tx),
+        (HTTP_PASSWORD, http_password_tx),
+    ];
+    #[cfg(feature = "influxql")]
+    senders.push((INFLUXQL_PASSWORD, influxql_password_tx));
+
+    tokio::spawn(async move {
+        let mut refresh = match vault.renew().await {
+            Ok(lease) => lease.map_or(DEFAULT_REFRESH, |l| l / 2),
+            Err(e) => {
+                eprintln!("{e}");
+                DEFAULT_REFRESH
+            }
+        };
+
+        loop {
+            tokio::time::sleep(refresh).await;
+
+            match vault.renew().await {
+                Ok(lease) => refresh = lease.map_or(DEFAULT_REFRESH, |l| l / 2),
+                Err(e) => eprintln!("{e}"),
+            }
+
+            let secret = match vault.read().await {
+                Ok(v) => v,
+                Err(e) => {
+                    eprintln!("{e}");
+                    continue;
+                }
+            };
+
+            for (key, tx) in &senders {
+                if let (Some(tx), Some(value)) = (tx, secret.get(*key)) {
+                    let changed = tx.send_if_modified(|current| {
+                        let changed = current != value;
+                        *current = value.clone();
+                        changed
+                    });
+
+                    if changed {
+                        println!("Updated {key} from Vault");
+                    }
+                }
+            }
+        }
+    });
+
+    Some(Secrets {
+        influxdb_token,
+        http_password,
+        #[cfg(feature = "influxql")]
+        influxql_password,
+    })
+}
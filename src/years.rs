@@ -0,0 +1,141 @@
+use axum::{
+    extract::{Path, Query},
+    response::IntoResponse,
+    Extension, Json,
+};
+use chrono::{DateTime, Datelike, Months, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+use influxdb_temp_client::{DataPoint, RangeOptions};
+
+use crate::{
+    between, calendar, fetch,
+    problem::{ApiError, ErrorCode},
+    scope::ReadAccess,
+    QueryLimits, SharedState,
+};
+
+/// Most previous years that can be compared at once.
+const MAX_YEARS: u32 = 10;
+
+#[derive(Debug, Deserialize)]
+pub struct YearsQuery {
+    /// How many previous years to compare with. Defaults to 1.
+    years: Option<u32>,
+    /// Comma-separated fields. Defaults to all.
+    fields: Option<String>,
+    /// About how many points to return per year.
+    points: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct Year {
+    year: i32,
+    /// The window in this year, in milliseconds.
+    start: i64,
+    stop: i64,
+    stale: bool,
+    /// With their times moved into the requested window.
+    points: Vec<DataPoint>,
+}
+
+#[derive(Debug, Serialize)]
+struct Comparison {
+    window_ms: u64,
+    /// The requested window first.
+    years: Vec<Year>,
+}
+
+/// The local time `years` years before `ms`. The 29th of February becomes
+/// the 28th.
+fn years_before(ms: u64, years: u32) -> Option<DateTime<Tz>> {
+    Utc.timestamp_millis_opt(ms as i64)
+        .single()?
+        .with_timezone(&calendar::timezone())
+        .checked_sub_months(Months::new(12 * years))
+}
+
+/// The window from `start` to `stop` in the previous years, with the points
+/// of every year moved onto the requested window so that the series line up.
+/// Windows that divide a day line up exactly.
+pub async fn compare(
+    Path((start, stop)): Path<(u64, u64)>,
+    Query(query): Query<YearsQuery>,
+    Extension(client): Extension<SharedState>,
+    Extension(limits): Extension<QueryLimits>,
+    _: ReadAccess,
+) -> impl IntoResponse {
+    let years = query.years.unwrap_or(1);
+    if years == 0 || years > MAX_YEARS {
+        return Err(ApiError::new(
+            ErrorCode::BadRequest,
+            format!("Years must be between 1 and {MAX_YEARS}."),
+        ));
+    }
+
+    let fields = match &query.fields {
+        Some(fields) => fields
+            .split(',')
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map_err(|e| ApiError::new(ErrorCode::UnknownField, e))?,
+        None => Vec::new(),
+    };
+
+    // Every year uses the window of the requested range, so that they have
+    // the same points.
+    let requested = between(start, stop)?;
+    let window_ms = RangeOptions {
+        points: query.points,
+        ..Default::default()
+    }
+    .window(&requested);
+
+    let options = RangeOptions {
+        fields,
+        window_ms: Some(window_ms),
+        ..Default::default()
+    };
+
+    let mut compared = Vec::new();
+    for ago in 0..=years {
+        let bounds = years_before(start, ago).zip(years_before(stop, ago));
+        let Some((from, to)) = bounds.filter(|(from, _)| from.timestamp_millis() >= 0) else {
+            return Err(ApiError::new(
+                ErrorCode::BadTimeRange,
+                format!("The window {ago} year(s) earlier is out of range."),
+            ));
+        };
+
+        let (from_ms, to_ms) = (from.timestamp_millis(), to.timestamp_millis());
+        let fetched = fetch(
+            &client,
+            &limits,
+            between(from_ms as u64, to_ms as u64)?,
+            &options,
+        )
+        .await?;
+
+        let shift = start as i64 - from_ms;
+        compared.push(Year {
+            year: from.year(),
+            start: from_ms,
+            stop: to_ms,
+            stale: fetched.stale,
+            points: fetched
+                .points
+                .into_iter()
+                .map(|p| DataPoint {
+                    time: p.time + shift,
+                    ..p
+                })
+                .collect(),
+        });
+    }
+
+    Ok(Json(Comparison {
+        window_ms,
+        years: compared,
+    }))
+}
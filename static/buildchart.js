@@ -7,6 +7,7 @@ const reset = document.querySelector("#reset")
 const start_date = document.querySelector("#start-date")
 const end_date = document.querySelector("#end-date")
 const password = document.querySelector("#password")
+const stale = document.querySelector("#stale")
 
 const chart = new Chart(ctx, {
     type: 'line',
@@ -90,7 +91,7 @@ function report_error(message) {
 }
 
 async function fetch_range_data(range) {
-    const data = await fetch_data("/data/range/" + range)
+    const data = await fetch_data("/api/data/range/" + range)
     const temp = data.map((d) => { return { time: d.time, value: d.temperature } })
     const humid = data.map((d) => { return { time: d.time, value: d.humidity } })
     const co2 = data.filter((d) => d.co2).map((d) => { return { time: d.time, value: d.co2 } });
@@ -103,7 +104,7 @@ async function fetch_range_data(range) {
 }
 
 async function fetch_range_data_between(start_ms, stop_ms) {
-    const data = await fetch_data("/data/from/" + start_ms + "/to/" + stop_ms)
+    const data = await fetch_data("/api/data/from/" + start_ms + "/to/" + stop_ms)
     const temp = data.map((d) => { return { time: d.time, value: d.temperature } })
     const humid = data.map((d) => { return { time: d.time, value: d.humidity } })
     const co2 = data.filter((d) => d.co2).map((d) => { return { time: d.time, value: d.co2 } });
@@ -124,12 +125,19 @@ async function fetch_data(url) {
         const data = await fetch(url, { headers: { authorization: "Bearer " + password.value } })
 
         if (data.status != 200) {
-            report_error(await data.text())
+            const text = await data.text()
+            try {
+                report_error(JSON.parse(text).detail ?? text)
+            } catch {
+                report_error(text)
+            }
             return []
         }
 
         body = await data.json();
 
+        stale.hidden = !data.headers.has("warning")
+
         const end = Date.now();
 
         load_time.textContent = end - start;